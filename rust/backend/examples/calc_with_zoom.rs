@@ -1,150 +1,129 @@
-use std::{fs::File, io::Write, time::Instant};
-use std::fs::create_dir_all;
+use std::env;
+use std::path::PathBuf;
+use std::time::Instant;
 
-use chrono::Utc;
 use log::{info, LevelFilter};
 use pretty_env_logger::env_logger::{Builder, Target};
-use serde::Serialize;
-use serde_derive::Deserialize;
-use serde_json::json;
-
-use common::{complex::ComplexNumber, fractal_calculation::calc_multi_threaded};
-use common::models::FractalRequest;
 
+use common::animated_image::AnimationFormat;
+use common::animation::{
+    render_animation, Animation, AnimationMode, AnimationOptions, Easing, Keyframe,
+};
+use common::fractal_templates;
+use common::render_engine::{engine_by_name, engine_names};
+use common::validation::RequestLimits;
+
+const FPS: f64 = 30.0;
+
+// usage: cargo run --release --example calc_with_zoom -- [--exp-map] [--video=gif,apng,y4m]
+//        [template] [engine] [shard]
+// zooms from the zoom of the template to max_zoom_factor, zoom_factor per frame. the frames and
+// manifest.json end up in images/<name>/frames.
+// an interrupted run continues with the missing frames, shard 0/3 renders every third frame.
+// --exp-map calculates one exponential map and resamples the frames from it.
+// --video writes animation.gif, .png or .y4m next to the frames once all of them are finished,
+// ffmpeg -i animation.y4m zoom.mp4 makes an mp4 of it
 fn main() {
     let mut builder = Builder::new();
     builder.target(Target::Stdout);
     builder.filter_level(LevelFilter::Info);
     builder.init();
-    //  info!("builder={:?}", builder);
-
-    // flower(false);
-//     tendrils(false);
-    seahorse_valley(false);
-    // sun(false);
-    //     tree(false);
-    //     starfish(false);
-    //     julia_island(false);
-}
-
-fn flower(debug: bool) {
-    let (req, zoom_factor, max_zoom_factor) = common::fractal_templates::flower(debug);
-    render(req, zoom_factor, max_zoom_factor);
-}
-
-fn tendrils(debug: bool) {
-    let (req, zoom_factor, max_zoom_factor) = common::fractal_templates::tendrils(debug);
-    render(req, zoom_factor, max_zoom_factor);
-}
-
-fn julia_island(debug: bool) {
-    let (req, zoom_factor, max_zoom_factor) = common::fractal_templates::julia_island(debug);
-    render(req, zoom_factor, max_zoom_factor);
-}
-
-fn seahorse_valley(debug: bool) {
-    let (req, zoom_factor, max_zoom_factor) = common::fractal_templates::seahorse_valley(debug);
-    render(req, zoom_factor, max_zoom_factor);
-}
-
-fn starfish(debug: bool) {
-    let (req, zoom_factor, max_zoom_factor) = common::fractal_templates::starfish(debug);
-    render(req, zoom_factor, max_zoom_factor);
-}
-
-fn sun(debug: bool) {
-    let (req, zoom_factor, max_zoom_factor) = common::fractal_templates::sun(debug);
-    render(req, zoom_factor, max_zoom_factor);
-}
-
-fn tree(debug: bool) {
-    let (req, zoom_factor, max_zoom_factor) = common::fractal_templates::tree(debug);
-    render(req, zoom_factor, max_zoom_factor);
-}
-
-#[derive(Serialize, Deserialize)]
-struct FractalData {
-    req: FractalRequest,
-    zoom_factor: f64,
-    max_zoom_factor: f64,
-    tl: ComplexNumber,
-    br: ComplexNumber,
-}
-
-fn render(mut req: FractalRequest, zoom_factor: f64, max_zoom_factor: f64) {
-    let width: u32 = req.width;
-    let height: u32 = req.height;
-
-    // -4.0 ... 1.3
-    let complex_width = req.complex_width;
 
+    let mut args: Vec<String> = env::args().collect();
+    let mode = match args.iter().position(|a| a == "--exp-map") {
+        Some(idx) => {
+            args.remove(idx);
+            AnimationMode::ExpMap
+        }
+        None => AnimationMode::Frames,
+    };
+    let videos = match args.iter().position(|a| a.starts_with("--video=")) {
+        Some(idx) => args
+            .remove(idx)
+            .trim_start_matches("--video=")
+            .split(',')
+            .map(|format| match format {
+                "gif" => AnimationFormat::Gif,
+                "apng" => AnimationFormat::Apng,
+                "y4m" => AnimationFormat::Y4m,
+                _ => panic!("unknown video format {}", format),
+            })
+            .collect(),
+        None => vec![],
+    };
+    let template = args.get(1).map(|s| s.as_str()).unwrap_or("seahorse_valley");
+    let engine = args.get(2).map(|s| s.as_str()).unwrap_or("multithreaded");
+    let options = AnimationOptions {
+        shard: args
+            .get(3)
+            .map(|s| s.parse().expect("shard should be <index>/<count>")),
+        // a local render, only a single frame has the limits of the server
+        limits: RequestLimits {
+            max_frames: u32::MAX,
+            max_animation_pixels: u64::MAX,
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+
+    let (req, zoom_factor, max_zoom_factor) = match template {
+        "flower" => fractal_templates::flower(false),
+        "tendrils" => fractal_templates::tendrils(false),
+        "julia_island" => fractal_templates::julia_island(false),
+        "seahorse_valley" => fractal_templates::seahorse_valley(false),
+        "starfish" => fractal_templates::starfish(false),
+        "sun" => fractal_templates::sun(false),
+        "tree" => fractal_templates::tree(false),
+        _ => panic!("unknown template {}", template),
+    };
+
+    let engine = engine_by_name(engine).unwrap_or_else(|| {
+        panic!(
+            "unknown engine {}. available engines {:?}",
+            engine,
+            engine_names()
+        )
+    });
+
+    let frames = ((max_zoom_factor / req.zoom).ln() / zoom_factor.ln()).ceil();
+    let keyframe = |time: f64, zoom: f64| Keyframe {
+        time,
+        center: req.center.clone(),
+        zoom,
+        rotation: 0.0,
+        max_iterations: req.max_iterations,
+        palette_offset: req.palette_offset,
+        easing: Easing::Linear,
+    };
+    let animation = Animation {
+        keyframes: vec![
+            keyframe(0.0, req.zoom),
+            keyframe(frames / FPS, req.zoom * zoom_factor.powf(frames)),
+        ],
+        request: req.clone(),
+        fps: FPS,
+        format: Default::default(),
+        mode,
+        videos,
+    };
+
+    let dir = PathBuf::from(format!(
+        "{}/../../images/{}/frames",
+        env!("CARGO_MANIFEST_DIR"),
+        req.name
+    ));
     let start = Instant::now();
-
-    while req.zoom < max_zoom_factor {
-        let c_w = complex_width / req.zoom;
-        let (_, duration, cores) = calc_multi_threaded(
-            &req.center,
-            c_w,
-            req.zoom,
-            width,
-            height,
-            req.max_iterations,
-            req.colors,
-            req.name.to_string(),
-        );
-
-        info!(
-            "name:  {} duration {duration},   cores {cores},     zoom {}",
-            req.name, req.zoom
-        );
-
-        let ratio = width as f64 / height as f64;
-        let complex_height = complex_width / ratio;
-
-        let re_min = req.center.a - req.complex_width / 2.0;
-        let re_max = req.center.a + req.complex_width / 2.0;
-
-        let img_min = req.center.b - complex_height / 2.0;
-        let img_max = req.center.b + complex_height / 2.0;
-
-        let tl = ComplexNumber {
-            a: re_min,
-            b: img_max,
-        };
-        let br = ComplexNumber {
-            a: re_max,
-            b: img_min,
-        };
-
-        let fractal_data = FractalData {
-            req: req.clone(),
-            zoom_factor,
-            max_zoom_factor,
-            tl,
-            br,
-        };
-
-        let json = serde_json::to_string_pretty(&json!(&fractal_data)).unwrap();
-
-        let path = get_filename(&req.name);
-
-        let mut f = File::create(path).expect("Unable to create file");
-        f.write_all(json.as_bytes()).expect("Unable to write data");
-        req.zoom *= zoom_factor;
-    }
+    let progress = render_animation(&animation, engine.as_ref(), &dir, &options)
+        .expect("rendering should work");
 
     info!("########################################################################################################");
-    info!("rendering took {} seconds", start.elapsed().as_secs_f64());
+    info!(
+        "{} of {} frames finished, {} rendered with engine {} in {} seconds",
+        progress.finished,
+        progress.frames,
+        progress.calculated,
+        engine.name(),
+        start.elapsed().as_secs_f64()
+    );
     info!("########################################################################################################");
 }
-
-fn get_filename(name: &str) -> String {
-    let now = Utc::now();
-
-    let path = env!("CARGO_MANIFEST_DIR");
-    // info!("CARGO_MANIFEST_DIR   {path}");
-    let path = format!("{}/../../images/{}/json", path, name);
-    create_dir_all(&path).expect("create dir should work");
-
-    format!("{}/{}_{}.json", path, now.timestamp_millis(), name)
-}
@@ -1,5 +1,8 @@
 use common::palette::read_palette;
 
 fn main() {
-    let _p = read_palette();
+    match read_palette() {
+        Ok(p) => println!("read {} palettes", p.len()),
+        Err(e) => println!("error reading palettes {}", e),
+    }
 }
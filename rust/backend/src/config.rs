@@ -0,0 +1,118 @@
+use std::env;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use log::{info, warn};
+
+use common::error::{FractalError, FractalResult};
+use common::output::{
+    DirectoryOutput, MemoryOutput, NoOutput, OutputSink, DEFAULT_FILE_NAME_TEMPLATE,
+};
+use common::tile_pyramid::TileCache;
+use common::validation::RequestLimits;
+
+use crate::jobs::Jobs;
+
+// limits can be changed with environment variables, e.g. FRACTAL_MAX_PIXELS=1000000
+pub fn request_limits() -> RequestLimits {
+    let default = RequestLimits::default();
+    let limits = RequestLimits {
+        max_width: env_or("FRACTAL_MAX_WIDTH", default.max_width),
+        max_height: env_or("FRACTAL_MAX_HEIGHT", default.max_height),
+        max_pixels: env_or("FRACTAL_MAX_PIXELS", default.max_pixels),
+        max_iterations: env_or("FRACTAL_MAX_ITERATIONS", default.max_iterations),
+        max_tiles: env_or("FRACTAL_MAX_TILES", default.max_tiles),
+        max_palette_colors: env_or("FRACTAL_MAX_PALETTE_COLORS", default.max_palette_colors),
+        max_frames: env_or("FRACTAL_MAX_FRAMES", default.max_frames),
+        max_animation_pixels: env_or("FRACTAL_MAX_ANIMATION_PIXELS", default.max_animation_pixels),
+    };
+    info!("request limits {:?}", &limits);
+    limits
+}
+
+// FRACTAL_OUTPUT=none, directory (default) or memory, FRACTAL_OUTPUT_DIR and
+// FRACTAL_OUTPUT_TEMPLATE. only requests with an output list are saved. memory keeps every file
+// until the server stops, it is meant for tests
+pub fn output_sink() -> FractalResult<(Box<dyn OutputSink>, String)> {
+    let template = env::var("FRACTAL_OUTPUT_TEMPLATE")
+        .unwrap_or_else(|_| DEFAULT_FILE_NAME_TEMPLATE.to_string());
+    let output = env::var("FRACTAL_OUTPUT").ok();
+    let sink = sink_for(output.as_deref(), env::var("FRACTAL_OUTPUT_DIR").ok())?;
+    info!("output sink: {}  template {}", sink.1, &template);
+    Ok((sink.0, template))
+}
+
+// the sink and a description for the log
+fn sink_for(
+    output: Option<&str>,
+    dir: Option<String>,
+) -> FractalResult<(Box<dyn OutputSink>, String)> {
+    match output {
+        Some("none") => Ok((Box::new(NoOutput), "none".to_string())),
+        Some("memory") => Ok((Box::<MemoryOutput>::default(), "memory".to_string())),
+        None | Some("directory") => {
+            let sink = match dir {
+                Some(dir) => DirectoryOutput::new(PathBuf::from(dir)),
+                None => DirectoryOutput::default(),
+            };
+            let description = format!("directory {}", sink.dir.display());
+            Ok((Box::new(sink), description))
+        }
+        Some(output) => Err(FractalError::Config(format!(
+            "FRACTAL_OUTPUT = '{}' is not valid, expected none, directory or memory",
+            output
+        ))),
+    }
+}
+
+// FRACTAL_TILE_CACHE is the directory of the rendered map tiles, the temp directory by default
+pub fn tile_cache() -> TileCache {
+    let dir = match env::var("FRACTAL_TILE_CACHE") {
+        Ok(dir) => PathBuf::from(dir),
+        Err(_) => env::temp_dir().join("fractal-tiles"),
+    };
+    info!("tile cache {}", dir.display());
+    TileCache::new(dir)
+}
+
+// FRACTAL_JOB_WORKERS renders run at the same time, FRACTAL_JOB_QUEUE more may wait for them
+pub fn jobs() -> Jobs {
+    let workers = env_or("FRACTAL_JOB_WORKERS", 2usize).max(1);
+    let queue_size = env_or("FRACTAL_JOB_QUEUE", 16usize);
+    info!("job workers {}  queue {}", workers, queue_size);
+    Jobs::new(workers, queue_size)
+}
+
+fn env_or<T: FromStr>(name: &str, default: T) -> T {
+    match env::var(name) {
+        Ok(value) => value.parse().unwrap_or_else(|_| {
+            warn!("environment variable {} = '{}' is not valid, using the default", name, value);
+            default
+        }),
+        Err(_) => default,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use common::error::FractalError;
+
+    use crate::config::sink_for;
+
+    #[test]
+    fn test_output_sink() {
+        for (output, description) in [
+            (None, "directory /tmp/out"),
+            (Some("directory"), "directory /tmp/out"),
+            (Some("none"), "none"),
+            (Some("memory"), "memory"),
+        ] {
+            let (_, found) = sink_for(output, Some("/tmp/out".to_string())).unwrap();
+            assert_eq!(found, description);
+        }
+        assert!(matches!(
+            sink_for(Some("s3"), None),
+            Err(FractalError::Config(_))
+        ));
+    }
+}
@@ -0,0 +1,121 @@
+use std::time::Instant;
+
+use log::info;
+use warp::http::header::CONTENT_TYPE;
+use warp::reply::with_header;
+use warp::{Filter, Reply};
+
+use common::animated_image::AnimationWriter;
+use common::error::FractalError;
+use common::iteration_buffer::IterationBuffer;
+use common::models::{CycleRequest, FractalRequest, IterationResponse, PaletteSpec};
+use common::palette::resolve_palette;
+use common::render_engine::RenderParams;
+use common::validation::RequestLimits;
+
+use crate::error::reject;
+use crate::palettes::{with_uploaded_palettes, UploadedPalettes};
+use crate::server::with_limits;
+use crate::utils;
+
+// palette cycling. api/cycle renders the request again, the limits count that as one more frame.
+// the image is calculated once, every frame only recolors the iteration buffer
+pub fn routes(
+    limits: RequestLimits,
+    palettes: UploadedPalettes,
+) -> impl Filter<Extract=(impl Reply, ), Error=warp::Rejection> + Clone {
+    let cycle = warp::path!("api" / "cycle")
+        .and(warp::post())
+        .and(warp::body::json())
+        .and(with_limits(limits.clone()))
+        .and(with_uploaded_palettes(palettes.clone()))
+        .and_then(
+            |req: CycleRequest, limits: RequestLimits, palettes: UploadedPalettes| {
+                info!(
+                    "POST api/cycle  frames {}  req {:?}",
+                    req.frames, &req.request
+                );
+                let req = CycleRequest {
+                    request: palettes.resolve(req.request.clone()),
+                    ..req
+                };
+                handle_cycle(req, limits)
+            },
+        );
+
+    let iterations = warp::path!("api" / "iterations")
+        .and(warp::post())
+        .and(warp::body::json())
+        .and(with_limits(limits))
+        .and(with_uploaded_palettes(palettes))
+        .and_then(
+            |req: FractalRequest, limits: RequestLimits, palettes: UploadedPalettes| {
+                info!("POST api/iterations  req {:?}", &req);
+                handle_iterations(palettes.resolve(req), limits)
+            },
+        );
+
+    cycle.or(iterations)
+}
+
+async fn handle_cycle(req: CycleRequest, limits: RequestLimits) -> utils::Result<impl Reply> {
+    req.validate(&limits)
+        .map_err(|e| reject(FractalError::Validation(e)))?;
+
+    let params = RenderParams::new(
+        &req.request,
+        resolve_palette(&req.request.palette).map_err(reject)?,
+    );
+    let format = req.format;
+    let body = utils::blocking(move || {
+        let start = Instant::now();
+        let buffer = IterationBuffer::calc(&params);
+        let mut body = vec![];
+        let mut writer = AnimationWriter::new(
+            format,
+            &mut body,
+            params.width,
+            params.height,
+            req.frames,
+            req.delay_ms,
+        )?;
+        for pixels in buffer.cycle(&params.palette, params.palette_offset, req.frames, req.step) {
+            writer.write_frame(&pixels)?;
+        }
+        writer.finish()?;
+        info!(
+            "palette cycling with {} frames took {} ms, {} bytes",
+            req.frames,
+            start.elapsed().as_millis(),
+            body.len()
+        );
+        Ok(body)
+    })
+    .await?;
+
+    Ok(with_header(body, CONTENT_TYPE, format.content_type()))
+}
+
+async fn handle_iterations(
+    req: FractalRequest,
+    limits: RequestLimits,
+) -> utils::Result<impl Reply> {
+    req.validate(&limits)
+        .map_err(|e| reject(FractalError::Validation(e)))?;
+
+    let params = RenderParams::new(&req, resolve_palette(&req.palette).map_err(reject)?);
+    let body = utils::blocking(move || {
+        let start = Instant::now();
+        let iterations = IterationBuffer::calc(&params);
+        info!("iteration buffer took {} ms", start.elapsed().as_millis());
+        IterationResponse {
+            iterations,
+            palette: PaletteSpec::from(params.palette),
+            palette_offset: params.palette_offset,
+        }
+        .encode()
+    })
+    .await?;
+
+    Ok(with_header(body, CONTENT_TYPE, "application/octet-stream"))
+}
@@ -0,0 +1,82 @@
+use std::convert::Infallible;
+
+use log::error;
+use warp::body::BodyDeserializeError;
+use warp::http::StatusCode;
+use warp::reject::{MethodNotAllowed, PayloadTooLarge, Reject};
+use warp::{Rejection, Reply};
+
+use common::error::FractalError;
+use common::models::ErrorResponse;
+use common::validation::FieldError;
+
+#[derive(Debug)]
+pub struct ApiError(pub FractalError);
+
+impl Reject for ApiError {}
+
+// errors of the server itself, not of a render. e.g. a full job queue or an Accept header
+// without a supported format
+#[derive(Debug)]
+pub struct StatusError(pub StatusCode, pub String);
+
+impl Reject for StatusError {}
+
+pub fn reject_status(status: StatusCode, message: String) -> Rejection {
+    warp::reject::custom(StatusError(status, message))
+}
+
+pub fn reject(e: FractalError) -> Rejection {
+    warp::reject::custom(ApiError(e))
+}
+
+pub fn status_code(e: &FractalError) -> StatusCode {
+    match e {
+        FractalError::UnknownEngine(_) | FractalError::PaletteNotFound(_) => StatusCode::NOT_FOUND,
+        FractalError::Validation(_) => StatusCode::UNPROCESSABLE_ENTITY,
+        e if e.is_client_error() => StatusCode::BAD_REQUEST,
+        _ => StatusCode::INTERNAL_SERVER_ERROR,
+    }
+}
+
+pub fn error_response(status: StatusCode, message: String, fields: Vec<FieldError>) -> ErrorResponse {
+    ErrorResponse {
+        status: status.as_u16(),
+        error: status.canonical_reason().unwrap_or("error").to_string(),
+        message,
+        fields,
+    }
+}
+
+pub fn field_errors(e: &FractalError) -> Vec<FieldError> {
+    match e {
+        FractalError::Validation(fields) => fields.clone(),
+        _ => vec![],
+    }
+}
+
+pub async fn handle_rejection(err: Rejection) -> Result<impl Reply, Infallible> {
+    let (status, message, fields) = if let Some(ApiError(e)) = err.find::<ApiError>() {
+        (status_code(e), e.to_string(), field_errors(e))
+    } else if let Some(StatusError(status, message)) = err.find::<StatusError>() {
+        (*status, message.clone(), vec![])
+    } else if err.is_not_found() {
+        (StatusCode::NOT_FOUND, "not found".to_string(), vec![])
+    } else if let Some(e) = err.find::<BodyDeserializeError>() {
+        (StatusCode::BAD_REQUEST, e.to_string(), vec![])
+    } else if err.find::<PayloadTooLarge>().is_some() {
+        (StatusCode::PAYLOAD_TOO_LARGE, "payload too large".to_string(), vec![])
+    } else if err.find::<MethodNotAllowed>().is_some() {
+        (StatusCode::METHOD_NOT_ALLOWED, "method not allowed".to_string(), vec![])
+    } else {
+        error!("unhandled rejection {:?}", err);
+        (StatusCode::INTERNAL_SERVER_ERROR, "internal server error".to_string(), vec![])
+    };
+
+    if status.is_server_error() {
+        error!("request failed with status {}: {}", status, message);
+    }
+
+    let response = error_response(status, message, fields);
+    Ok(warp::reply::with_status(warp::reply::json(&response), status))
+}
@@ -0,0 +1,443 @@
+use std::collections::BTreeMap;
+use std::convert::Infallible;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::thread;
+use std::time::Instant;
+
+use crossbeam_channel::{bounded, Receiver, Sender};
+use log::{error, info};
+use warp::http::header::CONTENT_TYPE;
+use warp::http::{Response, StatusCode};
+use warp::hyper::body::Bytes;
+use warp::reply::{json, with_status};
+use warp::{Filter, Reply};
+
+use common::color::Color;
+use common::error::{FractalError, FractalResult};
+use common::models::{FractalRequest, JobInfo, JobStatus};
+use common::output::{encode_output, save_outputs, OutputFormat};
+use common::palette::resolve_palette;
+use common::render_engine::RenderParams;
+use common::strip_render::{calc_strip, strip_rows};
+use common::validation::RequestLimits;
+
+use crate::error::{reject, reject_status};
+use crate::palettes::{with_uploaded_palettes, UploadedPalettes};
+use crate::server::with_limits;
+use crate::utils;
+
+// the progress is updated and a cancelled job stops after every strip
+const STRIP_HEIGHT: u32 = 16;
+// finished jobs are forgotten when there are more of them, the oldest first. this only happens
+// when a job is submitted, without new jobs the finished ones stay
+const MAX_FINISHED_JOBS: usize = 64;
+
+struct Job {
+    id: u64,
+    request: FractalRequest,
+    cancelled: AtomicBool,
+    state: Mutex<JobState>,
+}
+
+struct JobState {
+    status: JobStatus,
+    rows: u32,
+    duration_ms: Option<u128>,
+    error: Option<String>,
+    png: Option<Bytes>,
+}
+
+impl Job {
+    fn new(id: u64, request: FractalRequest) -> Job {
+        Job {
+            id,
+            request,
+            cancelled: AtomicBool::new(false),
+            state: Mutex::new(JobState {
+                status: JobStatus::Queued,
+                rows: 0,
+                duration_ms: None,
+                error: None,
+                png: None,
+            }),
+        }
+    }
+
+    fn info(&self) -> JobInfo {
+        let state = self.state.lock().unwrap();
+        JobInfo {
+            id: self.id,
+            status: state.status,
+            progress: state.rows as f64 / self.request.height as f64,
+            width: self.request.width,
+            height: self.request.height,
+            duration_ms: state.duration_ms,
+            error: state.error.clone(),
+        }
+    }
+
+    // false if the job was cancelled while it was queued
+    fn start(&self) -> bool {
+        let mut state = self.state.lock().unwrap();
+        if state.status != JobStatus::Queued {
+            return false;
+        }
+        state.status = JobStatus::Running;
+        true
+    }
+
+    // None if the job was cancelled
+    fn run(&self) -> FractalResult<Option<Bytes>> {
+        let req = &self.request;
+        let params = RenderParams::new(req, resolve_palette(&req.palette)?);
+        let mut pixels = Vec::with_capacity((params.width * params.height) as usize);
+        for strip in 0..params.height.div_ceil(STRIP_HEIGHT) {
+            if self.cancelled.load(Ordering::Relaxed) {
+                return Ok(None);
+            }
+            let (y0, y1) = strip_rows(strip, STRIP_HEIGHT, params.height);
+            let rgb = calc_strip(&params, y0, y1);
+            pixels.extend(rgb.chunks_exact(3).map(|c| Color {
+                r: c[0],
+                g: c[1],
+                b: c[2],
+            }));
+            self.state.lock().unwrap().rows = y1;
+        }
+        save_outputs(&pixels, &params, req)?;
+        let png = encode_output(OutputFormat::Png, &pixels, &params, req, None)?;
+        Ok(Some(Bytes::from(png)))
+    }
+
+    fn finish(&self, result: FractalResult<Option<Bytes>>, duration_ms: u128) {
+        let mut state = self.state.lock().unwrap();
+        state.duration_ms = Some(duration_ms);
+        match result {
+            // a cancelled job stays cancelled, even if the last strip was already calculated
+            Ok(_) if state.status == JobStatus::Cancelled => {}
+            Ok(png) => {
+                state.status = JobStatus::Finished;
+                state.png = png;
+            }
+            Err(e) => {
+                error!("job {} failed {}", self.id, e);
+                state.status = JobStatus::Failed;
+                state.error = Some(e.to_string());
+            }
+        }
+    }
+
+    fn cancel(&self) {
+        let mut state = self.state.lock().unwrap();
+        if !state.status.is_done() {
+            self.cancelled.store(true, Ordering::Relaxed);
+            state.status = JobStatus::Cancelled;
+        }
+    }
+}
+
+// renders in the background, a few jobs at a time on their own threads. the strips are
+// calculated with rayon, so one worker already uses all cores
+#[derive(Clone)]
+pub struct Jobs {
+    jobs: Arc<RwLock<BTreeMap<u64, Arc<Job>>>>,
+    queue: Sender<Arc<Job>>,
+    next_id: Arc<AtomicU64>,
+}
+
+impl Jobs {
+    // at most queue_size jobs wait for one of the workers
+    pub fn new(workers: usize, queue_size: usize) -> Jobs {
+        let (queue, receiver) = bounded(queue_size);
+        for idx in 0..workers {
+            let receiver = receiver.clone();
+            thread::Builder::new()
+                .name(format!("job-worker-{}", idx))
+                .spawn(move || work(receiver))
+                .expect("can't start a job worker");
+        }
+        Jobs {
+            jobs: Arc::new(RwLock::new(BTreeMap::new())),
+            queue,
+            next_id: Arc::new(AtomicU64::new(1)),
+        }
+    }
+
+    // None if the queue is full
+    pub fn submit(&self, request: FractalRequest) -> Option<JobInfo> {
+        let job = Arc::new(Job::new(
+            self.next_id.fetch_add(1, Ordering::Relaxed),
+            request,
+        ));
+        // inserted first, the worker may be done before this function returns
+        let mut jobs = self.jobs.write().unwrap();
+        jobs.insert(job.id, job.clone());
+        if self.queue.try_send(job.clone()).is_err() {
+            jobs.remove(&job.id);
+            return None;
+        }
+        forget_finished(&mut jobs);
+        Some(job.info())
+    }
+
+    pub fn info(&self, id: u64) -> Option<JobInfo> {
+        self.get(id).map(|job| job.info())
+    }
+
+    // the job and the png once it is finished
+    pub fn png(&self, id: u64) -> Option<(JobInfo, Option<Bytes>)> {
+        self.get(id)
+            .map(|job| (job.info(), job.state.lock().unwrap().png.clone()))
+    }
+
+    // a job that is done is removed with its image
+    pub fn cancel(&self, id: u64) -> Option<JobInfo> {
+        let mut jobs = self.jobs.write().unwrap();
+        let job = jobs.get(&id)?.clone();
+        if job.info().status.is_done() {
+            jobs.remove(&id);
+        } else {
+            job.cancel();
+        }
+        Some(job.info())
+    }
+
+    fn get(&self, id: u64) -> Option<Arc<Job>> {
+        self.jobs.read().unwrap().get(&id).cloned()
+    }
+}
+
+fn work(queue: Receiver<Arc<Job>>) {
+    while let Ok(job) = queue.recv() {
+        if !job.start() {
+            continue;
+        }
+        info!("job {} started", job.id);
+        let start = Instant::now();
+        let result = job.run();
+        let duration_ms = start.elapsed().as_millis();
+        job.finish(result, duration_ms);
+        info!("job {} is {:?} after {} ms", job.id, job.info().status, duration_ms);
+    }
+}
+
+fn forget_finished(jobs: &mut BTreeMap<u64, Arc<Job>>) {
+    let finished: Vec<u64> = jobs
+        .values()
+        .filter(|job| job.info().status.is_done())
+        .map(|job| job.id)
+        .collect();
+    for id in finished.iter().take(finished.len().saturating_sub(MAX_FINISHED_JOBS)) {
+        jobs.remove(id);
+    }
+}
+
+pub fn with_jobs(jobs: Jobs) -> impl Filter<Extract=(Jobs, ), Error=Infallible> + Clone {
+    warp::any().map(move || jobs.clone())
+}
+
+// POST api/jobs answers at once with the id, GET api/jobs/<id> until the status is Finished,
+// then GET api/jobs/<id>/image. DELETE api/jobs/<id> cancels a job or removes a finished one
+pub fn routes(
+    jobs: Jobs,
+    limits: RequestLimits,
+    palettes: UploadedPalettes,
+) -> impl Filter<Extract=(impl Reply, ), Error=warp::Rejection> + Clone {
+    let submit = warp::path!("api" / "jobs")
+        .and(warp::post())
+        .and(warp::body::json())
+        .and(with_jobs(jobs.clone()))
+        .and(with_limits(limits))
+        .and(with_uploaded_palettes(palettes))
+        .and_then(
+            |req: FractalRequest, jobs: Jobs, limits: RequestLimits, palettes: UploadedPalettes| {
+                info!("POST api/jobs  req {:?}", &req);
+                handle_submit(palettes.resolve(req), jobs, limits)
+            },
+        );
+
+    let status = warp::path!("api" / "jobs" / u64)
+        .and(warp::get())
+        .and(with_jobs(jobs.clone()))
+        .and_then(handle_status);
+
+    let image = warp::path!("api" / "jobs" / u64 / "image")
+        .and(warp::get())
+        .and(with_jobs(jobs.clone()))
+        .and_then(handle_image);
+
+    let cancel = warp::path!("api" / "jobs" / u64)
+        .and(warp::delete())
+        .and(with_jobs(jobs))
+        .and_then(handle_cancel);
+
+    submit.or(status).or(image).or(cancel)
+}
+
+async fn handle_submit(
+    req: FractalRequest,
+    jobs: Jobs,
+    limits: RequestLimits,
+) -> utils::Result<impl Reply> {
+    req.validate(&limits)
+        .map_err(|e| reject(FractalError::Validation(e)))?;
+    let info = jobs.submit(req).ok_or_else(|| {
+        reject_status(
+            StatusCode::SERVICE_UNAVAILABLE,
+            "too many jobs are waiting, try again later".to_string(),
+        )
+    })?;
+    info!("job {} queued", info.id);
+    Ok(with_status(json(&info), StatusCode::ACCEPTED))
+}
+
+async fn handle_status(id: u64, jobs: Jobs) -> utils::Result<impl Reply> {
+    let info = jobs.info(id).ok_or_else(|| unknown_job(id))?;
+    Ok(json(&info))
+}
+
+async fn handle_image(id: u64, jobs: Jobs) -> utils::Result<impl Reply> {
+    match jobs.png(id) {
+        Some((_, Some(png))) => Response::builder()
+            .header(CONTENT_TYPE, "image/png")
+            .body(png)
+            .map_err(|e| reject(FractalError::Render(e.to_string()))),
+        Some((info, None)) => Err(reject_status(
+            StatusCode::CONFLICT,
+            format!("job {} is {:?}, there is no image", id, info.status),
+        )),
+        None => Err(unknown_job(id)),
+    }
+}
+
+async fn handle_cancel(id: u64, jobs: Jobs) -> utils::Result<impl Reply> {
+    let info = jobs.cancel(id).ok_or_else(|| unknown_job(id))?;
+    info!("DELETE api/jobs/{}  {:?}", id, info.status);
+    Ok(json(&info))
+}
+
+fn unknown_job(id: u64) -> warp::Rejection {
+    reject_status(StatusCode::NOT_FOUND, format!("unknown job {}", id))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::Duration;
+
+    use common::fractal_templates::basic;
+    use common::models::{FractalRequest, JobInfo, JobStatus};
+
+    use crate::jobs::{forget_finished, Job, Jobs, MAX_FINISHED_JOBS};
+
+    fn request() -> FractalRequest {
+        let (mut req, _, _) = basic(true);
+        req.width = 24;
+        req.height = 40;
+        req.max_iterations = 100;
+        req.x_tiles = 1;
+        req.y_tiles = 1;
+        req
+    }
+
+    fn wait_until_done(jobs: &Jobs, id: u64) -> JobInfo {
+        for _ in 0..500 {
+            let info = jobs.info(id).unwrap();
+            if info.status.is_done() {
+                return info;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        panic!("job {} isn't done", id);
+    }
+
+    #[test]
+    fn test_finished_job() {
+        let jobs = Jobs::new(1, 1);
+        let info = jobs.submit(request()).unwrap();
+        let info = wait_until_done(&jobs, info.id);
+        assert_eq!(info.status, JobStatus::Finished);
+        assert_eq!(info.progress, 1.0);
+        assert!(info.duration_ms.is_some());
+
+        let (_, png) = jobs.png(info.id).unwrap();
+        let image = image::load_from_memory(&png.unwrap()).unwrap();
+        assert_eq!((image.width(), image.height()), (24, 40));
+
+        // cancelling a finished job removes it
+        assert_eq!(jobs.cancel(info.id).unwrap().status, JobStatus::Finished);
+        assert!(jobs.info(info.id).is_none());
+        assert!(jobs.cancel(info.id).is_none());
+    }
+
+    #[test]
+    fn test_full_queue() {
+        // one job runs and one waits, so the third one is always too many
+        let jobs = Jobs::new(1, 1);
+        let slow = FractalRequest {
+            width: 1000,
+            height: 1000,
+            max_iterations: 10_000,
+            ..request()
+        };
+        let first = jobs.submit(slow.clone()).unwrap();
+        let second = jobs.submit(slow.clone());
+        assert!(jobs.submit(slow).is_none());
+
+        for info in [Some(first), second].into_iter().flatten() {
+            assert_eq!(jobs.cancel(info.id).unwrap().status, JobStatus::Cancelled);
+            let info = wait_until_done(&jobs, info.id);
+            assert_eq!(info.status, JobStatus::Cancelled);
+            assert!(info.progress < 1.0);
+            assert_eq!(jobs.png(info.id).unwrap().1, None);
+        }
+    }
+
+    #[test]
+    fn test_cancel_queued() {
+        let job = Job::new(1, request());
+        job.cancel();
+        assert!(!job.start());
+        assert_eq!(job.info().status, JobStatus::Cancelled);
+    }
+
+    #[test]
+    fn test_cancel_running() {
+        let job = Job::new(1, request());
+        assert!(job.start());
+        assert_eq!(job.info().status, JobStatus::Running);
+
+        // the job stops before the next strip and doesn't become finished
+        job.cancel();
+        let result = job.run();
+        assert!(matches!(result, Ok(None)));
+        job.finish(result, 1);
+        let info = job.info();
+        assert_eq!(info.status, JobStatus::Cancelled);
+        assert_eq!(info.progress, 0.0);
+        assert!(job.state.lock().unwrap().png.is_none());
+    }
+
+    #[test]
+    fn test_forget_finished() {
+        let mut jobs = BTreeMap::new();
+        for id in 0..MAX_FINISHED_JOBS as u64 + 3 {
+            let job = Job::new(id, request());
+            // the oldest one is still waiting and is kept
+            if id > 0 {
+                job.finish(Ok(None), 1);
+            }
+            jobs.insert(id, Arc::new(job));
+        }
+        forget_finished(&mut jobs);
+
+        assert_eq!(jobs.len(), MAX_FINISHED_JOBS + 1);
+        assert!(jobs.contains_key(&0));
+        assert!(!jobs.contains_key(&1));
+        assert!(!jobs.contains_key(&2));
+        assert!(jobs.contains_key(&3));
+    }
+}
@@ -1,10 +1,25 @@
-use log::{info, LevelFilter};
+use std::process;
+
+use log::{error, info, LevelFilter};
 use pretty_env_logger::env_logger::{Builder, Target};
 use warp::Filter;
 
+use common::output::set_output_sink;
+use common::palette::palette_library;
+
+use crate::palettes::UploadedPalettes;
 use crate::server::routes;
 
+mod config;
+mod cycle;
+mod error;
+mod jobs;
+mod metadata;
+mod palettes;
+mod render;
 mod server;
+mod session;
+mod tiles;
 mod utils;
 
 // #[tokio::main(worker_threads = 2)]
@@ -16,7 +31,28 @@ async fn main() {
     builder.init();
     info!("builder={:?}", builder);
 
-    let routes = routes().with(utils::cors());
+    // read the palettes once at startup instead of on the first request
+    match palette_library() {
+        Ok(palettes) => info!("loaded {} palettes", palettes.len()),
+        Err(e) => error!("can't load the palettes: {}", e),
+    }
+
+    // a wrong output setting stops the server, the images would end up in the wrong place
+    let output = config::output_sink()
+        .and_then(|(sink, template)| set_output_sink(sink, &template));
+    if let Err(e) = output {
+        error!("can't configure the output: {}", e);
+        process::exit(1);
+    }
+
+    let routes = routes(
+        config::request_limits(),
+        UploadedPalettes::default(),
+        config::tile_cache(),
+        config::jobs(),
+    )
+        .recover(error::handle_rejection)
+        .with(utils::cors());
 
     warp::serve(routes).run(([127, 0, 0, 1], 3000)).await;
 }
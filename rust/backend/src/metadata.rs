@@ -0,0 +1,27 @@
+use log::info;
+use warp::hyper::body::Bytes;
+use warp::reply::json;
+use warp::{Filter, Reply};
+
+use common::png_metadata::read_request;
+
+use crate::error::reject;
+use crate::utils;
+
+const MAX_PNG_BYTES: u64 = 64 * 1024 * 1024;
+
+// POST api/metadata with a PNG rendered by this server as body returns the request it was rendered
+// from, the client can render it again or zoom further into it
+pub fn routes() -> impl Filter<Extract=(impl Reply, ), Error=warp::Rejection> + Clone {
+    warp::path!("api" / "metadata")
+        .and(warp::post())
+        .and(warp::body::content_length_limit(MAX_PNG_BYTES))
+        .and(warp::body::bytes())
+        .and_then(handle_metadata)
+}
+
+async fn handle_metadata(body: Bytes) -> utils::Result<impl Reply> {
+    info!("POST api/metadata  {} bytes", body.len());
+    let req = read_request(&body).map_err(reject)?;
+    Ok(json(&req))
+}
@@ -0,0 +1,306 @@
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::sync::{Arc, RwLock};
+use std::time::Instant;
+
+use log::info;
+use warp::hyper::body::Bytes;
+use serde_derive::Deserialize;
+use warp::http::header::CONTENT_TYPE;
+use warp::http::StatusCode;
+use warp::reply::{json, with_header};
+use warp::{Filter, Reply};
+
+use common::coloring::Palette;
+use common::error::{FractalError, FractalResult};
+use common::models::{FractalRequest, PaletteInfo, PaletteSpec};
+use common::palette::extract::{extract_palette, PaletteOrder};
+use common::palette::{
+    palette_library, read_palette_file, resolve_palette, write_palette_file, PaletteFormat,
+};
+use common::utils::encode_png;
+use common::validation::RequestLimits;
+
+use crate::error::{reject, reject_status};
+use crate::server::with_limits;
+use crate::utils;
+
+const MAX_UPLOAD_BYTES: u64 = 1024 * 1024;
+const MAX_IMAGE_BYTES: u64 = 16 * 1024 * 1024;
+// uploads are kept in memory until the server stops, so there can't be arbitrarily many
+const MAX_UPLOADED_PALETTES: usize = 256;
+
+// palettes uploaded with POST api/palettes/<file name>. they are only kept in memory
+#[derive(Clone, Default)]
+pub struct UploadedPalettes {
+    palettes: Arc<RwLock<HashMap<String, Palette>>>,
+}
+
+impl UploadedPalettes {
+    // false if there are already MAX_UPLOADED_PALETTES palettes. replacing one always works
+    pub fn insert(&self, name: String, palette: Palette) -> bool {
+        let mut palettes = self.palettes.write().unwrap();
+        if palettes.len() >= MAX_UPLOADED_PALETTES && !palettes.contains_key(&name) {
+            return false;
+        }
+        palettes.insert(name, palette);
+        true
+    }
+
+    pub fn get(&self, name: &str) -> Option<Palette> {
+        self.palettes
+            .read()
+            .unwrap()
+            .get(&name.to_lowercase())
+            .cloned()
+    }
+
+    pub fn infos(&self) -> Vec<PaletteInfo> {
+        self.palettes
+            .read()
+            .unwrap()
+            .iter()
+            .map(|(name, palette)| palette_info(name, palette))
+            .collect()
+    }
+
+    // uploaded palettes hide palettes from the palette directory with the same name
+    pub fn lookup(&self, name: &str) -> FractalResult<Palette> {
+        match self.get(name) {
+            Some(palette) => Ok(palette),
+            None => resolve_palette(&PaletteSpec::Name(name.to_string())),
+        }
+    }
+
+    // the engines only know the palette directory, so an uploaded palette is sent as colors
+    pub fn resolve(&self, req: FractalRequest) -> FractalRequest {
+        match &req.palette {
+            PaletteSpec::Name(name) => match self.get(name) {
+                Some(palette) => FractalRequest {
+                    palette: palette.into(),
+                    ..req
+                },
+                None => req,
+            },
+            _ => req,
+        }
+    }
+}
+
+pub fn with_uploaded_palettes(
+    palettes: UploadedPalettes,
+) -> impl Filter<Extract=(UploadedPalettes, ), Error=Infallible> + Clone {
+    warp::any().map(move || palettes.clone())
+}
+
+#[derive(Deserialize, Debug)]
+struct SwatchQuery {
+    #[serde(default = "default_swatch_width")]
+    width: u32,
+    #[serde(default = "default_swatch_height")]
+    height: u32,
+}
+
+#[derive(Deserialize, Debug)]
+struct ExtractQuery {
+    #[serde(default = "default_extract_colors")]
+    colors: usize,
+    #[serde(default)]
+    order: PaletteOrder,
+    name: Option<String>,
+    format: Option<PaletteFormat>,
+}
+
+fn default_extract_colors() -> usize {
+    8
+}
+
+fn default_swatch_width() -> u32 {
+    256
+}
+
+fn default_swatch_height() -> u32 {
+    32
+}
+
+fn palette_info(name: &str, palette: &Palette) -> PaletteInfo {
+    let colors = match palette {
+        Palette::Colors(colors) => colors.len(),
+        Palette::Gradient(gradient) => gradient.stops.len(),
+    };
+    PaletteInfo {
+        name: name.to_string(),
+        colors,
+    }
+}
+
+pub fn routes(
+    palettes: UploadedPalettes,
+    limits: RequestLimits,
+) -> impl Filter<Extract=(impl Reply, ), Error=warp::Rejection> + Clone {
+    let list = warp::path!("api" / "palettes")
+        .and(warp::get())
+        .and(with_uploaded_palettes(palettes.clone()))
+        .and_then(handle_list);
+
+    let swatch = warp::path!("api" / "palettes" / String)
+        .and(warp::get())
+        .and(warp::query::<SwatchQuery>())
+        .and(with_uploaded_palettes(palettes.clone()))
+        .and_then(handle_swatch);
+
+    let extract = warp::path!("api" / "palettes" / "extract")
+        .and(warp::post())
+        .and(warp::query::<ExtractQuery>())
+        .and(warp::body::content_length_limit(MAX_IMAGE_BYTES))
+        .and(warp::body::bytes())
+        .and(with_uploaded_palettes(palettes.clone()))
+        .and(with_limits(limits.clone()))
+        .and_then(handle_extract);
+
+    let upload = warp::path!("api" / "palettes" / String)
+        .and(warp::post())
+        .and(warp::body::content_length_limit(MAX_UPLOAD_BYTES))
+        .and(warp::body::bytes())
+        .and(with_uploaded_palettes(palettes))
+        .and(with_limits(limits))
+        .and_then(handle_upload);
+
+    list.or(swatch).or(extract).or(upload)
+}
+
+async fn handle_list(palettes: UploadedPalettes) -> utils::Result<impl Reply> {
+    let library = palette_library().map_err(reject)?;
+    let mut infos: Vec<PaletteInfo> = library
+        .iter()
+        .map(|(name, colors)| PaletteInfo {
+            name: name.clone(),
+            colors: colors.len(),
+        })
+        .filter(|info| palettes.get(&info.name).is_none())
+        .collect();
+    infos.extend(palettes.infos());
+    infos.sort_by(|a, b| a.name.cmp(&b.name));
+
+    Ok(json(&infos))
+}
+
+// GET api/palettes/<name>.png?width=256&height=32
+async fn handle_swatch(
+    file: String,
+    query: SwatchQuery,
+    palettes: UploadedPalettes,
+) -> utils::Result<impl Reply> {
+    let name = file
+        .strip_suffix(".png")
+        .ok_or_else(|| reject(FractalError::PaletteNotFound(file.clone())))?;
+    if query.width == 0 || query.width > 4096 || query.height == 0 || query.height > 512 {
+        return Err(reject(FractalError::InvalidRequest(
+            "swatch width must be between 1 and 4096 and height between 1 and 512".to_string(),
+        )));
+    }
+
+    let palette = palettes.lookup(name).map_err(reject)?;
+    let pixels = palette.swatch(query.width, query.height);
+    let png = encode_png(&pixels, query.width, query.height).map_err(reject)?;
+
+    Ok(with_header(png, CONTENT_TYPE, "image/png"))
+}
+
+fn check_palette_name(name: &str) -> utils::Result<()> {
+    let valid_name = name.len() <= 64
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-' || c == '.');
+    if !valid_name {
+        return Err(reject(FractalError::InvalidRequest(
+            "palette name must be at most 64 characters of a-z, A-Z, 0-9, '_', '-' or '.'"
+                .to_string(),
+        )));
+    }
+    Ok(())
+}
+
+// POST api/palettes/extract?colors=8&order=Hue&name=brand.map with a PNG or JPEG as body.
+// with a name the palette is stored like an uploaded one, format=Map returns a .MAP file
+async fn handle_extract(
+    query: ExtractQuery,
+    body: Bytes,
+    palettes: UploadedPalettes,
+    limits: RequestLimits,
+) -> utils::Result<Box<dyn Reply>> {
+    info!("POST api/palettes/extract  {:?}  {} bytes", &query, body.len());
+
+    if let Some(name) = &query.name {
+        check_palette_name(name)?;
+    }
+    let (colors, order) = (query.colors, query.order);
+    let gradient = utils::blocking(move || {
+        let start = Instant::now();
+        let gradient = extract_palette("image", &body, colors, order, limits.max_pixels)?;
+        info!(
+            "extracted {} colors in {} ms",
+            gradient.stops.len(),
+            start.elapsed().as_millis()
+        );
+        Ok(gradient)
+    })
+    .await?;
+
+    let palette = Palette::Gradient(gradient.clone());
+    let name = query.name.map(|n| n.to_lowercase());
+    if let Some(name) = &name {
+        if !palettes.insert(name.clone(), palette.clone()) {
+            return Err(too_many_palettes());
+        }
+    }
+
+    match query.format {
+        Some(format) => {
+            let file = write_palette_file(format, name.as_deref().unwrap_or("extracted"), &palette);
+            Ok(Box::new(with_header(file, CONTENT_TYPE, "text/plain")))
+        }
+        None => Ok(Box::new(json(&gradient))),
+    }
+}
+
+async fn handle_upload(
+    name: String,
+    body: Bytes,
+    palettes: UploadedPalettes,
+    limits: RequestLimits,
+) -> utils::Result<impl Reply> {
+    info!("POST api/palettes/{}  {} bytes", &name, body.len());
+
+    check_palette_name(&name)?;
+
+    let palette = read_palette_file(&name, &body).map_err(reject)?;
+    let info = palette_info(&name.to_lowercase(), &palette);
+    let colors = info.colors;
+    if colors > limits.max_palette_colors {
+        return Err(reject_status(
+            StatusCode::PAYLOAD_TOO_LARGE,
+            format!(
+                "palette has {} colors, but at most {} are allowed",
+                colors, limits.max_palette_colors
+            ),
+        ));
+    }
+
+    if !palettes.insert(info.name.clone(), palette) {
+        return Err(too_many_palettes());
+    }
+    info!("uploaded palette {} with {} colors", &info.name, colors);
+
+    Ok(json(&info))
+}
+
+fn too_many_palettes() -> warp::Rejection {
+    reject_status(
+        StatusCode::TOO_MANY_REQUESTS,
+        format!(
+            "there are already {} uploaded palettes, replace one of them",
+            MAX_UPLOADED_PALETTES
+        ),
+    )
+}
@@ -0,0 +1,227 @@
+use log::{error, info};
+use warp::http::header::{CONTENT_TYPE, VARY};
+use warp::http::{Response, StatusCode};
+use warp::{Filter, Reply};
+
+use common::error::FractalError;
+use common::models::{FractalResponse, RenderRequest};
+use common::output::{encode_output, OutputFormat};
+use common::palette::resolve_palette;
+use common::render_engine::{engine, engine_names, RenderParams};
+use common::validation::RequestLimits;
+
+use crate::error::{reject, reject_status};
+use crate::palettes::{with_uploaded_palettes, UploadedPalettes};
+use crate::server::with_limits;
+use crate::utils;
+
+const JSON: &str = "application/json";
+const RAW: &str = "application/octet-stream";
+
+// what api/render answers with, chosen by the Accept header
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum RenderFormat {
+    Json,
+    Image(OutputFormat),
+    // 4 bytes per pixel, row by row. the size is in the x-fractal-width and x-fractal-height headers
+    Rgba,
+}
+
+impl RenderFormat {
+    fn from_media_type(media_type: &str) -> Option<RenderFormat> {
+        match media_type {
+            // curl and fetch() send */* when they don't care
+            JSON | "*/*" => Some(RenderFormat::Json),
+            RAW => Some(RenderFormat::Rgba),
+            "image/png" | "image/*" => Some(RenderFormat::Image(OutputFormat::Png)),
+            "image/webp" => Some(RenderFormat::Image(OutputFormat::WebP)),
+            "image/jpeg" => Some(RenderFormat::Image(OutputFormat::Jpeg)),
+            _ => None,
+        }
+    }
+
+    fn content_type(&self) -> &'static str {
+        match self {
+            RenderFormat::Json => JSON,
+            RenderFormat::Image(format) => format.content_type(),
+            RenderFormat::Rgba => RAW,
+        }
+    }
+}
+
+// the supported media type with the highest q value, the first one wins a tie.
+// without an Accept header or with only */* the answer is the json of the api/<engine> routes
+fn negotiate(accept: Option<&str>) -> Result<RenderFormat, String> {
+    let accept = match accept.map(str::trim) {
+        None | Some("") => return Ok(RenderFormat::Json),
+        Some(accept) => accept,
+    };
+    let mut best: Option<(f64, RenderFormat)> = None;
+    for range in accept.split(',') {
+        let mut parts = range.split(';').map(str::trim);
+        let media_type = parts.next().unwrap_or("").to_ascii_lowercase();
+        let q = parts
+            .filter_map(|p| p.strip_prefix("q="))
+            .find_map(|q| q.parse::<f64>().ok())
+            .unwrap_or(1.0);
+        if q <= 0.0 {
+            continue;
+        }
+        if let Some(format) = RenderFormat::from_media_type(&media_type) {
+            if best.is_none_or(|(best_q, _)| q > best_q) {
+                best = Some((q, format));
+            }
+        }
+    }
+    best.map(|(_, format)| format).ok_or_else(|| {
+        format!(
+            "can't answer with {}, supported are {}, {}, image/png, image/webp and image/jpeg",
+            accept, JSON, RAW
+        )
+    })
+}
+
+// one endpoint for all engines, e.g.
+// curl -H 'Accept: image/webp' -d '{"engine": "rayon", ...}' localhost:3000/api/render
+pub fn routes(
+    limits: RequestLimits,
+    palettes: UploadedPalettes,
+) -> impl Filter<Extract=(impl Reply, ), Error=warp::Rejection> + Clone {
+    warp::path!("api" / "render")
+        .and(warp::post())
+        .and(warp::header::optional::<String>("accept"))
+        .and(warp::body::json())
+        .and(with_limits(limits))
+        .and(with_uploaded_palettes(palettes))
+        .and_then(
+            |accept: Option<String>,
+             req: RenderRequest,
+             limits: RequestLimits,
+             palettes: UploadedPalettes| {
+                info!(
+                    "POST api/render  engine {}  accept {:?}  req {:?}",
+                    &req.engine, &accept, &req.request
+                );
+                let req = RenderRequest {
+                    request: palettes.resolve(req.request.clone()),
+                    ..req
+                };
+                handle_render(req, accept, limits)
+            },
+        )
+}
+
+async fn handle_render(
+    req: RenderRequest,
+    accept: Option<String>,
+    limits: RequestLimits,
+) -> utils::Result<impl Reply> {
+    // checked first, there is no point in rendering an image nobody can read
+    let format = negotiate(accept.as_deref())
+        .map_err(|e| reject_status(StatusCode::NOT_ACCEPTABLE, e))?;
+    let engine = engine(&req.engine).map_err(|e| {
+        error!("{}. available engines {:?}", e, engine_names());
+        reject(e)
+    })?;
+    let req = req.request;
+    req.validate(&limits)
+        .map_err(|e| reject(FractalError::Validation(e)))?;
+
+    let name = engine.name();
+    let (body, width, height, duration_ms) = utils::blocking(move || {
+        let params = RenderParams::new(&req, resolve_palette(&req.palette)?);
+        let result = engine.render_params(&req, &params)?;
+        let duration_calculation = format!(
+            "calculation {} took {:0.2} ms using {} cores",
+            engine.name(),
+            result.duration_ms,
+            result.threads
+        );
+        info!("{}  answering with {:?}", duration_calculation, format);
+
+        let (width, height, duration_ms) = (
+            result.fractal.width,
+            result.fractal.height,
+            result.duration_ms,
+        );
+        let body = match format {
+            RenderFormat::Json => serde_json::to_vec(&FractalResponse {
+                duration_calculation,
+                duration_ms,
+                fractal: result.fractal,
+            })?,
+            RenderFormat::Image(output) => {
+                encode_output(output, &result.fractal.pixels, &params, &req, None)?
+            }
+            RenderFormat::Rgba => result
+                .fractal
+                .pixels
+                .iter()
+                .flat_map(|c| [c.r, c.g, c.b, 255])
+                .collect(),
+        };
+        Ok((body, width, height, duration_ms))
+    })
+    .await?;
+
+    Response::builder()
+        .status(StatusCode::OK)
+        .header(CONTENT_TYPE, format.content_type())
+        .header(VARY, "accept")
+        .header("x-fractal-engine", name)
+        .header("x-fractal-width", width)
+        .header("x-fractal-height", height)
+        .header("x-fractal-duration-ms", duration_ms.to_string())
+        .body(body)
+        .map_err(|e| reject(FractalError::Render(e.to_string())))
+}
+
+#[cfg(test)]
+mod tests {
+    use common::output::OutputFormat;
+
+    use crate::render::{negotiate, RenderFormat};
+
+    #[test]
+    fn test_negotiate() {
+        assert_eq!(negotiate(None), Ok(RenderFormat::Json));
+        assert_eq!(negotiate(Some(" ")), Ok(RenderFormat::Json));
+        assert_eq!(
+            negotiate(Some("image/webp")),
+            Ok(RenderFormat::Image(OutputFormat::WebP))
+        );
+        assert_eq!(
+            negotiate(Some("application/octet-stream")),
+            Ok(RenderFormat::Rgba)
+        );
+        assert_eq!(negotiate(Some("*/*")), Ok(RenderFormat::Json));
+        assert_eq!(
+            negotiate(Some("image/*, */*;q=0.8")),
+            Ok(RenderFormat::Image(OutputFormat::Png))
+        );
+    }
+
+    #[test]
+    fn test_negotiate_q_values() {
+        assert_eq!(
+            negotiate(Some("image/png;q=0.5, image/jpeg; q=0.9, application/json;q=0.1")),
+            Ok(RenderFormat::Image(OutputFormat::Jpeg))
+        );
+        // a tie goes to the first one, q=0 means never
+        assert_eq!(
+            negotiate(Some("Image/WebP, application/json")),
+            Ok(RenderFormat::Image(OutputFormat::WebP))
+        );
+        assert_eq!(
+            negotiate(Some("image/webp;q=0, text/html, application/json;q=0.2")),
+            Ok(RenderFormat::Json)
+        );
+    }
+
+    #[test]
+    fn test_not_acceptable() {
+        assert!(negotiate(Some("text/html")).is_err());
+        assert!(negotiate(Some("image/gif, text/*;q=0.8")).is_err());
+        assert!(negotiate(Some("image/png;q=0")).is_err());
+    }
+}
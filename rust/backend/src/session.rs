@@ -0,0 +1,636 @@
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Instant;
+
+use crossbeam_channel::unbounded;
+use futures_util::{SinkExt, StreamExt};
+use log::{error, info};
+use tokio::sync::mpsc::{unbounded_channel, UnboundedSender};
+use warp::ws::{Message, WebSocket};
+
+use common::error::{FractalError, FractalResult};
+use common::fractal::calc_fractal_iterations;
+use common::fractal_calculation_crossbeam::calc_crossbeam_tiles;
+use common::image_tile::tiles;
+use common::iteration_buffer::IterationBuffer;
+use common::models::{
+    FractalRequest, RecolorRequest, WebSocketCommand, WebSocketEvent, WebSocketRequest,
+};
+use common::output::save_outputs;
+use common::palette::resolve_palette;
+use common::render_engine::RenderParams;
+use common::tile_frame::{TileEncoding, TileFrame};
+use common::utils::print_debug;
+use common::validation::RequestLimits;
+
+use crate::error::field_errors;
+use crate::palettes::UploadedPalettes;
+
+// one socket for a whole exploration: the client sends RENDERFRACTAL once and then RERENDER,
+// RECOLOR or CANCEL, the server answers with events and the tiles of the current render
+pub async fn handle_session(ws: WebSocket, limits: RequestLimits, palettes: UploadedPalettes) {
+    let (mut websocket_tx, mut websocket_rx) = ws.split();
+
+    // everything is sent by this task in the order it was queued, the renders only queue
+    let (tx, mut rx) = unbounded_channel::<Message>();
+    tokio::task::spawn(async move {
+        while let Some(msg) = rx.recv().await {
+            if let Err(e) = websocket_tx.send(msg).await {
+                error!("websocket send error: {}", e);
+                break;
+            }
+        }
+    });
+
+    let mut session = Session::new(tx, limits, palettes);
+
+    while let Some(msg) = websocket_rx.next().await {
+        let msg = match msg {
+            Ok(msg) => msg,
+            Err(e) => {
+                error!("error receiving websocket message {}", e);
+                break;
+            }
+        };
+        if msg.is_close() {
+            info!("got a close message");
+            break;
+        }
+        if msg.is_ping() || msg.is_pong() {
+            continue;
+        }
+        match parse_web_socket_request(&msg) {
+            Ok(req) => session.handle(req),
+            Err(e) => {
+                error!("invalid websocket request {}", e);
+                session.shared.send_error(None, &e);
+            }
+        }
+    }
+    // nobody is waiting for the tiles anymore
+    session.shared.stop();
+}
+
+fn parse_web_socket_request(msg: &Message) -> Result<WebSocketRequest, FractalError> {
+    let txt = msg
+        .to_str()
+        .map_err(|_| FractalError::InvalidRequest("expected a text message".to_string()))?;
+    info!("got a text message '{}'", txt);
+
+    let req: WebSocketRequest = serde_json::from_str(txt)?;
+    info!("got a web_socket_request    {:?}", &req);
+
+    Ok(req)
+}
+
+// the state the renders share with the session
+struct Shared {
+    tx: UnboundedSender<Message>,
+    // the render whose tiles are sent. it is only changed and checked under the lock, so no tile
+    // of an old render can follow the RENDER_STARTED of the next one
+    current: Mutex<Option<(u32, Instant)>>,
+    // the last finished render, RECOLOR only colors its iterations again
+    finished: Mutex<Option<(FractalRequest, Arc<IterationBuffer>)>>,
+}
+
+impl Shared {
+    fn send_event(&self, event: &WebSocketEvent) {
+        match serde_json::to_string(event) {
+            Ok(json) => {
+                // the writer only stops when the socket is gone
+                let _ = self.tx.send(Message::text(json));
+            }
+            Err(e) => error!("can't serialize {:?}: {}", event, e),
+        }
+    }
+
+    fn send_error(&self, id: Option<u32>, e: &FractalError) {
+        self.send_event(&WebSocketEvent::Error {
+            id,
+            message: e.to_string(),
+            fields: field_errors(e),
+        });
+    }
+
+    fn is_current(&self, id: u32) -> bool {
+        self.current
+            .lock()
+            .unwrap()
+            .is_some_and(|(current, _)| current == id)
+    }
+
+    fn start(&self, id: u32, event: WebSocketEvent) {
+        let mut current = self.current.lock().unwrap();
+        *current = Some((id, Instant::now()));
+        self.send_event(&event);
+    }
+
+    // false if the render was cancelled or replaced by another one
+    fn send_for(&self, id: u32, msgs: Vec<Message>) -> bool {
+        let current = self.current.lock().unwrap();
+        if !current.is_some_and(|(current, _)| current == id) {
+            return false;
+        }
+        for msg in msgs {
+            let _ = self.tx.send(msg);
+        }
+        true
+    }
+
+    fn finish(&self, id: u32, result: FractalResult<()>) {
+        let mut current = self.current.lock().unwrap();
+        let Some((current_id, start)) = *current else {
+            return;
+        };
+        if current_id != id {
+            return;
+        }
+        *current = None;
+        match result {
+            Ok(()) => self.send_event(&WebSocketEvent::RenderDone {
+                id,
+                duration_ms: start.elapsed().as_millis(),
+                cancelled: false,
+            }),
+            Err(e) => {
+                error!("render {} failed {}", id, e);
+                self.send_error(Some(id), &e);
+            }
+        }
+    }
+
+    // the id of the render that was stopped
+    fn stop(&self) -> Option<(u32, Instant)> {
+        self.current.lock().unwrap().take()
+    }
+}
+
+struct Session {
+    shared: Arc<Shared>,
+    limits: RequestLimits,
+    palettes: UploadedPalettes,
+    next_id: u32,
+    // the request of the last render, RERENDER changes its viewport
+    last_request: Option<FractalRequest>,
+    encoding: TileEncoding,
+    compress: bool,
+}
+
+impl Session {
+    fn new(
+        tx: UnboundedSender<Message>,
+        limits: RequestLimits,
+        palettes: UploadedPalettes,
+    ) -> Session {
+        Session {
+            shared: Arc::new(Shared {
+                tx,
+                current: Mutex::new(None),
+                finished: Mutex::new(None),
+            }),
+            limits,
+            palettes,
+            next_id: 1,
+            last_request: None,
+            encoding: TileEncoding::default(),
+            compress: false,
+        }
+    }
+
+    fn handle(&mut self, req: WebSocketRequest) {
+        match req.command {
+            WebSocketCommand::RENDERFRACTAL(fractal_request) => {
+                self.encoding = req.encoding;
+                self.compress = req.compress;
+                self.render(self.palettes.resolve(fractal_request));
+            }
+            WebSocketCommand::RERENDER(viewport) => match &self.last_request {
+                Some(last) => self.render(viewport.to_request(last)),
+                None => self.shared.send_error(
+                    None,
+                    &FractalError::InvalidRequest(
+                        "RERENDER needs a RENDERFRACTAL first".to_string(),
+                    ),
+                ),
+            },
+            WebSocketCommand::RECOLOR(recolor) => self.recolor(recolor),
+            WebSocketCommand::CANCEL => {
+                if let Some((id, start)) = self.shared.stop() {
+                    info!("render {} cancelled", id);
+                    self.shared.send_event(&WebSocketEvent::RenderDone {
+                        id,
+                        duration_ms: start.elapsed().as_millis(),
+                        cancelled: true,
+                    });
+                }
+            }
+            WebSocketCommand::PING => self.shared.send_event(&WebSocketEvent::Pong),
+        }
+    }
+
+    fn params(&self, req: &FractalRequest) -> FractalResult<RenderParams> {
+        req.validate(&self.limits)
+            .map_err(FractalError::Validation)?;
+        Ok(RenderParams::new(req, resolve_palette(&req.palette)?))
+    }
+
+    // the render before is cancelled
+    fn start(&mut self, params: &RenderParams) -> SessionRender {
+        let id = self.next_id;
+        self.next_id += 1;
+        let tiles = tile_count(params);
+        self.shared.start(
+            id,
+            WebSocketEvent::RenderStarted {
+                id,
+                width: params.width,
+                height: params.height,
+                tiles,
+            },
+        );
+        SessionRender {
+            shared: self.shared.clone(),
+            id,
+            encoding: self.encoding,
+            compress: self.compress,
+            tiles,
+        }
+    }
+
+    fn render(&mut self, req: FractalRequest) {
+        info!(
+            "client wants to start rendering an image  encoding {:?}  compress {}  {:?}",
+            self.encoding, self.compress, &req
+        );
+        let params = match self.params(&req) {
+            Ok(params) => params,
+            Err(e) => {
+                error!("invalid websocket request {}", e);
+                self.shared.send_error(None, &e);
+                return;
+            }
+        };
+        print_debug(&params);
+
+        self.last_request = Some(req.clone());
+        let render = self.start(&params);
+        // the tiles are calculated on the blocking pool, not on the threads of the runtime
+        tokio::task::spawn_blocking(move || {
+            let result = render.calc_tiles(&req, &params);
+            render.shared.finish(render.id, result);
+        });
+    }
+
+    fn recolor(&mut self, recolor: RecolorRequest) {
+        let finished = self.shared.finished.lock().unwrap().clone();
+        let result = match finished {
+            None => Err(FractalError::InvalidRequest(
+                "there is no finished render to recolor".to_string(),
+            )),
+            Some(_) if self.encoding == TileEncoding::Iterations => Err(
+                FractalError::InvalidRequest("the client colors the iterations itself".to_string()),
+            ),
+            Some((req, iterations)) => {
+                let req = FractalRequest {
+                    palette: recolor.palette.unwrap_or(req.palette.clone()),
+                    palette_offset: recolor.palette_offset.unwrap_or(req.palette_offset),
+                    ..req
+                };
+                let req = self.palettes.resolve(req);
+                self.params(&req).map(|params| (req, iterations, params))
+            }
+        };
+        let (req, iterations, params) = match result {
+            Ok(result) => result,
+            Err(e) => {
+                error!("can't recolor {}", e);
+                self.shared.send_error(None, &e);
+                return;
+            }
+        };
+        info!("recolor with {:?}", &req.palette);
+
+        let render = self.start(&params);
+        tokio::task::spawn_blocking(move || {
+            let result = render.color_tiles(&req, &params, iterations);
+            render.shared.finish(render.id, result);
+        });
+    }
+}
+
+fn tile_count(params: &RenderParams) -> u32 {
+    tiles(params.width, params.height, params.x_tiles, params.y_tiles).count() as u32
+}
+
+// one render or recolor of the session, runs on the blocking pool
+struct SessionRender {
+    shared: Arc<Shared>,
+    id: u32,
+    encoding: TileEncoding,
+    compress: bool,
+    tiles: u32,
+}
+
+impl SessionRender {
+    // the iterations are kept as f64 for RECOLOR and the outputs, only the tiles in the
+    // Iterations encoding have f32 values
+    fn calc_tiles(&self, req: &FractalRequest, params: &RenderParams) -> FractalResult<()> {
+        let start = Instant::now();
+        let mut values = vec![None; (params.width * params.height) as usize];
+        let (sender, receiver) = unbounded::<Option<(TileFrame, Vec<Option<f64>>)>>();
+        let result = thread::scope(|s| {
+            let producer = s.spawn(|| {
+                calc_crossbeam_tiles(params, sender, |tile| {
+                    // the tiles that are left are skipped once the render is cancelled
+                    self.shared.is_current(self.id).then(|| {
+                        let tile_values: Vec<Option<f64>> = (tile.y_from()..tile.y_to())
+                            .flat_map(|y| {
+                                (tile.x_from()..tile.x_to()).map(move |x| {
+                                    calc_fractal_iterations(x as u32, y as u32, params)
+                                })
+                            })
+                            .collect();
+                        let frame =
+                            TileFrame::from_values(tile, &tile_values, self.encoding, params);
+                        (frame, tile_values)
+                    })
+                })
+            });
+
+            let mut tiles_done = 0;
+            for (frame, tile_values) in receiver.iter().flatten() {
+                for (i, value) in tile_values.into_iter().enumerate() {
+                    let x = frame.x + i as u32 % frame.width;
+                    let y = frame.y + i as u32 / frame.width;
+                    values[(y * params.width + x) as usize] = value;
+                }
+                tiles_done += 1;
+                self.send_tile(&frame, tiles_done);
+            }
+            producer
+                .join()
+                .unwrap_or_else(|_| Err(FractalError::Render("a tile thread panicked".to_string())))
+        });
+        info!(
+            "render {} calculated in {} ms",
+            self.id,
+            start.elapsed().as_millis()
+        );
+        result?;
+        if !self.shared.is_current(self.id) {
+            return Ok(());
+        }
+
+        let iterations = IterationBuffer {
+            width: params.width,
+            height: params.height,
+            coloring: params.coloring,
+            values,
+        };
+        self.save(req, params, Arc::new(iterations))
+    }
+
+    fn color_tiles(
+        &self,
+        req: &FractalRequest,
+        params: &RenderParams,
+        iterations: Arc<IterationBuffer>,
+    ) -> FractalResult<()> {
+        let all = tiles(params.width, params.height, params.x_tiles, params.y_tiles);
+        for (tiles_done, tile) in all.enumerate() {
+            let values: Vec<Option<f64>> = (tile.y_from()..tile.y_to())
+                .flat_map(|y| {
+                    let row = y * params.width as usize;
+                    iterations.values[row + tile.x_from()..row + tile.x_to()].to_vec()
+                })
+                .collect();
+            let frame = TileFrame::from_values(&tile, &values, self.encoding, params);
+            if !self.send_tile(&frame, tiles_done as u32 + 1) {
+                return Ok(());
+            }
+        }
+        self.save(req, params, iterations)
+    }
+
+    // the outputs of the request and the iterations for the next RECOLOR
+    fn save(
+        &self,
+        req: &FractalRequest,
+        params: &RenderParams,
+        iterations: Arc<IterationBuffer>,
+    ) -> FractalResult<()> {
+        let pixels = iterations.colorize(&params.palette, params.palette_offset);
+        save_outputs(&pixels, params, req)?;
+        *self.shared.finished.lock().unwrap() = Some((req.clone(), iterations));
+        Ok(())
+    }
+
+    // the tile and the PROGRESS event, false if the render is not the current one anymore
+    fn send_tile(&self, frame: &TileFrame, tiles_done: u32) -> bool {
+        let tile = frame.encode(self.compress);
+        let progress = WebSocketEvent::Progress {
+            id: self.id,
+            tiles_done,
+            tiles: self.tiles,
+        };
+        let progress = match serde_json::to_string(&progress) {
+            Ok(json) => json,
+            Err(e) => {
+                error!("can't serialize {:?}: {}", progress, e);
+                return false;
+            }
+        };
+        self.shared.send_for(
+            self.id,
+            vec![Message::binary(tile), Message::text(progress)],
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver};
+    use tokio::time::timeout;
+    use warp::ws::Message;
+
+    use common::fractal_templates::basic;
+    use common::models::{
+        FractalRequest, RecolorRequest, WebSocketCommand, WebSocketEvent, WebSocketRequest,
+    };
+    use common::tile_frame::TileEncoding;
+    use common::validation::RequestLimits;
+    use common::viewport::Viewport;
+
+    use crate::palettes::UploadedPalettes;
+    use crate::session::Session;
+
+    fn session() -> (Session, UnboundedReceiver<Message>) {
+        let (tx, rx) = unbounded_channel();
+        let session = Session::new(tx, RequestLimits::default(), UploadedPalettes::default());
+        (session, rx)
+    }
+
+    fn request(width: u32, height: u32, max_iterations: u32) -> FractalRequest {
+        let (mut req, _, _) = basic(true);
+        req.width = width;
+        req.height = height;
+        req.max_iterations = max_iterations;
+        req.x_tiles = 4;
+        req.y_tiles = 4;
+        req
+    }
+
+    fn command(command: WebSocketCommand) -> WebSocketRequest {
+        WebSocketRequest {
+            command,
+            encoding: TileEncoding::Rgb,
+            compress: false,
+        }
+    }
+
+    // None for a tile
+    fn event(msg: Message) -> Option<WebSocketEvent> {
+        if msg.is_binary() {
+            return None;
+        }
+        Some(serde_json::from_str(msg.to_str().unwrap()).unwrap())
+    }
+
+    async fn next(rx: &mut UnboundedReceiver<Message>) -> Option<WebSocketEvent> {
+        let msg = timeout(Duration::from_secs(30), rx.recv()).await;
+        event(msg.expect("no message in time").expect("the session is gone"))
+    }
+
+    // everything up to and including RENDER_DONE
+    async fn until_done(rx: &mut UnboundedReceiver<Message>) -> Vec<Option<WebSocketEvent>> {
+        let mut events = vec![];
+        loop {
+            let event = next(rx).await;
+            let done = matches!(event, Some(WebSocketEvent::RenderDone { .. }));
+            events.push(event);
+            if done {
+                return events;
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_ping() {
+        let (mut session, mut rx) = session();
+        session.handle(command(WebSocketCommand::PING));
+        assert_eq!(next(&mut rx).await, Some(WebSocketEvent::Pong));
+    }
+
+    #[tokio::test]
+    async fn test_rerender_needs_a_render() {
+        let (mut session, mut rx) = session();
+        let viewport = Viewport::from_request(&request(32, 32, 100));
+        session.handle(command(WebSocketCommand::RERENDER(viewport)));
+        assert!(matches!(
+            next(&mut rx).await,
+            Some(WebSocketEvent::Error { id: None, .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_event_order() {
+        let (mut session, mut rx) = session();
+        session.handle(command(WebSocketCommand::RENDERFRACTAL(request(32, 24, 100))));
+        let events = until_done(&mut rx).await;
+
+        assert_eq!(
+            events[0],
+            Some(WebSocketEvent::RenderStarted {
+                id: 1,
+                width: 32,
+                height: 24,
+                tiles: 16,
+            })
+        );
+        // every tile is followed by its PROGRESS
+        assert_eq!(events.len(), 1 + 2 * 16 + 1);
+        for (i, pair) in events[1..33].chunks(2).enumerate() {
+            assert_eq!(pair[0], None);
+            assert_eq!(
+                pair[1],
+                Some(WebSocketEvent::Progress {
+                    id: 1,
+                    tiles_done: i as u32 + 1,
+                    tiles: 16,
+                })
+            );
+        }
+        assert!(matches!(
+            events[33],
+            Some(WebSocketEvent::RenderDone {
+                id: 1,
+                cancelled: false,
+                ..
+            })
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_cancel() {
+        let (mut session, mut rx) = session();
+        session.handle(command(WebSocketCommand::RENDERFRACTAL(request(
+            256, 256, 1_000_000,
+        ))));
+        session.handle(command(WebSocketCommand::CANCEL));
+        assert!(session.shared.finished.lock().unwrap().is_none());
+
+        // the channel closes once the render has stopped and the session is gone
+        drop(session);
+        let mut events = vec![];
+        while let Some(msg) = timeout(Duration::from_secs(30), rx.recv()).await.unwrap() {
+            events.push(event(msg));
+        }
+        let done = events
+            .iter()
+            .position(|e| matches!(e, Some(WebSocketEvent::RenderDone { .. })))
+            .unwrap();
+        assert!(matches!(
+            events[done],
+            Some(WebSocketEvent::RenderDone {
+                id: 1,
+                cancelled: true,
+                ..
+            })
+        ));
+        assert_eq!(done, events.len() - 1, "{:?}", &events[done..]);
+    }
+
+    #[tokio::test]
+    async fn test_recolor() {
+        let (mut session, mut rx) = session();
+        let recolor = RecolorRequest {
+            palette: None,
+            palette_offset: Some(3.0),
+        };
+        session.handle(command(WebSocketCommand::RECOLOR(recolor.clone())));
+        assert!(matches!(
+            next(&mut rx).await,
+            Some(WebSocketEvent::Error { id: None, .. })
+        ));
+
+        session.handle(command(WebSocketCommand::RENDERFRACTAL(request(32, 24, 100))));
+        until_done(&mut rx).await;
+        let (_, rendered) = session.shared.finished.lock().unwrap().clone().unwrap();
+
+        session.handle(command(WebSocketCommand::RECOLOR(recolor)));
+        let events = until_done(&mut rx).await;
+        assert!(matches!(
+            events[0],
+            Some(WebSocketEvent::RenderStarted { id: 2, .. })
+        ));
+        assert_eq!(events.iter().filter(|e| e.is_none()).count(), 16);
+
+        // the iterations are not calculated again
+        let (req, recolored) = session.shared.finished.lock().unwrap().clone().unwrap();
+        assert_eq!(req.palette_offset, 3.0);
+        assert!(Arc::ptr_eq(&rendered, &recolored));
+    }
+}
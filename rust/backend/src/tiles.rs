@@ -0,0 +1,95 @@
+use std::time::Instant;
+
+use log::info;
+use serde_derive::Deserialize;
+use warp::http::header::CONTENT_TYPE;
+use warp::reply::with_header;
+use warp::{Filter, Reply};
+
+use common::coloring::Coloring;
+use common::error::FractalError;
+use common::models::PaletteSpec;
+use common::tile_pyramid::{TileCache, TileCoord, TileRegion, TileSettings};
+use common::validation::RequestLimits;
+
+use crate::error::reject;
+use crate::palettes::{with_uploaded_palettes, UploadedPalettes};
+use crate::server::with_limits;
+use crate::utils;
+
+#[derive(Deserialize, Debug)]
+struct TileQuery {
+    palette: Option<String>,
+    max_iterations: Option<u32>,
+    coloring: Option<Coloring>,
+    #[serde(default)]
+    palette_offset: f64,
+}
+
+// GET tiles/<z>/<x>/<y>.png?palette=blues.map&max_iterations=2000 for slippy maps like Leaflet.
+// tiles are rendered on the first request and read from the cache afterwards
+pub fn routes(
+    cache: TileCache,
+    limits: RequestLimits,
+    palettes: UploadedPalettes,
+) -> impl Filter<Extract=(impl Reply, ), Error=warp::Rejection> + Clone {
+    warp::path!("tiles" / u32 / u32 / String)
+        .and(warp::get())
+        .and(warp::query::<TileQuery>())
+        .and(warp::any().map(move || cache.clone()))
+        .and(with_limits(limits))
+        .and(with_uploaded_palettes(palettes))
+        .and_then(handle_tile)
+}
+
+async fn handle_tile(
+    z: u32,
+    x: u32,
+    file: String,
+    query: TileQuery,
+    cache: TileCache,
+    limits: RequestLimits,
+    palettes: UploadedPalettes,
+) -> utils::Result<impl Reply> {
+    info!("GET tiles/{}/{}/{}  {:?}", z, x, &file, &query);
+    let start = Instant::now();
+    let y = file
+        .strip_suffix(".png")
+        .and_then(|y| y.parse().ok())
+        .ok_or_else(|| reject(FractalError::InvalidRequest(format!("{} is not a tile", file))))?;
+    let coord = TileCoord::new(z, x, y).map_err(reject)?;
+
+    let settings = tile_settings(query, &limits, &palettes)?;
+    let png =
+        utils::blocking(move || cache.get_or_render(&settings, &TileRegion::default(), coord))
+            .await?;
+
+    info!("tile {}/{}/{} took {} ms", z, x, y, start.elapsed().as_millis());
+    Ok(with_header(png, CONTENT_TYPE, "image/png"))
+}
+
+fn tile_settings(
+    query: TileQuery,
+    limits: &RequestLimits,
+    palettes: &UploadedPalettes,
+) -> utils::Result<TileSettings> {
+    let default = TileSettings::default();
+    let max_iterations = query.max_iterations.unwrap_or(default.max_iterations);
+    if max_iterations == 0 || max_iterations > limits.max_iterations {
+        return Err(reject(FractalError::InvalidRequest(format!(
+            "max_iterations must be between 1 and {}",
+            limits.max_iterations
+        ))));
+    }
+    // an uploaded palette ends up in the hash of the cache directory with its colors
+    let palette = match query.palette {
+        Some(name) => palettes.lookup(&name).map_err(reject)?.into(),
+        None => PaletteSpec::default(),
+    };
+    Ok(TileSettings {
+        max_iterations,
+        palette,
+        coloring: query.coloring.unwrap_or(default.coloring),
+        palette_offset: query.palette_offset,
+    })
+}
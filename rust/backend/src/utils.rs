@@ -1,7 +1,24 @@
 use warp::Rejection;
 
+use common::error::{FractalError, FractalResult};
+
+use crate::error::reject;
+
 pub type Result<T> = std::result::Result<T, Rejection>;
 
+// renders and encoders run on the blocking pool, on the async threads they would stall every
+// other request and the websocket sessions
+pub async fn blocking<T, F>(f: F) -> Result<T>
+where
+    F: FnOnce() -> FractalResult<T> + Send + 'static,
+    T: Send + 'static,
+{
+    tokio::task::spawn_blocking(f)
+        .await
+        .map_err(|e| reject(FractalError::Render(e.to_string())))?
+        .map_err(reject)
+}
+
 pub fn cors() -> warp::cors::Builder {
     warp::cors()
         .allow_any_origin()
@@ -10,6 +27,10 @@ pub fn cors() -> warp::cors::Builder {
             "x-provided-by",
             "x-initiated-by",
             "x-processed-by",
+            "x-fractal-engine",
+            "x-fractal-width",
+            "x-fractal-height",
+            "x-fractal-duration-ms",
         ])
         .allow_headers(vec![
             "User-Agent",
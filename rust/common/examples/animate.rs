@@ -0,0 +1,53 @@
+use std::env;
+use std::fs;
+use std::path::Path;
+
+use common::animation::{render_animation, Animation, AnimationOptions};
+use common::render_engine::engine;
+use common::validation::RequestLimits;
+
+// usage: cargo run --release --example animate -- animation.json [output dir] [engine] [shard]
+// animation.json is an Animation: the base request, fps and the keyframes. started again it only
+// renders the missing frames, shard 1/4 renders every 4th frame so 4 processes can share the work
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    let path = args
+        .get(1)
+        .expect("usage: animate animation.json [output dir] [engine] [shard]");
+    let dir = args
+        .get(2)
+        .map(|s| s.as_str())
+        .unwrap_or("images/animation");
+    let engine_name = args.get(3).map(|s| s.as_str()).unwrap_or("rayon");
+    let options = AnimationOptions {
+        shard: args
+            .get(4)
+            .map(|s| s.parse().expect("shard should be <index>/<count>")),
+        // a local render, only a single frame has the limits of the server
+        limits: RequestLimits {
+            max_frames: u32::MAX,
+            max_animation_pixels: u64::MAX,
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+
+    let json = fs::read_to_string(path).expect("the animation file should be readable");
+    let animation: Animation = serde_json::from_str(&json).expect("the animation should be valid");
+    println!(
+        "{} keyframes, {} frames",
+        animation.keyframes.len(),
+        animation.frame_count()
+    );
+
+    let engine = engine(engine_name).expect("engine should exist");
+    let progress = render_animation(&animation, engine.as_ref(), Path::new(dir), &options)
+        .expect("rendering should work");
+    println!(
+        "{} of {} frames finished, {} rendered",
+        progress.finished, progress.frames, progress.calculated
+    );
+    if progress.is_complete() {
+        println!("wrote {}/manifest.json", dir);
+    }
+}
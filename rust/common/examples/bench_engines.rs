@@ -0,0 +1,44 @@
+use std::env;
+use std::time::Instant;
+
+use common::fractal_templates::basic;
+use common::palette::resolve_palette;
+use common::render_engine::{engines, RenderParams};
+
+// usage: cargo run --release --example bench_engines -- [iterations] [width] [height] [max_iterations]
+// prints one csv line per run with the duration in ms of every engine
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    let arg = |idx: usize, default: u32| {
+        args.get(idx)
+            .map(|a| a.parse::<u32>().expect("argument should be a number"))
+            .unwrap_or(default)
+    };
+
+    let iterations = arg(1, 5);
+    let (mut req, _, _) = basic(true);
+    req.width = arg(2, 1280);
+    req.height = arg(3, 720);
+    req.max_iterations = arg(4, 10_000);
+
+    let colors = resolve_palette(&req.palette).expect("palette should be available");
+
+    let engines = engines();
+    let names: Vec<&str> = engines.iter().map(|e| e.name()).collect();
+    println!("{}", names.join("; "));
+
+    for _ in 0..iterations {
+        let durations: Vec<String> = engines
+            .iter()
+            .map(|engine| {
+                let params = RenderParams::new(&req, colors.clone());
+                let start = Instant::now();
+                match engine.calc(&params) {
+                    Ok(_) => start.elapsed().as_millis().to_string(),
+                    Err(e) => format!("error: {}", e),
+                }
+            })
+            .collect();
+        println!("{}", durations.join("; "));
+    }
+}
@@ -0,0 +1,73 @@
+use std::env;
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::Path;
+use std::time::Instant;
+
+use common::animated_image::{write_animation, write_png_sequence, AnimationFormat};
+use common::fractal_templates::basic;
+use common::iteration_buffer::IterationBuffer;
+use common::palette::resolve_palette;
+use common::render_engine::RenderParams;
+
+// usage: cargo run --release --example cycle_palette -- [gif|apng|y4m|png] [frames] [step]
+// calculates the basic template once and writes the palette cycling animation to images/cycle
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    let format = args.get(1).map(|s| s.as_str()).unwrap_or("gif");
+    let frames = args
+        .get(2)
+        .map(|s| s.parse().expect("frames should be a number"))
+        .unwrap_or(64);
+    let step = args
+        .get(3)
+        .map(|s| s.parse().expect("step should be a number"))
+        .unwrap_or(1.0);
+
+    let (mut req, _, _) = basic(true);
+    req.width = 640;
+    req.height = 360;
+    let palette = resolve_palette(&req.palette).expect("palette should be available");
+    let params = RenderParams::new(&req, palette);
+
+    let start = Instant::now();
+    let buffer = IterationBuffer::calc(&params);
+    println!("calculation took {} ms", start.elapsed().as_millis());
+
+    let start = Instant::now();
+    let pixels: Vec<_> = buffer
+        .cycle(&params.palette, req.palette_offset, frames, step)
+        .collect();
+    println!(
+        "recoloring {} frames took {} ms",
+        frames,
+        start.elapsed().as_millis()
+    );
+
+    let dir = Path::new("images/cycle");
+    let animation = match format {
+        "png" => {
+            let paths = write_png_sequence(dir, "cycle", req.width, req.height, &pixels)
+                .expect("writing the frames should work");
+            println!("wrote {} frames to {}", paths.len(), dir.display());
+            return;
+        }
+        "apng" => AnimationFormat::Apng,
+        "y4m" => AnimationFormat::Y4m,
+        _ => AnimationFormat::Gif,
+    };
+
+    std::fs::create_dir_all(dir).expect("creating the directory should work");
+    let path = dir.join(format!("cycle.{}", animation.extension()));
+    let file = File::create(&path).expect("creating the file should work");
+    write_animation(
+        animation,
+        BufWriter::new(file),
+        req.width,
+        req.height,
+        &pixels,
+        40,
+    )
+    .expect("writing the animation should work");
+    println!("wrote {}", path.display());
+}
@@ -0,0 +1,47 @@
+use std::env;
+use std::fs;
+use std::path::Path;
+
+use common::coloring::Palette;
+use common::palette::extract::{extract_palette, PaletteOrder};
+use common::palette::{palette_dir, write_palette_file, PaletteFormat};
+
+// usage: cargo run --release --example extract_palette -- image.png [colors] [luminance|hue] [out.map]
+// writes the palette to the palette directory unless a file is given
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    let image = args
+        .get(1)
+        .expect("usage: extract_palette image.png [colors] [luminance|hue] [out.map]");
+    let colors = args
+        .get(2)
+        .map(|s| s.parse().expect("colors should be a number"))
+        .unwrap_or(8);
+    let order = match args.get(3).map(|s| s.as_str()) {
+        Some("hue") => PaletteOrder::Hue,
+        _ => PaletteOrder::Luminance,
+    };
+
+    let data = fs::read(image).expect("reading the image should work");
+    // a local file, so there is no limit on its size
+    let gradient =
+        extract_palette(image, &data, colors, order, u64::MAX).expect("extracting should work");
+    for stop in &gradient.stops {
+        println!("{:.3}  {}", stop.position, stop.color);
+    }
+
+    let out = match args.get(4) {
+        Some(out) => Path::new(out).to_path_buf(),
+        None => {
+            let stem = Path::new(image)
+                .file_stem()
+                .unwrap()
+                .to_string_lossy()
+                .to_string();
+            palette_dir().join(format!("{}.map", stem))
+        }
+    };
+    let map = write_palette_file(PaletteFormat::Map, "", &Palette::Gradient(gradient));
+    fs::write(&out, map).expect("writing the palette should work");
+    println!("wrote {}", out.display());
+}
@@ -0,0 +1,32 @@
+use std::env;
+use std::path::Path;
+use std::time::Instant;
+
+use common::tile_pyramid::{generate_pyramid, PyramidLayout, TileRegion, TileSettings};
+
+// usage: cargo run --release --example generate_pyramid -- [max zoom] [Xyz|DeepZoom]
+// writes the tiles to images/pyramid for a static slippy map or OpenSeadragon
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    let max_zoom: u32 = args
+        .get(1)
+        .map(|s| s.parse().expect("max zoom should be a number"))
+        .unwrap_or(4);
+    let layout = match args.get(2).map(|s| s.as_str()) {
+        None | Some("Xyz") => PyramidLayout::Xyz,
+        Some("DeepZoom") => PyramidLayout::DeepZoom,
+        Some(layout) => panic!("unknown layout {}", layout),
+    };
+
+    let start = Instant::now();
+    let files = generate_pyramid(
+        &TileSettings::default(),
+        &TileRegion::default(),
+        max_zoom,
+        Path::new("images/pyramid"),
+        layout,
+        "mandelbrot",
+    )
+    .expect("generating the pyramid should work");
+    println!("wrote {} files in {} s", files, start.elapsed().as_secs());
+}
@@ -0,0 +1,62 @@
+use std::env;
+use std::fs;
+use std::path::Path;
+use std::time::Instant;
+
+use common::fractal_templates;
+use common::strip_render::{render_strips, StripOptions};
+
+// usage: cargo run --release --example render_strips -- [template] [width] [strip height] [max strips]
+// renders e.g. 32768x24576 without holding the image in memory. stopping it with ctrl-c and
+// starting it again with the same arguments continues with the missing strips
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    let template = args.get(1).map(|s| s.as_str()).unwrap_or("seahorse_valley");
+    let width: u32 = args
+        .get(2)
+        .map(|s| s.parse().expect("width should be a number"))
+        .unwrap_or(32768);
+    let strip_height = args
+        .get(3)
+        .map(|s| s.parse().expect("strip height should be a number"))
+        .unwrap_or(256);
+    let max_strips = args
+        .get(4)
+        .map(|s| s.parse().expect("max strips should be a number"));
+
+    let (mut req, _, _) = match template {
+        "basic" => fractal_templates::basic(false),
+        "flower" => fractal_templates::flower(false),
+        "tendrils" => fractal_templates::tendrils(false),
+        "julia_island" => fractal_templates::julia_island(false),
+        "seahorse_valley" => fractal_templates::seahorse_valley(false),
+        "starfish" => fractal_templates::starfish(false),
+        "sun" => fractal_templates::sun(false),
+        "tree" => fractal_templates::tree(false),
+        _ => panic!("unknown template {}", template),
+    };
+    // keep the aspect ratio of the template
+    req.height = (width as f64 * req.height as f64 / req.width as f64).round() as u32;
+    req.width = width;
+
+    let dir = Path::new("images/strips");
+    fs::create_dir_all(dir).expect("creating the directory should work");
+    let output = dir.join(format!("{}_{}x{}.png", req.name, req.width, req.height));
+
+    let start = Instant::now();
+    let options = StripOptions {
+        strip_height,
+        max_strips,
+    };
+    let progress = render_strips(&req, &output, &options).expect("rendering should work");
+    println!(
+        "{} of {} strips finished, {} calculated in {} s",
+        progress.finished,
+        progress.strips,
+        progress.calculated,
+        start.elapsed().as_secs()
+    );
+    if progress.is_complete() {
+        println!("wrote {}", output.display());
+    }
+}
@@ -0,0 +1,37 @@
+use std::env;
+use std::path::Path;
+
+use common::output::OutputFormat;
+use common::png_metadata::read_request_file;
+use common::render_engine::engine;
+use common::viewport::Viewport;
+
+// usage: cargo run --release --example rerender -- images/<name>/<file>.png [zoom factor] [engine]
+// renders a saved image again from the request stored in its metadata, optionally zoomed into the center
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    let path = args
+        .get(1)
+        .expect("usage: rerender image.png [zoom factor] [engine]");
+    let factor: f64 = args
+        .get(2)
+        .map(|s| s.parse().expect("zoom factor should be a number"))
+        .unwrap_or(1.0);
+    let engine_name = args.get(3).map(|s| s.as_str()).unwrap_or("rayon");
+
+    let req = read_request_file(Path::new(path)).expect("the PNG should contain a request");
+    println!("{:?}", &req);
+
+    let viewport = Viewport::from_request(&req);
+    let mut req = viewport.zoom_at(&viewport.center, factor).to_request(&req);
+    if req.output.is_empty() {
+        req.output = vec![OutputFormat::Png];
+    }
+
+    let engine = engine(engine_name).expect("engine should exist");
+    let result = engine.render(&req).expect("rendering should work");
+    println!(
+        "rendered {}x{} in {} ms",
+        result.fractal.width, result.fractal.height, result.duration_ms
+    );
+}
@@ -0,0 +1,309 @@
+use std::fs::create_dir_all;
+use std::io;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use image::codecs::gif::{GifEncoder, Repeat};
+use image::{Delay, Frame, RgbImage, RgbaImage};
+use serde_derive::{Deserialize, Serialize};
+
+use crate::color::Color;
+use crate::error::{FractalError, FractalResult};
+
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, Default, PartialEq)]
+pub enum AnimationFormat {
+    #[default]
+    Gif,
+    Apng,
+    // raw video for other encoders, e.g. ffmpeg -i zoom.y4m zoom.mp4
+    Y4m,
+}
+
+impl AnimationFormat {
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            AnimationFormat::Gif => "image/gif",
+            AnimationFormat::Apng => "image/apng",
+            AnimationFormat::Y4m => "video/x-yuv4mpeg",
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            AnimationFormat::Gif => "gif",
+            AnimationFormat::Apng => "png",
+            AnimationFormat::Y4m => "y4m",
+        }
+    }
+}
+
+// frames have width * height pixels, the animation loops forever
+pub fn write_animation<W: Write>(
+    format: AnimationFormat,
+    w: W,
+    width: u32,
+    height: u32,
+    frames: &[Vec<Color>],
+    delay_ms: u32,
+) -> FractalResult<()> {
+    let mut writer = AnimationWriter::new(format, w, width, height, frames.len() as u32, delay_ms)?;
+    for pixels in frames {
+        writer.write_frame(pixels)?;
+    }
+    writer.finish()
+}
+
+enum Encoder<W: Write> {
+    Gif(GifEncoder<W>),
+    Apng(png::Writer<W>),
+    Y4m(W),
+}
+
+// writes one frame at a time, so long animations never have to be in memory at once
+pub struct AnimationWriter<W: Write> {
+    width: u32,
+    height: u32,
+    delay_ms: u32,
+    encoder: Encoder<W>,
+}
+
+impl<W: Write> AnimationWriter<W> {
+    // APNG needs the number of frames up front, the other formats ignore frame_count
+    pub fn new(
+        format: AnimationFormat,
+        mut w: W,
+        width: u32,
+        height: u32,
+        frame_count: u32,
+        delay_ms: u32,
+    ) -> FractalResult<AnimationWriter<W>> {
+        let encoder = match format {
+            AnimationFormat::Gif => {
+                let mut encoder = GifEncoder::new_with_speed(w, 10);
+                encoder.set_repeat(Repeat::Infinite)?;
+                Encoder::Gif(encoder)
+            }
+            AnimationFormat::Apng => {
+                let mut encoder = png::Encoder::new(w, width, height);
+                encoder.set_color(png::ColorType::Rgb);
+                encoder.set_depth(png::BitDepth::Eight);
+                encoder.set_animated(frame_count, 0)?;
+                encoder.set_frame_delay(delay_ms.min(u16::MAX as u32) as u16, 1000)?;
+                Encoder::Apng(encoder.write_header()?)
+            }
+            AnimationFormat::Y4m => {
+                write_y4m_header(&mut w, width, height, delay_ms)
+                    .map_err(|e| FractalError::Encoding(e.to_string()))?;
+                Encoder::Y4m(w)
+            }
+        };
+        Ok(AnimationWriter {
+            width,
+            height,
+            delay_ms,
+            encoder,
+        })
+    }
+
+    pub fn write_frame(&mut self, pixels: &[Color]) -> FractalResult<()> {
+        let (width, height) = (self.width, self.height);
+        if pixels.len() != width as usize * height as usize {
+            return Err(FractalError::Render(format!(
+                "frame does not have {}x{} pixels",
+                width, height
+            )));
+        }
+        match &mut self.encoder {
+            Encoder::Gif(encoder) => {
+                let rgba: Vec<u8> = pixels.iter().flat_map(|c| [c.r, c.g, c.b, 255]).collect();
+                let image = RgbaImage::from_raw(width, height, rgba).ok_or_else(|| {
+                    FractalError::Render(format!("frame does not have {}x{} pixels", width, height))
+                })?;
+                let delay = Delay::from_numer_denom_ms(self.delay_ms, 1);
+                encoder.encode_frame(Frame::from_parts(image, 0, 0, delay))?;
+            }
+            Encoder::Apng(writer) => {
+                let rgb: Vec<u8> = pixels.iter().flat_map(|c| [c.r, c.g, c.b]).collect();
+                writer.write_image_data(&rgb)?;
+            }
+            Encoder::Y4m(w) => write_y4m_frame(w, width, height, pixels)
+                .map_err(|e| FractalError::Encoding(e.to_string()))?,
+        }
+        Ok(())
+    }
+
+    pub fn finish(self) -> FractalResult<()> {
+        match self.encoder {
+            // the trailer is written when the encoder is dropped
+            Encoder::Gif(encoder) => drop(encoder),
+            Encoder::Apng(writer) => writer.finish()?,
+            Encoder::Y4m(mut w) => w
+                .flush()
+                .map_err(|e| FractalError::Encoding(e.to_string()))?,
+        }
+        Ok(())
+    }
+}
+
+// uncompressed 4:2:0 YUV with BT.601 limited range, ffmpeg, x264 and most other encoders read it
+fn write_y4m_header<W: Write>(w: &mut W, width: u32, height: u32, delay_ms: u32) -> io::Result<()> {
+    let delay_ms = delay_ms.max(1);
+    let divisor = gcd(1000, delay_ms);
+    writeln!(
+        w,
+        "YUV4MPEG2 W{} H{} F{}:{} Ip A1:1 C420jpeg XCOLORRANGE=LIMITED",
+        width,
+        height,
+        1000 / divisor,
+        delay_ms / divisor
+    )
+}
+
+fn write_y4m_frame<W: Write>(
+    w: &mut W,
+    width: u32,
+    height: u32,
+    pixels: &[Color],
+) -> io::Result<()> {
+    let (width, height) = (width as usize, height as usize);
+    let (chroma_width, chroma_height) = (width.div_ceil(2), height.div_ceil(2));
+    let yuv: Vec<(f64, f64, f64)> = pixels.iter().map(rgb_to_ycbcr).collect();
+
+    let mut frame = Vec::with_capacity(width * height + 2 * chroma_width * chroma_height);
+    frame.extend(yuv.iter().map(|p| p.0.round() as u8));
+    // every chroma sample is the average of a 2x2 block
+    for plane in [1, 2] {
+        for cy in 0..chroma_height {
+            for cx in 0..chroma_width {
+                let mut sum = 0.0;
+                let mut count = 0.0;
+                for y in 2 * cy..(2 * cy + 2).min(height) {
+                    for x in 2 * cx..(2 * cx + 2).min(width) {
+                        let p = yuv[y * width + x];
+                        sum += if plane == 1 { p.1 } else { p.2 };
+                        count += 1.0;
+                    }
+                }
+                frame.push((sum / count).round() as u8);
+            }
+        }
+    }
+    w.write_all(b"FRAME\n")?;
+    w.write_all(&frame)
+}
+
+fn rgb_to_ycbcr(c: &Color) -> (f64, f64, f64) {
+    let (r, g, b) = (c.r as f64 / 255.0, c.g as f64 / 255.0, c.b as f64 / 255.0);
+    (
+        16.0 + 65.481 * r + 128.553 * g + 24.966 * b,
+        128.0 - 37.797 * r - 74.203 * g + 112.0 * b,
+        128.0 + 112.0 * r - 93.786 * g - 18.214 * b,
+    )
+}
+
+fn gcd(a: u32, b: u32) -> u32 {
+    if b == 0 {
+        a
+    } else {
+        gcd(b, a % b)
+    }
+}
+
+// writes <dir>/<name>_00000.png, <dir>/<name>_00001.png, ...
+pub fn write_png_sequence(
+    dir: &Path,
+    name: &str,
+    width: u32,
+    height: u32,
+    frames: &[Vec<Color>],
+) -> FractalResult<Vec<PathBuf>> {
+    create_dir_all(dir).map_err(|e| FractalError::io(&dir.display().to_string(), e))?;
+
+    let mut paths = vec![];
+    for (idx, pixels) in frames.iter().enumerate() {
+        let path = dir.join(format!("{}_{:05}.png", name, idx));
+        let rgb: Vec<u8> = pixels.iter().flat_map(|c| [c.r, c.g, c.b]).collect();
+        let image = RgbImage::from_raw(width, height, rgb).ok_or_else(|| {
+            FractalError::Render(format!("frame does not have {}x{} pixels", width, height))
+        })?;
+        image.save(&path)?;
+        paths.push(path);
+    }
+    Ok(paths)
+}
+
+#[cfg(test)]
+mod tests {
+    use image::codecs::gif::GifDecoder;
+    use image::AnimationDecoder;
+
+    use crate::animated_image::{write_animation, AnimationFormat, AnimationWriter};
+    use crate::color::{Color, BLUE, RED};
+
+    fn frames() -> Vec<Vec<Color>> {
+        vec![vec![RED; 6], vec![BLUE; 6], vec![RED; 6]]
+    }
+
+    #[test]
+    fn test_gif() {
+        let mut gif = vec![];
+        write_animation(AnimationFormat::Gif, &mut gif, 3, 2, &frames(), 40).unwrap();
+
+        let decoder = GifDecoder::new(gif.as_slice()).unwrap();
+        let frames = decoder.into_frames().collect_frames().unwrap();
+        assert_eq!(frames.len(), 3);
+        assert_eq!(frames[1].buffer().get_pixel(2, 1).0, [0, 0, 255, 255]);
+    }
+
+    #[test]
+    fn test_apng() {
+        let mut apng = vec![];
+        write_animation(AnimationFormat::Apng, &mut apng, 3, 2, &frames(), 40).unwrap();
+
+        let decoder = png::Decoder::new(apng.as_slice());
+        let reader = decoder.read_info().unwrap();
+        let info = reader.info();
+        assert_eq!((info.width, info.height), (3, 2));
+        assert_eq!(info.animation_control().unwrap().num_frames, 3);
+    }
+
+    #[test]
+    fn test_y4m() {
+        let mut y4m = vec![];
+        write_animation(AnimationFormat::Y4m, &mut y4m, 3, 2, &frames(), 40).unwrap();
+
+        let header = b"YUV4MPEG2 W3 H2 F25:1 Ip A1:1 C420jpeg XCOLORRANGE=LIMITED\n";
+        assert!(y4m.starts_with(header));
+        // 6 luma and 2 * 2 chroma samples per frame
+        let frame_size = 6 + 6 + 4;
+        assert_eq!(y4m.len(), header.len() + 3 * frame_size);
+
+        let frame = &y4m[header.len() + frame_size..][..frame_size];
+        assert!(frame.starts_with(b"FRAME\n"));
+        // blue: Y 41, Cb 240, Cr 110
+        assert_eq!(&frame[6..12], &[41; 6]);
+        assert_eq!(&frame[12..], &[240, 240, 110, 110]);
+    }
+
+    #[test]
+    fn test_streamed_frames() {
+        let mut apng = vec![];
+        let mut writer =
+            AnimationWriter::new(AnimationFormat::Apng, &mut apng, 3, 2, 2, 40).unwrap();
+        writer.write_frame(&[RED; 6]).unwrap();
+        assert!(writer.write_frame(&[RED; 5]).is_err());
+        writer.write_frame(&[BLUE; 6]).unwrap();
+        writer.finish().unwrap();
+
+        let decoder = png::Decoder::new(apng.as_slice());
+        let reader = decoder.read_info().unwrap();
+        assert_eq!(reader.info().animation_control().unwrap().num_frames, 2);
+    }
+
+    #[test]
+    fn test_wrong_frame_size() {
+        let mut gif = vec![];
+        let res = write_animation(AnimationFormat::Gif, &mut gif, 4, 4, &frames(), 40);
+        assert!(res.is_err());
+    }
+}
@@ -0,0 +1,1035 @@
+use std::fs;
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Instant;
+
+use log::info;
+use serde_derive::{Deserialize, Serialize};
+
+use crate::animated_image::{AnimationFormat, AnimationWriter};
+use crate::color::Color;
+use crate::coloring::Palette;
+use crate::complex::ComplexNumber;
+use crate::error::{FractalError, FractalResult};
+use crate::exp_map::{ExpMap, ExpMapInfo};
+use crate::iteration_buffer::IterationBuffer;
+use crate::models::FractalRequest;
+use crate::output::{encode_output, OutputFormat};
+use crate::palette::resolve_palette;
+use crate::render_engine::{RenderEngine, RenderParams};
+use crate::strip_render::StripOptions;
+use crate::utils::{stable_hash, write_file_atomic};
+use crate::validation::{
+    validate_animation_pixels, validate_finite, validate_positive, FieldError, RequestLimits,
+};
+use crate::viewport::Viewport;
+
+pub const MANIFEST_FILE: &str = "manifest.json";
+
+// how the time between two keyframes is mapped to the progress of the movement
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, Default, PartialEq)]
+pub enum Easing {
+    #[default]
+    Linear,
+    // starts slow
+    EaseIn,
+    // ends slow
+    EaseOut,
+    // starts and ends slow, smoothstep
+    EaseInOut,
+    // jumps to the next keyframe at its time
+    Hold,
+}
+
+impl Easing {
+    pub fn apply(&self, t: f64) -> f64 {
+        let t = t.clamp(0.0, 1.0);
+        match self {
+            Easing::Linear => t,
+            Easing::EaseIn => t * t,
+            Easing::EaseOut => t * (2.0 - t),
+            Easing::EaseInOut => t * t * (3.0 - 2.0 * t),
+            Easing::Hold => {
+                if t < 1.0 {
+                    0.0
+                } else {
+                    1.0
+                }
+            }
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct Keyframe {
+    // seconds from the start of the animation
+    pub time: f64,
+    pub center: ComplexNumber,
+    pub zoom: f64,
+    // degrees counterclockwise
+    #[serde(default)]
+    pub rotation: f64,
+    pub max_iterations: u32,
+    #[serde(default)]
+    pub palette_offset: f64,
+    // the easing of the way to the next keyframe
+    #[serde(default)]
+    pub easing: Easing,
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, Default, PartialEq)]
+pub enum AnimationMode {
+    // every frame is calculated by the engine
+    #[default]
+    Frames,
+    // one exponential map is calculated and every frame is resampled from it. only for zooms into
+    // one point, the keyframes may only change the zoom and the rotation
+    ExpMap,
+}
+
+// everything the keyframes don't change, e.g. size, palette and coloring, comes from request
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct Animation {
+    pub request: FractalRequest,
+    pub fps: f64,
+    pub keyframes: Vec<Keyframe>,
+    #[serde(default)]
+    pub format: OutputFormat,
+    #[serde(default)]
+    pub mode: AnimationMode,
+    // written from the frames once all of them are finished
+    #[serde(default)]
+    pub videos: Vec<AnimationFormat>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct AnimationFrame {
+    pub index: u32,
+    pub time: f64,
+    pub center: ComplexNumber,
+    pub zoom: f64,
+    pub rotation: f64,
+    pub max_iterations: u32,
+    pub palette_offset: f64,
+}
+
+impl AnimationFrame {
+    pub fn to_request(&self, base: &FractalRequest) -> FractalRequest {
+        FractalRequest {
+            center: self.center.clone(),
+            zoom: self.zoom,
+            rotation: self.rotation,
+            max_iterations: self.max_iterations,
+            palette_offset: self.palette_offset,
+            output: vec![],
+            ..base.clone()
+        }
+    }
+}
+
+impl Animation {
+    // the request is the base of every frame, so it has to pass the limits of a single image
+    pub fn validate(&self, limits: &RequestLimits) -> FractalResult<()> {
+        let mut errors = match self.request.validate(limits) {
+            Ok(()) => vec![],
+            Err(errors) => errors,
+        };
+        if let Some(k) = self
+            .keyframes
+            .iter()
+            .find(|k| k.max_iterations == 0 || k.max_iterations > limits.max_iterations)
+        {
+            errors.push(FieldError::new(
+                "keyframes",
+                format!(
+                    "max_iterations must be between 1 and {}, got {}",
+                    limits.max_iterations, k.max_iterations
+                ),
+            ));
+        }
+        // the keyframes replace these values of the validated request
+        for (idx, k) in self.keyframes.iter().enumerate() {
+            let field = |name: &str| format!("keyframes[{}].{}", idx, name);
+            validate_finite(&mut errors, &field("time"), k.time);
+            validate_finite(&mut errors, &field("center.a"), k.center.a);
+            validate_finite(&mut errors, &field("center.b"), k.center.b);
+            validate_finite(&mut errors, &field("rotation"), k.rotation);
+            validate_finite(&mut errors, &field("palette_offset"), k.palette_offset);
+            validate_positive(&mut errors, &field("zoom"), k.zoom);
+        }
+        let times_are_valid = self.keyframes.iter().all(|k| k.time.is_finite());
+        if self.fps.is_finite() && self.fps > 0.0 && !self.keyframes.is_empty() && times_are_valid {
+            let frames = self.frame_count();
+            if frames > limits.max_frames {
+                errors.push(FieldError::new(
+                    "fps",
+                    format!(
+                        "the animation has {} frames, but at most {} are allowed",
+                        frames, limits.max_frames
+                    ),
+                ));
+            }
+            validate_animation_pixels(&mut errors, &self.request, frames, limits);
+        }
+        if !errors.is_empty() {
+            return Err(FractalError::Validation(errors));
+        }
+
+        if !self.fps.is_finite() || self.fps <= 0.0 {
+            return Err(FractalError::InvalidRequest(
+                "fps must be bigger than 0".to_string(),
+            ));
+        }
+        if self.keyframes.is_empty() {
+            return Err(FractalError::InvalidRequest(
+                "an animation needs at least one keyframe".to_string(),
+            ));
+        }
+        if self.keyframes[0].time != 0.0 {
+            return Err(FractalError::InvalidRequest(
+                "the first keyframe must be at time 0".to_string(),
+            ));
+        }
+        if self.keyframes.windows(2).any(|k| k[1].time <= k[0].time) {
+            return Err(FractalError::InvalidRequest(
+                "the times of the keyframes must increase".to_string(),
+            ));
+        }
+        if !self.videos.is_empty() && self.format == OutputFormat::Pfm {
+            return Err(FractalError::InvalidRequest(
+                "videos can't be made from Pfm frames".to_string(),
+            ));
+        }
+        if self.mode == AnimationMode::ExpMap {
+            let first = &self.keyframes[0];
+            let only_zoom = self.keyframes.iter().all(|k| {
+                k.center == first.center
+                    && k.max_iterations == first.max_iterations
+                    && k.palette_offset == first.palette_offset
+            });
+            if !only_zoom {
+                return Err(FractalError::InvalidRequest(
+                    "an exp map animation may only change the zoom and the rotation".to_string(),
+                ));
+            }
+            if self.format.needs_iterations() {
+                return Err(FractalError::InvalidRequest(format!(
+                    "an exp map animation can't write {:?} frames",
+                    self.format
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    // covers the zoom range of all keyframes
+    pub fn exp_map_info(&self) -> ExpMapInfo {
+        let first = &self.keyframes[0];
+        let zooms = self.keyframes.iter().map(|k| k.zoom);
+        let min_zoom = zooms.clone().fold(f64::MAX, f64::min);
+        let max_zoom = zooms.fold(f64::MIN, f64::max);
+        let req = FractalRequest {
+            center: first.center.clone(),
+            ..self.request.clone()
+        };
+        ExpMapInfo::for_zoom(&req, min_zoom, max_zoom)
+    }
+
+    pub fn duration(&self) -> f64 {
+        self.keyframes.last().map(|k| k.time).unwrap_or(0.0)
+    }
+
+    // the last keyframe is always a frame of its own
+    pub fn frame_count(&self) -> u32 {
+        (self.duration() * self.fps + 1e-9).floor() as u32 + 1
+    }
+
+    pub fn frame(&self, index: u32) -> AnimationFrame {
+        let time = index as f64 / self.fps;
+        let next = self
+            .keyframes
+            .iter()
+            .position(|k| k.time > time)
+            .unwrap_or(self.keyframes.len());
+        if next == 0 || next == self.keyframes.len() {
+            let k = &self.keyframes[next.saturating_sub(1)];
+            return interpolate(index, time, k, k, 0.0);
+        }
+
+        let (from, to) = (&self.keyframes[next - 1], &self.keyframes[next]);
+        let t = from
+            .easing
+            .apply((time - from.time) / (to.time - from.time));
+        interpolate(index, time, from, to, t)
+    }
+
+    pub fn frames(&self) -> Vec<AnimationFrame> {
+        (0..self.frame_count()).map(|idx| self.frame(idx)).collect()
+    }
+}
+
+// the zoom changes by the same factor in every frame. the center moves linearly with the width of
+// the view, so the movement is a Viewport::zoom_at around one fixed point and the next center
+// moves straight to the middle of the screen instead of sliding off it while zooming in
+fn interpolate(index: u32, time: f64, from: &Keyframe, to: &Keyframe, t: f64) -> AnimationFrame {
+    let zoom = (from.zoom.ln() + (to.zoom.ln() - from.zoom.ln()) * t).exp();
+    let (w0, w1, w) = (1.0 / from.zoom, 1.0 / to.zoom, 1.0 / zoom);
+    let s = if (w0 - w1).abs() > f64::EPSILON * w0 {
+        (w0 - w) / (w0 - w1)
+    } else {
+        t
+    };
+    let lerp = |a: f64, b: f64, t: f64| a + (b - a) * t;
+
+    AnimationFrame {
+        index,
+        time,
+        center: ComplexNumber {
+            a: lerp(from.center.a, to.center.a, s),
+            b: lerp(from.center.b, to.center.b, s),
+        },
+        zoom,
+        rotation: lerp(from.rotation, to.rotation, t),
+        max_iterations: lerp(from.max_iterations as f64, to.max_iterations as f64, t).round()
+            as u32,
+        palette_offset: lerp(from.palette_offset, to.palette_offset, t),
+    }
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct FrameRecord {
+    pub frame: AnimationFrame,
+    pub file: String,
+    // stable_hash of the file as hex and its size
+    pub hash: String,
+    pub bytes: u64,
+    pub duration_ms: u128,
+}
+
+// written next to the frames once all of them are finished, enough to render any frame again
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct AnimationManifest {
+    pub animation: Animation,
+    pub frames: Vec<FrameRecord>,
+    #[serde(default)]
+    pub videos: Vec<String>,
+}
+
+// several processes, e.g. on different machines with a shared directory, can render the frames of
+// one animation. shard index of count renders the frames with frame % count == index
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq)]
+pub struct Shard {
+    pub index: u32,
+    pub count: u32,
+}
+
+impl Shard {
+    pub fn contains(&self, frame: u32) -> bool {
+        frame % self.count == self.index
+    }
+}
+
+// "2/4" is the third of four shards
+impl FromStr for Shard {
+    type Err = FractalError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid =
+            || FractalError::InvalidRequest(format!("shard '{}' is not <index>/<count>", s));
+        let (index, count) = s.split_once('/').ok_or_else(invalid)?;
+        let shard = Shard {
+            index: index.trim().parse().map_err(|_| invalid())?,
+            count: count.trim().parse().map_err(|_| invalid())?,
+        };
+        if shard.index >= shard.count {
+            return Err(invalid());
+        }
+        Ok(shard)
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct AnimationOptions {
+    pub shard: Option<Shard>,
+    // render at most this many frames now
+    pub max_frames: Option<u32>,
+    // hash the finished frames instead of only comparing their size
+    pub verify: bool,
+    pub limits: RequestLimits,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct AnimationProgress {
+    pub frames: u32,
+    // of all shards
+    pub finished: u32,
+    // rendered by this call, the others were left over from an earlier one or another shard
+    pub calculated: u32,
+    // once every frame is finished
+    pub manifest: Option<AnimationManifest>,
+}
+
+impl AnimationProgress {
+    pub fn is_complete(&self) -> bool {
+        self.finished == self.frames
+    }
+}
+
+const ANIMATION_FILE: &str = "animation.json";
+
+pub fn frame_file_name(index: u32, format: OutputFormat) -> String {
+    format!("frame_{:06}{}", index, format.suffix())
+}
+
+fn record_path(dir: &Path, index: u32) -> PathBuf {
+    dir.join(format!("frame_{:06}.json", index))
+}
+
+// renders the frames to dir/frame_000000.png ... every frame gets a record in
+// dir/frame_000000.json, frames with a record that matches are skipped when the render is started
+// again. whoever finishes the last frame writes dir/manifest.json
+pub fn render_animation(
+    animation: &Animation,
+    engine: &dyn RenderEngine,
+    dir: &Path,
+    options: &AnimationOptions,
+) -> FractalResult<AnimationProgress> {
+    animation.validate(&options.limits)?;
+    if options.shard.is_some_and(|s| s.index >= s.count) {
+        return Err(FractalError::InvalidRequest(
+            "the shard index must be smaller than the number of shards".to_string(),
+        ));
+    }
+    prepare_animation_dir(dir, animation)?;
+    let palette = resolve_palette(&animation.request.palette)?;
+    let start = Instant::now();
+
+    let frames = animation.frames();
+    let mut progress = AnimationProgress {
+        frames: frames.len() as u32,
+        finished: 0,
+        calculated: 0,
+        manifest: None,
+    };
+    let mut records = vec![];
+    let mut exp_map = None;
+    for frame in frames {
+        if let Some(record) = finished_frame(dir, &frame, options.verify) {
+            progress.finished += 1;
+            records.push(record);
+            continue;
+        }
+        let mine = options.shard.is_none_or(|s| s.contains(frame.index));
+        let limit = options
+            .max_frames
+            .is_some_and(|max| progress.calculated >= max);
+        if !mine || limit {
+            continue;
+        }
+
+        if animation.mode == AnimationMode::ExpMap && exp_map.is_none() {
+            exp_map = Some(exp_map_for(animation, &palette, dir)?);
+        }
+        let record = render_frame(animation, engine, exp_map.as_mut(), &palette, frame, dir)?;
+        info!(
+            "frame {} / {}  zoom {}  took {} ms",
+            record.frame.index + 1,
+            progress.frames,
+            record.frame.zoom,
+            record.duration_ms
+        );
+        progress.finished += 1;
+        progress.calculated += 1;
+        records.push(record);
+    }
+
+    if progress.is_complete() {
+        let videos = write_videos(animation, &records, dir)?;
+        let manifest = AnimationManifest {
+            animation: animation.clone(),
+            frames: records,
+            videos,
+        };
+        let json = serde_json::to_string_pretty(&manifest)?;
+        write_file_atomic(&dir.join(MANIFEST_FILE), json.as_bytes())?;
+        progress.manifest = Some(manifest);
+    }
+    info!(
+        "{} of {} frames finished, {} rendered in {} s",
+        progress.finished,
+        progress.frames,
+        progress.calculated,
+        start.elapsed().as_secs_f64()
+    );
+    Ok(progress)
+}
+
+// dir/animation.gif, .png (APNG) or .y4m. the frames are read back from their files, so the
+// frames of other shards or earlier runs end up in the videos as well
+fn write_videos(
+    animation: &Animation,
+    records: &[FrameRecord],
+    dir: &Path,
+) -> FractalResult<Vec<String>> {
+    if animation.videos.is_empty() {
+        return Ok(vec![]);
+    }
+    let start = Instant::now();
+    let (width, height) = (animation.request.width, animation.request.height);
+    let delay_ms = (1000.0 / animation.fps).round() as u32;
+
+    let mut videos = vec![];
+    let mut writers = vec![];
+    for format in &animation.videos {
+        let file = format!("animation.{}", format.extension());
+        let path = dir.join(format!("{}.tmp", file));
+        let w = BufWriter::new(
+            File::create(&path).map_err(|e| FractalError::io(&path.to_string_lossy(), e))?,
+        );
+        let frame_count = records.len() as u32;
+        writers.push(AnimationWriter::new(
+            *format,
+            w,
+            width,
+            height,
+            frame_count,
+            delay_ms,
+        )?);
+        videos.push(file);
+    }
+
+    for record in records {
+        let path = dir.join(&record.file);
+        let data = fs::read(&path).map_err(|e| FractalError::io(&path.to_string_lossy(), e))?;
+        let pixels: Vec<Color> = image::load_from_memory(&data)?
+            .to_rgb8()
+            .pixels()
+            .map(|p| Color {
+                r: p[0],
+                g: p[1],
+                b: p[2],
+            })
+            .collect();
+        for writer in writers.iter_mut() {
+            writer.write_frame(&pixels)?;
+        }
+    }
+    for writer in writers {
+        writer.finish()?;
+    }
+
+    for file in &videos {
+        let path = dir.join(file);
+        let tmp = dir.join(format!("{}.tmp", file));
+        fs::rename(&tmp, &path).map_err(|e| FractalError::io(&path.to_string_lossy(), e))?;
+    }
+    info!(
+        "videos {:?} took {} ms",
+        videos,
+        start.elapsed().as_millis()
+    );
+    Ok(videos)
+}
+
+// the frames of a different animation must not end up in this one
+fn prepare_animation_dir(dir: &Path, animation: &Animation) -> FractalResult<()> {
+    let path = dir.join(ANIMATION_FILE);
+    let json = serde_json::to_string_pretty(animation)?;
+    match fs::read_to_string(&path) {
+        Ok(existing) if existing == json => Ok(()),
+        Ok(_) => Err(FractalError::InvalidRequest(format!(
+            "{} contains the frames of a different animation",
+            dir.display()
+        ))),
+        Err(_) => write_file_atomic(&path, json.as_bytes()),
+    }
+}
+
+fn finished_frame(dir: &Path, frame: &AnimationFrame, verify: bool) -> Option<FrameRecord> {
+    let json = fs::read_to_string(record_path(dir, frame.index)).ok()?;
+    let record: FrameRecord = serde_json::from_str(&json).ok()?;
+    if record.frame != *frame {
+        return None;
+    }
+    let path = dir.join(&record.file);
+    let finished = if verify {
+        fs::read(path).is_ok_and(|data| format!("{:016x}", stable_hash(&data)) == record.hash)
+    } else {
+        fs::metadata(path).is_ok_and(|m| m.len() == record.bytes)
+    };
+    finished.then_some(record)
+}
+
+const EXP_MAP_NAME: &str = "exp_map";
+
+// the exp map on disk and the band of it in memory. a band covers the frames around the current
+// one, while zooming the next band is only read every few hundred frames
+struct ExpMapBands {
+    dir: PathBuf,
+    info: ExpMapInfo,
+    band: Option<ExpMap>,
+}
+
+impl ExpMapBands {
+    fn band_for(&mut self, viewport: &Viewport) -> FractalResult<&ExpMap> {
+        let rows = self.info.rows_for(viewport);
+        if !self.band.as_ref().is_some_and(|band| band.contains(&rows)) {
+            let margin = rows.len() as u32 / 2;
+            let band = rows.start.saturating_sub(margin)..(rows.end + margin).min(self.info.height);
+            let map = ExpMap::load(&self.dir, EXP_MAP_NAME, &self.info, band)?
+                .ok_or_else(|| FractalError::Render("the exp map is missing".to_string()))?;
+            self.band = Some(map);
+        }
+        Ok(self.band.as_ref().unwrap())
+    }
+}
+
+// every shard calculates the map itself unless one of them already saved it. an interrupted map
+// continues with the missing strips
+fn exp_map_for(animation: &Animation, palette: &Palette, dir: &Path) -> FractalResult<ExpMapBands> {
+    let info = animation.exp_map_info();
+    if !ExpMap::is_rendered(dir, EXP_MAP_NAME, &info) {
+        let frame_pixels = animation.frame_count() as u64
+            * animation.request.width as u64
+            * animation.request.height as u64;
+        info!(
+            "exp map {}x{} has {} pixels, the frames have {}",
+            info.width,
+            info.height,
+            info.pixels(),
+            frame_pixels
+        );
+
+        let req = animation.frame(0).to_request(&animation.request);
+        let params = RenderParams::new(&req, palette.clone());
+        ExpMap::render(
+            &info,
+            &params,
+            &req,
+            dir,
+            EXP_MAP_NAME,
+            &StripOptions::default(),
+        )?;
+    }
+    Ok(ExpMapBands {
+        dir: dir.to_path_buf(),
+        info,
+        band: None,
+    })
+}
+
+fn render_frame(
+    animation: &Animation,
+    engine: &dyn RenderEngine,
+    exp_map: Option<&mut ExpMapBands>,
+    palette: &Palette,
+    frame: AnimationFrame,
+    dir: &Path,
+) -> FractalResult<FrameRecord> {
+    let start = Instant::now();
+    let req = frame.to_request(&animation.request);
+    let params = RenderParams::new(&req, palette.clone());
+    let pixels = match exp_map {
+        Some(map) => map.band_for(&params.viewport)?.compose(&params.viewport),
+        None => engine.calc(&params)?,
+    };
+    let iterations = animation
+        .format
+        .needs_iterations()
+        .then(|| IterationBuffer::calc(&params));
+    let data = encode_output(
+        animation.format,
+        &pixels,
+        &params,
+        &req,
+        iterations.as_ref(),
+    )?;
+
+    // the record is written last, a frame without one is rendered again
+    let file = frame_file_name(frame.index, animation.format);
+    write_file_atomic(&dir.join(&file), &data)?;
+    let record = FrameRecord {
+        file,
+        hash: format!("{:016x}", stable_hash(&data)),
+        bytes: data.len() as u64,
+        duration_ms: start.elapsed().as_millis(),
+        frame,
+    };
+    let json = serde_json::to_string_pretty(&record)?;
+    write_file_atomic(&record_path(dir, record.frame.index), json.as_bytes())?;
+    Ok(record)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::PathBuf;
+
+    use image::codecs::gif::GifDecoder;
+    use image::AnimationDecoder;
+
+    use crate::animated_image::AnimationFormat;
+    use crate::animation::{
+        render_animation, Animation, AnimationFrame, AnimationManifest, AnimationMode,
+        AnimationOptions, Easing, Keyframe, Shard, MANIFEST_FILE,
+    };
+    use crate::complex::ComplexNumber;
+    use crate::error::FractalError;
+    use crate::fractal_calculation_rayon::RayonEngine;
+    use crate::fractal_templates::basic;
+    use crate::output::OutputFormat;
+    use crate::png_metadata::read_request;
+    use crate::utils::stable_hash;
+    use crate::validation::RequestLimits;
+    use crate::viewport::Viewport;
+
+    fn assert_close(a: f64, b: f64) {
+        assert!((a - b).abs() < 1e-9, "{} != {}", a, b);
+    }
+
+    fn keyframe(time: f64, a: f64, zoom: f64) -> Keyframe {
+        Keyframe {
+            time,
+            center: ComplexNumber { a, b: 0.0 },
+            zoom,
+            rotation: 0.0,
+            max_iterations: 100,
+            palette_offset: 0.0,
+            easing: Easing::Linear,
+        }
+    }
+
+    fn animation(keyframes: Vec<Keyframe>) -> Animation {
+        let (mut request, _, _) = basic(true);
+        request.width = 24;
+        request.height = 16;
+        request.zoom = 1.0;
+        Animation {
+            request,
+            fps: 4.0,
+            keyframes,
+            format: OutputFormat::Png,
+            mode: AnimationMode::Frames,
+            videos: vec![],
+        }
+    }
+
+    #[test]
+    fn test_easing() {
+        for easing in [
+            Easing::Linear,
+            Easing::EaseIn,
+            Easing::EaseOut,
+            Easing::EaseInOut,
+        ] {
+            assert_close(easing.apply(0.0), 0.0);
+            assert_close(easing.apply(1.0), 1.0);
+        }
+        assert_close(Easing::EaseInOut.apply(0.5), 0.5);
+        assert!(Easing::EaseIn.apply(0.5) < 0.5);
+        assert!(Easing::EaseOut.apply(0.5) > 0.5);
+        assert_close(Easing::Hold.apply(0.99), 0.0);
+    }
+
+    #[test]
+    fn test_zoom_is_logarithmic() {
+        let animation = animation(vec![keyframe(0.0, 0.0, 1.0), keyframe(2.0, 0.0, 1e8)]);
+        assert_eq!(animation.frame_count(), 9);
+
+        let frames = animation.frames();
+        assert_close(frames[0].zoom, 1.0);
+        assert_close(frames[4].zoom.log10(), 4.0);
+        assert_close(frames[8].zoom.log10(), 8.0);
+        for f in frames.windows(2) {
+            assert_close(f[1].zoom / f[0].zoom, 10.0);
+        }
+    }
+
+    #[test]
+    fn test_zoom_around_fixed_point() {
+        let from = keyframe(0.0, -0.5, 1.0);
+        let to = keyframe(1.0, -1.5, 1000.0);
+        let animation = animation(vec![from.clone(), to.clone()]);
+
+        let (w0, w1) = (1.0 / from.zoom, 1.0 / to.zoom);
+        let fixed = ComplexNumber {
+            a: (from.center.a * w1 - to.center.a * w0) / (w1 - w0),
+            b: 0.0,
+        };
+        let pixel = |frame: &AnimationFrame, c: &ComplexNumber| {
+            Viewport::from_request(&frame.to_request(&animation.request)).complex_to_pixel(c)
+        };
+
+        let frames = animation.frames();
+        let (x, y) = pixel(&frames[0], &fixed);
+        let mut distance = f64::MAX;
+        for frame in &frames {
+            let (fx, fy) = pixel(frame, &fixed);
+            assert!(
+                (fx - x).abs() < 1e-6 && (fy - y).abs() < 1e-6,
+                "{:?}",
+                frame
+            );
+
+            // the target gets closer to the middle in every frame
+            let (tx, _) = pixel(frame, &to.center);
+            let d = (tx - animation.request.width as f64 / 2.0).abs();
+            assert!(d < distance);
+            distance = d;
+        }
+        assert!(distance < 1e-6);
+    }
+
+    #[test]
+    fn test_keyframes_and_easing() {
+        let mut first = keyframe(0.0, 0.0, 2.0);
+        first.max_iterations = 100;
+        first.palette_offset = 0.0;
+        first.easing = Easing::Hold;
+        let mut second = keyframe(1.0, 1.0, 2.0);
+        second.max_iterations = 300;
+        second.palette_offset = 10.0;
+        second.rotation = 90.0;
+        let animation = animation(vec![first, second, keyframe(2.0, 2.0, 2.0)]);
+
+        let frame = animation.frame(2);
+        assert_close(frame.center.a, 0.0);
+        assert_eq!(frame.max_iterations, 100);
+
+        // a pure pan moves linearly
+        let frame = animation.frame(6);
+        assert_close(frame.center.a, 1.5);
+        assert_eq!(frame.max_iterations, 200);
+        assert_close(frame.palette_offset, 5.0);
+        assert_close(frame.rotation, 45.0);
+        assert_close(animation.frame(8).center.a, 2.0);
+    }
+
+    #[test]
+    fn test_invalid_animations() {
+        let limits = RequestLimits::default();
+        assert!(animation(vec![]).validate(&limits).is_err());
+        assert!(animation(vec![keyframe(1.0, 0.0, 1.0)])
+            .validate(&limits)
+            .is_err());
+        assert!(
+            animation(vec![keyframe(0.0, 0.0, 1.0), keyframe(0.0, 0.0, 2.0)])
+                .validate(&limits)
+                .is_err()
+        );
+        assert!(animation(vec![keyframe(0.0, 0.0, 0.0)])
+            .validate(&limits)
+            .is_err());
+        assert!(animation(vec![keyframe(0.0, 0.0, 1.0)])
+            .validate(&limits)
+            .is_ok());
+    }
+
+    #[test]
+    fn test_animation_limits() {
+        let limits = RequestLimits::default();
+        let keyframes = vec![keyframe(0.0, 0.0, 1.0), keyframe(10.0, 0.0, 2.0)];
+
+        // the frames can't be bigger than a single image
+        let mut too_large = animation(keyframes.clone());
+        too_large.request.width = 100_000;
+        assert!(too_large.validate(&limits).is_err());
+
+        let mut iterations = animation(keyframes.clone());
+        iterations.keyframes[1].max_iterations = limits.max_iterations + 1;
+        assert!(iterations.validate(&limits).is_err());
+
+        // every value of a keyframe has to be a finite number
+        let invalid: [fn(&mut Keyframe); 6] = [
+            |k| k.zoom = f64::INFINITY,
+            |k| k.zoom = -1.0,
+            |k| k.center.a = f64::NAN,
+            |k| k.rotation = f64::INFINITY,
+            |k| k.palette_offset = f64::NAN,
+            |k| k.time = f64::NAN,
+        ];
+        for change in invalid {
+            let mut animation = animation(keyframes.clone());
+            change(&mut animation.keyframes[1]);
+            assert!(matches!(
+                animation.validate(&limits),
+                Err(FractalError::Validation(_))
+            ));
+        }
+
+        // 41 frames of 24x16
+        let animation = animation(keyframes);
+        assert!(animation.validate(&limits).is_ok());
+        let frames = RequestLimits {
+            max_frames: 40,
+            ..limits.clone()
+        };
+        assert!(animation.validate(&frames).is_err());
+        let pixels = RequestLimits {
+            max_animation_pixels: 40 * 24 * 16,
+            ..limits
+        };
+        assert!(animation.validate(&pixels).is_err());
+    }
+
+    fn output_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("animation_{}_{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    fn zoom_animation() -> Animation {
+        animation(vec![keyframe(0.0, -0.5, 1.0), keyframe(0.5, -0.75, 4.0)])
+    }
+
+    #[test]
+    fn test_render_animation() {
+        let dir = output_dir("complete");
+        let animation = zoom_animation();
+
+        let progress =
+            render_animation(&animation, &RayonEngine, &dir, &AnimationOptions::default()).unwrap();
+        assert_eq!((progress.frames, progress.calculated), (3, 3));
+        let manifest = progress.manifest.unwrap();
+        assert_eq!(manifest.frames.len(), 3);
+        assert_eq!(manifest.frames[2].file, "frame_000002.png");
+
+        let json = fs::read_to_string(dir.join(MANIFEST_FILE)).unwrap();
+        let read: AnimationManifest = serde_json::from_str(&json).unwrap();
+        assert_eq!(read, manifest);
+
+        let png = fs::read(dir.join("frame_000001.png")).unwrap();
+        assert_eq!(
+            format!("{:016x}", stable_hash(&png)),
+            manifest.frames[1].hash
+        );
+        let req = read_request(&png).unwrap();
+        assert_close(req.zoom, 2.0);
+        assert_eq!(req, manifest.frames[1].frame.to_request(&animation.request));
+    }
+
+    #[test]
+    fn test_interrupted_animation_continues() {
+        let dir = output_dir("resumed");
+        let animation = zoom_animation();
+        let mut options = AnimationOptions {
+            max_frames: Some(2),
+            ..Default::default()
+        };
+
+        let progress = render_animation(&animation, &RayonEngine, &dir, &options).unwrap();
+        assert_eq!((progress.finished, progress.calculated), (2, 2));
+        assert!(progress.manifest.is_none());
+        assert!(!dir.join(MANIFEST_FILE).exists());
+
+        // a damaged frame with the right size is only noticed with verify
+        let frame = dir.join("frame_000001.png");
+        let mut png = fs::read(&frame).unwrap();
+        let last = png.len() - 1;
+        png[last] ^= 0xff;
+        fs::write(&frame, &png).unwrap();
+        let progress = render_animation(&animation, &RayonEngine, &dir, &options).unwrap();
+        assert_eq!((progress.finished, progress.calculated), (3, 1));
+
+        options.verify = true;
+        fs::write(&frame, &png).unwrap();
+        let progress = render_animation(&animation, &RayonEngine, &dir, &options).unwrap();
+        assert_eq!((progress.finished, progress.calculated), (3, 1));
+        assert!(progress.is_complete());
+        assert_eq!(progress.manifest.unwrap().frames.len(), 3);
+    }
+
+    #[test]
+    fn test_shards() {
+        let dir = output_dir("shards");
+        let animation = animation(vec![keyframe(0.0, -0.5, 1.0), keyframe(1.0, -0.75, 4.0)]);
+        let shard = |s: &str| AnimationOptions {
+            shard: Some(s.parse().unwrap()),
+            ..Default::default()
+        };
+
+        let progress = render_animation(&animation, &RayonEngine, &dir, &shard("1/2")).unwrap();
+        assert_eq!((progress.frames, progress.calculated), (5, 2));
+        assert!(dir.join("frame_000003.png").exists());
+        assert!(!dir.join("frame_000002.png").exists());
+
+        let progress = render_animation(&animation, &RayonEngine, &dir, &shard("0/2")).unwrap();
+        assert_eq!(progress.calculated, 3);
+        assert!(progress.is_complete());
+
+        let mut other = animation.clone();
+        other.fps = 8.0;
+        assert!(render_animation(&other, &RayonEngine, &dir, &shard("0/2")).is_err());
+    }
+
+    #[test]
+    fn test_parse_shard() {
+        assert_eq!(
+            "2/4".parse::<Shard>().unwrap(),
+            Shard { index: 2, count: 4 }
+        );
+        assert!("4/4".parse::<Shard>().is_err());
+        assert!("2".parse::<Shard>().is_err());
+        assert!("a/4".parse::<Shard>().is_err());
+    }
+
+    #[test]
+    fn test_exp_map_animation() {
+        let dir = output_dir("exp_map");
+        let mut animation = zoom_animation();
+        animation.mode = AnimationMode::ExpMap;
+        assert!(animation.validate(&RequestLimits::default()).is_err());
+
+        animation.keyframes[1].center = animation.keyframes[0].center.clone();
+        animation.keyframes[1].rotation = 30.0;
+        let progress =
+            render_animation(&animation, &RayonEngine, &dir, &AnimationOptions::default()).unwrap();
+        assert!(progress.is_complete());
+        assert!(dir.join("exp_map.png").exists());
+
+        let png = fs::read(dir.join("frame_000002.png")).unwrap();
+        let image = image::load_from_memory(&png).unwrap();
+        assert_eq!((image.width(), image.height()), (24, 16));
+        assert_close(read_request(&png).unwrap().zoom, 4.0);
+
+        animation.format = OutputFormat::Pfm;
+        assert!(animation.validate(&RequestLimits::default()).is_err());
+    }
+
+    #[test]
+    fn test_videos() {
+        let dir = output_dir("videos");
+        let mut animation = zoom_animation();
+        animation.videos = vec![
+            AnimationFormat::Gif,
+            AnimationFormat::Apng,
+            AnimationFormat::Y4m,
+        ];
+
+        let progress =
+            render_animation(&animation, &RayonEngine, &dir, &AnimationOptions::default()).unwrap();
+        let manifest = progress.manifest.unwrap();
+        assert_eq!(
+            manifest.videos,
+            vec!["animation.gif", "animation.png", "animation.y4m"]
+        );
+
+        let gif = fs::read(dir.join("animation.gif")).unwrap();
+        let frames = GifDecoder::new(gif.as_slice())
+            .unwrap()
+            .into_frames()
+            .collect_frames()
+            .unwrap();
+        assert_eq!(frames.len(), 3);
+
+        let y4m = fs::read(dir.join("animation.y4m")).unwrap();
+        let header = b"YUV4MPEG2 W24 H16 F4:1 Ip A1:1 C420jpeg XCOLORRANGE=LIMITED\n";
+        assert!(y4m.starts_with(header));
+        assert_eq!(y4m.len(), header.len() + 3 * (6 + 24 * 16 + 2 * 12 * 8));
+
+        animation.format = OutputFormat::Pfm;
+        assert!(animation.validate(&RequestLimits::default()).is_err());
+    }
+}
@@ -4,6 +4,8 @@ use std::fs;
 use log::info;
 use serde_derive::{Deserialize, Serialize};
 
+use crate::error::{FractalError, FractalResult};
+
 pub const BLACK: Color = Color { r: 0, g: 0, b: 0 };
 
 pub const MAROON: Color = Color { r: 128, g: 0, b: 0 };
@@ -88,6 +90,99 @@ pub struct Hsl {
     pub(crate) l: f32,
 }
 
+// h in degrees, s and l in percent like in 256-colors.json
+impl From<&Color> for Hsl {
+    fn from(c: &Color) -> Self {
+        let r = c.r as f32 / 255.0;
+        let g = c.g as f32 / 255.0;
+        let b = c.b as f32 / 255.0;
+        let max = r.max(g).max(b);
+        let min = r.min(g).min(b);
+        let l = (max + min) / 2.0;
+
+        if max == min {
+            return Hsl {
+                h: 0.0,
+                s: 0.0,
+                l: l * 100.0,
+            };
+        }
+
+        let d = max - min;
+        let s = if l > 0.5 {
+            d / (2.0 - max - min)
+        } else {
+            d / (max + min)
+        };
+        let h = if max == r {
+            (g - b) / d + if g < b { 6.0 } else { 0.0 }
+        } else if max == g {
+            (b - r) / d + 2.0
+        } else {
+            (r - g) / d + 4.0
+        };
+
+        Hsl {
+            h: h * 60.0,
+            s: s * 100.0,
+            l: l * 100.0,
+        }
+    }
+}
+
+impl From<&Hsl> for Color {
+    fn from(hsl: &Hsl) -> Self {
+        let [r, g, b] = hsl.to_unit_rgb();
+        let to_u8 = |v: f32| (v * 255.0).round().clamp(0.0, 255.0) as u8;
+        Color {
+            r: to_u8(r),
+            g: to_u8(g),
+            b: to_u8(b),
+        }
+    }
+}
+
+impl From<&Hsl> for Rgb16 {
+    fn from(hsl: &Hsl) -> Self {
+        let [r, g, b] = hsl.to_unit_rgb();
+        Rgb16::from_unit(r as f64, g as f64, b as f64)
+    }
+}
+
+impl Hsl {
+    // r, g and b in 0..1
+    fn to_unit_rgb(&self) -> [f32; 3] {
+        let h = self.h.rem_euclid(360.0) / 360.0;
+        let s = self.s / 100.0;
+        let l = self.l / 100.0;
+
+        if s == 0.0 {
+            return [l, l, l];
+        }
+
+        let q = if l < 0.5 { l * (1.0 + s) } else { l + s - l * s };
+        let p = 2.0 * l - q;
+        [
+            hue_to_rgb(p, q, h + 1.0 / 3.0),
+            hue_to_rgb(p, q, h),
+            hue_to_rgb(p, q, h - 1.0 / 3.0),
+        ]
+    }
+}
+
+fn hue_to_rgb(p: f32, q: f32, t: f32) -> f32 {
+    let t = t.rem_euclid(1.0);
+    if t < 1.0 / 6.0 {
+        p + (q - p) * 6.0 * t
+    } else if t < 1.0 / 2.0 {
+        q
+    } else if t < 2.0 / 3.0 {
+        p + (q - p) * (2.0 / 3.0 - t) * 6.0
+    } else {
+        p
+    }
+}
+
 #[derive(Deserialize, Serialize, Clone, PartialEq, Default)]
 pub struct Color {
     pub r: u8,
@@ -95,12 +190,43 @@ pub struct Color {
     pub b: u8,
 }
 
-pub fn color256() -> Vec<Color> {
+// 16 bits per channel for PNGs without banding, only used for output
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Rgb16 {
+    pub r: u16,
+    pub g: u16,
+    pub b: u16,
+}
+
+impl Rgb16 {
+    // r, g and b in 0..1
+    pub fn from_unit(r: f64, g: f64, b: f64) -> Rgb16 {
+        let to_u16 = |v: f64| (v * 65535.0).round().clamp(0.0, 65535.0) as u16;
+        Rgb16 {
+            r: to_u16(r),
+            g: to_u16(g),
+            b: to_u16(b),
+        }
+    }
+}
+
+// 0xAB becomes 0xABAB, so white stays white
+impl From<&Color> for Rgb16 {
+    fn from(c: &Color) -> Self {
+        Rgb16 {
+            r: c.r as u16 * 257,
+            g: c.g as u16 * 257,
+            b: c.b as u16 * 257,
+        }
+    }
+}
+
+pub fn color256() -> FractalResult<Vec<Color>> {
     let filename = "256-colors.json";
     let path = format!("{}/../{}", env!("CARGO_MANIFEST_DIR"), filename);
     info!("path to json {}", path);
-    let data = fs::read_to_string(path).expect("Unable to read file");
-    let colors: Vec<FileColor> = serde_json::from_str(&data).expect("JSON was not well-formatted");
+    let data = fs::read_to_string(&path).map_err(|e| FractalError::io(&path, e))?;
+    let colors: Vec<FileColor> = serde_json::from_str(&data)?;
     let cs: Vec<Color> = colors
         .iter()
         .map(|c| Color {
@@ -111,28 +237,14 @@ pub fn color256() -> Vec<Color> {
         .collect();
 
     // cs.iter().for_each(|c| info!("color  {}", &c));
-    cs
+    Ok(cs)
 }
 
 pub fn color16() -> Vec<Color> {
-    let mut v = vec![];
-    v.push(BLACK);
-    v.push(MAROON);
-    v.push(GREEN);
-    v.push(OLIVE);
-    v.push(NAVY);
-    v.push(PURPLE);
-    v.push(TEAL);
-    v.push(SILVER);
-    v.push(GRAY);
-    v.push(RED);
-    v.push(LIME);
-    v.push(YELLOW);
-    v.push(BLUE);
-    v.push(FUCHSIA);
-    v.push(AQUA);
-    v.push(WHITE);
-    v
+    vec![
+        BLACK, MAROON, GREEN, OLIVE, NAVY, PURPLE, TEAL, SILVER, GRAY, RED, LIME, YELLOW, BLUE,
+        FUCHSIA, AQUA, WHITE,
+    ]
 }
 
 impl Debug for Color {
@@ -153,9 +265,28 @@ impl Display for Color {
 
 #[cfg(test)]
 mod tests {
+    use crate::color::{Color, Hsl, Rgb16, BLUE, GRAY, LIME, OLIVE};
+
     #[test]
     fn it_works() {
         let result = 2 + 2;
         assert_eq!(result, 4);
     }
+
+    #[test]
+    fn test_hsl_roundtrip() {
+        for c in [BLUE, GRAY, LIME, OLIVE, Color { r: 12, g: 200, b: 99 }] {
+            assert_eq!(Color::from(&Hsl::from(&c)), c);
+        }
+        let hsl = Hsl::from(&BLUE);
+        assert_eq!((hsl.h, hsl.s, hsl.l), (240.0, 100.0, 50.0));
+    }
+
+    #[test]
+    fn test_rgb16() {
+        let white = Color { r: 255, g: 255, b: 255 };
+        assert_eq!(Rgb16::from(&white), Rgb16 { r: 65535, g: 65535, b: 65535 });
+        assert_eq!(Rgb16::from(&OLIVE), Rgb16 { r: 32896, g: 32896, b: 0 });
+        assert_eq!(Rgb16::from(&Hsl::from(&BLUE)), Rgb16::from(&BLUE));
+    }
 }
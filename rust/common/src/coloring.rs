@@ -0,0 +1,217 @@
+use std::f64::consts::LN_2;
+
+use serde_derive::{Deserialize, Serialize};
+
+use crate::color::{Color, Rgb16, BLACK};
+use crate::complex::ComplexNumber;
+use crate::gradient::{interpolate, interpolate16, Gradient, Interpolation};
+
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, Default, PartialEq)]
+pub enum Coloring {
+    // color = palette[iterations % palette.len()], shows bands
+    #[default]
+    Modulo,
+    // uses the fractional iteration count, no bands
+    Smooth,
+}
+
+impl Coloring {
+    // a bigger escape radius makes the fractional iteration count more accurate
+    pub fn bailout(&self) -> f64 {
+        match self {
+            Coloring::Modulo => 4.0,
+            Coloring::Smooth => 256.0 * 256.0,
+        }
+    }
+}
+
+// a resolved PaletteSpec
+#[derive(Clone, Debug, PartialEq)]
+pub enum Palette {
+    Colors(Vec<Color>),
+    Gradient(Gradient),
+}
+
+impl Palette {
+    // the discrete colors, gradients are sampled once per step
+    pub fn to_colors(&self) -> Vec<Color> {
+        match self {
+            Palette::Colors(colors) => colors.clone(),
+            Palette::Gradient(gradient) => {
+                gradient.to_colors(gradient.steps.clamp(2, 256) as usize)
+            }
+        }
+    }
+
+    pub fn to_gradient(&self) -> Gradient {
+        match self {
+            Palette::Colors(colors) => {
+                Gradient::new(colors, Interpolation::Rgb, colors.len().max(1) as u32)
+            }
+            Palette::Gradient(gradient) => gradient.clone(),
+        }
+    }
+
+    // a strip with all colors from left to right
+    pub fn swatch(&self, width: u32, height: u32) -> Vec<Color> {
+        let row: Vec<Color> = (0..width)
+            .map(|x| match self {
+                Palette::Colors(colors) if colors.is_empty() => BLACK,
+                Palette::Colors(colors) => colors[x as usize * colors.len() / width as usize].clone(),
+                Palette::Gradient(gradient) => {
+                    gradient.sample(x as f64 / (width.max(2) - 1) as f64)
+                }
+            })
+            .collect();
+        (0..height).flat_map(|_| row.iter().cloned()).collect()
+    }
+
+    // value is the iteration_value, shifted by the palette offset
+    pub fn color(&self, coloring: Coloring, value: f64) -> Color {
+        match coloring {
+            Coloring::Modulo => self.color_modulo(value),
+            Coloring::Smooth => self.color_smooth(value),
+        }
+    }
+
+    pub fn color_modulo(&self, iterations: f64) -> Color {
+        let iterations = iterations.floor();
+        match self {
+            Palette::Colors(colors) if colors.is_empty() => BLACK,
+            Palette::Colors(colors) => {
+                colors[iterations.rem_euclid(colors.len() as f64) as usize].clone()
+            }
+            Palette::Gradient(gradient) => gradient.color_at(iterations),
+        }
+    }
+
+    pub fn color_smooth(&self, iterations: f64) -> Color {
+        match self {
+            Palette::Colors(colors) if colors.is_empty() => BLACK,
+            Palette::Colors(colors) => {
+                let idx = iterations.floor().rem_euclid(colors.len() as f64) as usize;
+                let from = &colors[idx];
+                let to = &colors[(idx + 1) % colors.len()];
+                // the weight of the segment idx is in, also for negative values
+                let weight = iterations - iterations.floor();
+                interpolate(from, to, weight, Interpolation::Rgb)
+            }
+            Palette::Gradient(gradient) => gradient.color_at(iterations),
+        }
+    }
+
+    // for 16 bit output, smooth coloring isn't rounded to 8 bits
+    pub fn color16(&self, coloring: Coloring, value: f64) -> Rgb16 {
+        match (coloring, self) {
+            (Coloring::Modulo, _) => Rgb16::from(&self.color_modulo(value)),
+            (Coloring::Smooth, Palette::Colors(colors)) if colors.is_empty() => Rgb16::default(),
+            (Coloring::Smooth, Palette::Colors(colors)) => {
+                let idx = value.floor().rem_euclid(colors.len() as f64) as usize;
+                let from = &colors[idx];
+                let to = &colors[(idx + 1) % colors.len()];
+                interpolate16(from, to, value - value.floor(), Interpolation::Rgb)
+            }
+            (Coloring::Smooth, Palette::Gradient(gradient)) => gradient.color_at16(value),
+        }
+    }
+}
+
+// the value the palette is indexed with. z is the first value outside of the bailout radius
+pub fn iteration_value(coloring: Coloring, iterations: u32, z: &ComplexNumber) -> f64 {
+    match coloring {
+        Coloring::Modulo => iterations as f64,
+        Coloring::Smooth => smooth_iterations(iterations, z),
+    }
+}
+
+// normalized iteration count, continuous across the borders of the bands
+pub fn smooth_iterations(iterations: u32, z: &ComplexNumber) -> f64 {
+    let log_z = z.length_squared().ln() / 2.0;
+    let nu = (log_z / LN_2).ln() / LN_2;
+    iterations as f64 + 1.0 - nu
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::color::{Color, BLUE, RED};
+    use crate::coloring::{smooth_iterations, Coloring, Palette};
+    use crate::complex::ComplexNumber;
+    use crate::gradient::{Gradient, Interpolation};
+
+    #[test]
+    fn test_modulo() {
+        let palette = Palette::Colors(vec![RED, BLUE]);
+        assert_eq!(palette.color_modulo(0.0), RED);
+        assert_eq!(palette.color_modulo(3.0), BLUE);
+        assert_eq!(palette.color_modulo(3.7), BLUE);
+        assert_eq!(palette.color_modulo(-1.0), BLUE);
+
+        let palette = Palette::Gradient(Gradient::new(&[RED, BLUE], Interpolation::Rgb, 2));
+        assert_eq!(palette.color_modulo(0.0), RED);
+        assert_eq!(palette.color_modulo(1.0), palette.color_smooth(1.0));
+        assert_eq!(palette.color_modulo(2.0), RED);
+    }
+
+    #[test]
+    fn test_swatch() {
+        let palette = Palette::Colors(vec![RED, BLUE]);
+        assert_eq!(palette.swatch(4, 2), vec![RED, RED, BLUE, BLUE, RED, RED, BLUE, BLUE]);
+
+        let palette = Palette::Gradient(Gradient::new(&[RED, BLUE], Interpolation::Rgb, 2));
+        let swatch = palette.swatch(3, 1);
+        assert_eq!(swatch[0], RED);
+        assert_eq!(swatch[2], BLUE);
+    }
+
+    #[test]
+    fn test_smooth_is_between_neighbours() {
+        let palette = Palette::Colors(vec![RED, BLUE]);
+        assert_eq!(palette.color_smooth(0.0), RED);
+        assert_eq!(
+            palette.color_smooth(0.5),
+            Color {
+                r: 128,
+                g: 0,
+                b: 128
+            }
+        );
+        assert_eq!(palette.color_smooth(1.0), BLUE);
+    }
+
+    #[test]
+    fn test_smooth_is_continuous() {
+        let palette = Palette::Colors(vec![RED, BLUE, Color { r: 0, g: 255, b: 0 }]);
+        let distance = |a: &Color, b: &Color| {
+            (a.r as i32 - b.r as i32).abs()
+                + (a.g as i32 - b.g as i32).abs()
+                + (a.b as i32 - b.b as i32).abs()
+        };
+        // across 0 and with the negative values of a palette offset
+        for offset in [0.0, -3.3] {
+            let colors: Vec<Color> = (-300..300)
+                .map(|i| palette.color(Coloring::Smooth, i as f64 / 100.0 + offset))
+                .collect();
+            assert!(colors.windows(2).all(|w| distance(&w[0], &w[1]) <= 16));
+
+            let colors16: Vec<_> = (-300..300)
+                .map(|i| palette.color16(Coloring::Smooth, i as f64 / 100.0 + offset))
+                .collect();
+            assert!(colors16.windows(2).all(|w| {
+                (w[0].r as i32 - w[1].r as i32).abs()
+                    + (w[0].g as i32 - w[1].g as i32).abs()
+                    + (w[0].b as i32 - w[1].b as i32).abs()
+                    <= 16 * 257
+            }));
+        }
+        // -0.25 is a quarter of the way back from the first color to the last one
+        assert_eq!(palette.color_smooth(-0.25), palette.color_smooth(2.75));
+    }
+
+    #[test]
+    fn test_smooth_iterations() {
+        // |z| = 256 is exactly the bailout radius, so nu = 3
+        let z = ComplexNumber { a: 256.0, b: 0.0 };
+        assert!((smooth_iterations(10, &z) - 8.0).abs() < 1e-9);
+        assert_eq!(Coloring::default(), Coloring::Modulo);
+    }
+}
@@ -0,0 +1,127 @@
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+use std::io;
+
+use crate::validation::FieldError;
+
+pub type FractalResult<T> = Result<T, FractalError>;
+
+#[derive(Debug)]
+pub enum FractalError {
+    UnknownEngine(String),
+    PaletteNotFound(String),
+    InvalidPalette {
+        file: String,
+        line: Option<usize>,
+        message: String,
+    },
+    InvalidRequest(String),
+    Validation(Vec<FieldError>),
+    Io { path: String, source: io::Error },
+    Json(serde_json::Error),
+    Image(image::ImageError),
+    Encoding(String),
+    Render(String),
+    // a wrong setting of the server, e.g. an environment variable
+    Config(String),
+}
+
+impl FractalError {
+    // true if the error was caused by the request and not by the server
+    pub fn is_client_error(&self) -> bool {
+        matches!(
+            self,
+            FractalError::UnknownEngine(_)
+                | FractalError::PaletteNotFound(_)
+                | FractalError::InvalidPalette { .. }
+                | FractalError::InvalidRequest(_)
+                | FractalError::Validation(_)
+                | FractalError::Json(_)
+        )
+    }
+
+    pub fn io(path: &str, source: io::Error) -> FractalError {
+        FractalError::Io {
+            path: path.to_string(),
+            source,
+        }
+    }
+}
+
+impl Display for FractalError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FractalError::UnknownEngine(name) => write!(f, "unknown engine '{}'", name),
+            FractalError::PaletteNotFound(name) => write!(f, "palette '{}' not found", name),
+            FractalError::InvalidPalette {
+                file,
+                line: Some(line),
+                message,
+            } => write!(f, "invalid palette '{}' line {}: {}", file, line, message),
+            FractalError::InvalidPalette { file, message, .. } => {
+                write!(f, "invalid palette '{}': {}", file, message)
+            }
+            FractalError::InvalidRequest(message) => write!(f, "invalid request: {}", message),
+            FractalError::Validation(errors) => {
+                let errors: Vec<String> = errors.iter().map(|e| e.to_string()).collect();
+                write!(f, "invalid request: {}", errors.join(", "))
+            }
+            FractalError::Io { path, source } => write!(f, "io error for '{}': {}", path, source),
+            FractalError::Json(e) => write!(f, "json error: {}", e),
+            FractalError::Image(e) => write!(f, "image error: {}", e),
+            FractalError::Encoding(message) => write!(f, "encoding failed: {}", message),
+            FractalError::Render(message) => write!(f, "rendering failed: {}", message),
+            FractalError::Config(message) => write!(f, "invalid configuration: {}", message),
+        }
+    }
+}
+
+impl Error for FractalError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            FractalError::Io { source, .. } => Some(source),
+            FractalError::Json(e) => Some(e),
+            FractalError::Image(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<serde_json::Error> for FractalError {
+    fn from(e: serde_json::Error) -> Self {
+        FractalError::Json(e)
+    }
+}
+
+impl From<image::ImageError> for FractalError {
+    fn from(e: image::ImageError) -> Self {
+        FractalError::Image(e)
+    }
+}
+
+impl From<png::EncodingError> for FractalError {
+    fn from(e: png::EncodingError) -> Self {
+        FractalError::Encoding(e.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+
+    use crate::error::FractalError;
+
+    #[test]
+    fn test_client_errors() {
+        assert!(FractalError::UnknownEngine("nope".to_string()).is_client_error());
+        assert!(FractalError::PaletteNotFound("nope.map".to_string()).is_client_error());
+        assert!(!FractalError::io("/nope", io::Error::from(io::ErrorKind::NotFound)).is_client_error());
+        assert!(!FractalError::Render("thread panicked".to_string()).is_client_error());
+    }
+
+    #[test]
+    fn test_display() {
+        let e = FractalError::PaletteNotFound("nope.map".to_string());
+        assert_eq!(e.to_string(), "palette 'nope.map' not found");
+    }
+}
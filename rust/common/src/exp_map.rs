@@ -0,0 +1,473 @@
+use std::f64::consts::PI;
+use std::fs;
+use std::fs::File;
+use std::io::BufReader;
+use std::ops::Range;
+use std::path::Path;
+use std::time::Instant;
+
+use log::info;
+use rayon::prelude::*;
+use serde_derive::{Deserialize, Serialize};
+
+use crate::color::Color;
+use crate::complex::ComplexNumber;
+use crate::error::{FractalError, FractalResult};
+use crate::fractal::calc_point_color;
+use crate::models::FractalRequest;
+use crate::render_engine::RenderParams;
+use crate::strip_render::{render_image_strips, StripImage, StripOptions, StripProgress};
+use crate::utils::write_file_atomic;
+use crate::viewport::Viewport;
+
+// a zoom into one point only ever shows the colors on circles around that point. the exponential
+// map has the angle as x and the log of the radius as y: row 0 is the circle through the corners
+// of the first frame and every row is a little smaller, so the pixels stay square. a frame is a
+// resampling of a band of rows, the same idea as the maps of Kalles Fraktaler used by zoomasm
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct ExpMapInfo {
+    pub center: ComplexNumber,
+    pub r_max: f64,
+    pub r_min: f64,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl ExpMapInfo {
+    // big enough for every frame of req between the two zoom levels without upscaling
+    pub fn for_zoom(req: &FractalRequest, min_zoom: f64, max_zoom: f64) -> ExpMapInfo {
+        let viewport = |zoom: f64| {
+            Viewport::from_request(&FractalRequest {
+                zoom,
+                ..req.clone()
+            })
+        };
+        // the smaller side of a pixel, they are only different with AspectPolicy::Stretch
+        let pixel = |vp: &Viewport| vp.x_delta().min(vp.y_delta());
+        let (first, last) = (viewport(min_zoom), viewport(max_zoom));
+        let r_max = first.complex_width.hypot(first.complex_height) / 2.0;
+        // half a pixel of the last frame
+        let r_min = pixel(&last) / 2.0;
+
+        let width = (2.0 * PI * r_max / pixel(&first)).ceil().max(4.0) as u32;
+        let step = 2.0 * PI / width as f64;
+        let height = ((r_max / r_min).ln() / step).ceil() as u32 + 1;
+        ExpMapInfo {
+            center: req.center.clone(),
+            r_max,
+            r_min,
+            width,
+            height,
+        }
+    }
+
+    // the angle between two columns, also the log of the ratio of two neighbouring rows
+    pub fn step(&self) -> f64 {
+        2.0 * PI / self.width as f64
+    }
+
+    pub fn radius(&self, row: f64) -> f64 {
+        self.r_max * (-row * self.step()).exp()
+    }
+
+    pub fn row(&self, radius: f64) -> f64 {
+        (self.r_max / radius).ln() / self.step()
+    }
+
+    pub fn pixels(&self) -> u64 {
+        self.width as u64 * self.height as u64
+    }
+}
+
+// the rows of the map a frame samples: from its corners down to half a pixel from its center.
+// the points closer to the center all get the color of the last row
+impl ExpMapInfo {
+    pub fn rows_for(&self, viewport: &Viewport) -> Range<u32> {
+        let r_max = viewport.complex_width.hypot(viewport.complex_height) / 2.0;
+        let r_min = viewport.x_delta() / 2.0;
+        let row = |radius: f64| self.row(radius).max(0.0) as u32;
+        let start = row(r_max).min(self.height - 1);
+        // one more row for the bilinear sampling
+        let end = (self.row(r_min).ceil().max(0.0) as u32 + 2).min(self.height);
+        start..end.max(start + 1)
+    }
+}
+
+// the map is calculated in strips like a big render, so deep zooms aren't limited by the memory
+struct ExpMapImage<'a> {
+    info: &'a ExpMapInfo,
+    params: &'a RenderParams,
+    req: &'a FractalRequest,
+}
+
+impl StripImage for ExpMapImage<'_> {
+    fn width(&self) -> u32 {
+        self.info.width
+    }
+
+    fn height(&self) -> u32 {
+        self.info.height
+    }
+
+    fn request(&self) -> &FractalRequest {
+        self.req
+    }
+
+    fn description(&self) -> FractalResult<serde_json::Value> {
+        Ok(serde_json::json!({ "exp_map": self.info, "request": self.req }))
+    }
+
+    fn calc_rows(&self, y0: u32, y1: u32) -> Vec<u8> {
+        let info = self.info;
+        let step = info.step();
+        (y0..y1)
+            .into_par_iter()
+            .flat_map_iter(|row| {
+                let radius = info.radius(row as f64);
+                let center = &info.center;
+                (0..info.width).flat_map(move |col| {
+                    let angle = col as f64 * step;
+                    let c = ComplexNumber {
+                        a: center.a + radius * angle.cos(),
+                        b: center.b + radius * angle.sin(),
+                    };
+                    let color = calc_point_color(&c, self.params);
+                    [color.r, color.g, color.b]
+                })
+            })
+            .collect()
+    }
+}
+
+// a band of rows of the map, enough for the frames whose rows_for are inside it
+pub struct ExpMap {
+    pub info: ExpMapInfo,
+    pub rows: Range<u32>,
+    pub pixels: Vec<Color>,
+}
+
+impl ExpMap {
+    // writes <dir>/<name>.png and, once all strips are finished, <name>.json with the info.
+    // params only provides the palette, coloring and max_iterations, req ends up in the metadata
+    pub fn render(
+        info: &ExpMapInfo,
+        params: &RenderParams,
+        req: &FractalRequest,
+        dir: &Path,
+        name: &str,
+        options: &StripOptions,
+    ) -> FractalResult<StripProgress> {
+        let start = Instant::now();
+        fs::create_dir_all(dir).map_err(|e| FractalError::io(&dir.to_string_lossy(), e))?;
+        let image = ExpMapImage { info, params, req };
+        let progress = render_image_strips(&image, &dir.join(format!("{}.png", name)), options)?;
+        if progress.is_complete() {
+            let json = serde_json::to_string_pretty(info)?;
+            write_file_atomic(&dir.join(format!("{}.json", name)), json.as_bytes())?;
+        }
+        info!(
+            "exp map {}x{}  {} of {} strips took {} ms",
+            info.width,
+            info.height,
+            progress.calculated,
+            progress.strips,
+            start.elapsed().as_millis()
+        );
+        Ok(progress)
+    }
+
+    // the json is written after the PNG, so with the json the map is complete. the text is
+    // compared because parsing doesn't always give back the exact same f64
+    pub fn is_rendered(dir: &Path, name: &str, info: &ExpMapInfo) -> bool {
+        let saved = fs::read_to_string(dir.join(format!("{}.json", name)));
+        match (saved, serde_json::to_string_pretty(info)) {
+            (Ok(saved), Ok(json)) => saved == json,
+            _ => false,
+        }
+    }
+
+    // reads the PNG row by row and only keeps the rows of the band. None if there is no saved
+    // map for this info
+    pub fn load(
+        dir: &Path,
+        name: &str,
+        info: &ExpMapInfo,
+        rows: Range<u32>,
+    ) -> FractalResult<Option<ExpMap>> {
+        if !ExpMap::is_rendered(dir, name, info) {
+            return Ok(None);
+        }
+        let path = dir.join(format!("{}.png", name));
+        let invalid =
+            |message: String| FractalError::Render(format!("{}: {}", path.display(), message));
+        if rows.is_empty() || rows.end > info.height {
+            return Err(invalid(format!("rows {:?} are not in the map", rows)));
+        }
+
+        let file = File::open(&path).map_err(|e| FractalError::io(&path.to_string_lossy(), e))?;
+        let mut reader = png::Decoder::new(BufReader::new(file))
+            .read_info()
+            .map_err(|e| invalid(e.to_string()))?;
+        let png = reader.info();
+        if (png.width, png.height) != (info.width, info.height)
+            || png.color_type != png::ColorType::Rgb
+            || png.bit_depth != png::BitDepth::Eight
+        {
+            return Err(invalid(format!(
+                "not an 8 bit RGB image with {}x{} pixels",
+                info.width, info.height
+            )));
+        }
+
+        let mut pixels = Vec::with_capacity(rows.len() * info.width as usize);
+        for y in 0..rows.end {
+            let row = reader
+                .next_row()
+                .map_err(|e| invalid(e.to_string()))?
+                .ok_or_else(|| invalid("the image ends too early".to_string()))?;
+            if y >= rows.start {
+                pixels.extend(row.data().chunks_exact(3).map(|p| Color {
+                    r: p[0],
+                    g: p[1],
+                    b: p[2],
+                }));
+            }
+        }
+        Ok(Some(ExpMap {
+            info: info.clone(),
+            rows,
+            pixels,
+        }))
+    }
+
+    pub fn contains(&self, rows: &Range<u32>) -> bool {
+        self.rows.start <= rows.start && rows.end <= self.rows.end
+    }
+
+    // bilinear, the columns wrap around. points outside the band use its first or last row
+    pub fn sample(&self, c: &ComplexNumber) -> Color {
+        let info = &self.info;
+        let (dx, dy) = (c.a - info.center.a, c.b - info.center.b);
+        let (first_row, last_row) = (self.rows.start as f64, (self.rows.end - 1) as f64);
+        let radius = dx.hypot(dy);
+        let row = if radius <= info.r_min {
+            last_row
+        } else {
+            info.row(radius).clamp(first_row, last_row)
+        };
+        let col = dy.atan2(dx).rem_euclid(2.0 * PI) / info.step();
+
+        let (r0, c0) = (row.floor(), col.floor());
+        let (fr, fc) = (row - r0, col - c0);
+        let r0 = r0 as u32 - self.rows.start;
+        let r1 = (r0 + 1).min(self.rows.len() as u32 - 1);
+        let c0 = c0 as u32 % info.width;
+        let c1 = (c0 + 1) % info.width;
+        let pixel = |r: u32, c: u32| &self.pixels[(r * info.width + c) as usize];
+
+        let mix = |a: u8, b: u8, c: u8, d: u8| {
+            let top = a as f64 + (b as f64 - a as f64) * fc;
+            let bottom = c as f64 + (d as f64 - c as f64) * fc;
+            (top + (bottom - top) * fr).round() as u8
+        };
+        let (p00, p01, p10, p11) = (pixel(r0, c0), pixel(r0, c1), pixel(r1, c0), pixel(r1, c1));
+        Color {
+            r: mix(p00.r, p01.r, p10.r, p11.r),
+            g: mix(p00.g, p01.g, p10.g, p11.g),
+            b: mix(p00.b, p01.b, p10.b, p11.b),
+        }
+    }
+
+    // the frame an engine would calculate for this viewport, up to the resampling. the band
+    // has to contain the rows_for the viewport
+    pub fn compose(&self, viewport: &Viewport) -> Vec<Color> {
+        (0..viewport.height)
+            .into_par_iter()
+            .flat_map_iter(|y| {
+                (0..viewport.width)
+                    .map(move |x| self.sample(&viewport.pixel_to_complex(x as f64, y as f64)))
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::{Path, PathBuf};
+
+    use crate::complex::ComplexNumber;
+    use crate::exp_map::{ExpMap, ExpMapInfo};
+    use crate::fractal_templates::basic;
+    use crate::models::FractalRequest;
+    use crate::palette::resolve_palette;
+    use crate::render_engine::{engine_by_name, RenderParams};
+    use crate::strip_render::StripOptions;
+    use crate::viewport::Viewport;
+
+    fn request() -> FractalRequest {
+        let (mut req, _, _) = basic(true);
+        req.width = 64;
+        req.height = 48;
+        req.zoom = 1.0;
+        req.complex_width = 0.1;
+        req.max_iterations = 200;
+        req.center = ComplexNumber {
+            a: -0.7453,
+            b: 0.1127,
+        };
+        req
+    }
+
+    #[test]
+    fn test_info() {
+        let req = request();
+        let info = ExpMapInfo::for_zoom(&req, 1.0, 1000.0);
+        let first = Viewport::from_request(&req);
+        assert!((info.r_max - first.complex_width.hypot(first.complex_height) / 2.0).abs() < 1e-12);
+        assert!(info.radius((info.height - 1) as f64) <= info.r_min);
+        assert!(info.radius((info.height - 2) as f64) > info.r_min);
+        // neighbouring rows and columns are about as far apart on the outer circle
+        assert!(
+            (info.r_max * info.step() - (info.r_max - info.radius(1.0))).abs()
+                < 0.05 * info.r_max * info.step()
+        );
+        assert!((info.row(info.radius(17.0)) - 17.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_deep_zoom_needs_fewer_pixels() {
+        let mut req = request();
+        req.width = 1920;
+        req.height = 1080;
+        let info = ExpMapInfo::for_zoom(&req, 1.0, 1e10);
+
+        // zoom factor 1.01 per frame like the templates
+        let frames = (1e10f64.ln() / 1.01f64.ln()).ceil() as u64;
+        assert!(info.pixels() * 10 < frames * 1920 * 1080);
+    }
+
+    fn output_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("exp_map_{}_{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    fn render(req: &FractalRequest, info: &ExpMapInfo, dir: &Path) {
+        let params = RenderParams::new(req, resolve_palette(&req.palette).unwrap());
+        let progress =
+            ExpMap::render(info, &params, req, dir, "map", &StripOptions::default()).unwrap();
+        assert!(progress.is_complete());
+    }
+
+    #[test]
+    fn test_composed_frames_match_engine() {
+        let req = request();
+        let info = ExpMapInfo::for_zoom(&req, 1.0, 8.0);
+        let dir = output_dir("compose");
+        render(&req, &info, &dir);
+
+        let engine = engine_by_name("rayon").unwrap();
+        for zoom in [1.0, 2.5, 8.0] {
+            let frame = FractalRequest {
+                zoom,
+                ..req.clone()
+            };
+            let expected = engine.render(&frame).unwrap().fractal.pixels;
+            let viewport = Viewport::from_request(&frame);
+            let rows = info.rows_for(&viewport);
+            let map = ExpMap::load(&dir, "map", &info, rows).unwrap().unwrap();
+            let composed = map.compose(&viewport);
+
+            // resampling blurs the edges of the bands a little
+            let close = expected
+                .iter()
+                .zip(&composed)
+                .filter(|(a, b)| {
+                    (a.r as i32 - b.r as i32).abs()
+                        + (a.g as i32 - b.g as i32).abs()
+                        + (a.b as i32 - b.b as i32).abs()
+                        < 96
+                })
+                .count();
+            assert!(
+                close * 10 > expected.len() * 8,
+                "zoom {}: {} of {}",
+                zoom,
+                close,
+                expected.len()
+            );
+        }
+    }
+
+    #[test]
+    fn test_rows_for_deep_zoom() {
+        let mut req = request();
+        req.width = 1920;
+        req.height = 1080;
+        let info = ExpMapInfo::for_zoom(&req, 1.0, 1e10);
+
+        // every frame needs about the same number of rows, only a small part of the map
+        let rows = |zoom: f64| {
+            info.rows_for(&Viewport::from_request(&FractalRequest {
+                zoom,
+                ..req.clone()
+            }))
+        };
+        let (first, last) = (rows(1.0), rows(1e10));
+        assert_eq!(first.start, 0);
+        assert_eq!(last.end, info.height);
+        assert!(first.len().abs_diff(last.len()) <= 2);
+        assert!(first.len() * 3 < info.height as usize);
+    }
+
+    #[test]
+    fn test_render_and_load() {
+        let req = request();
+        let info = ExpMapInfo::for_zoom(&req, 1.0, 2.0);
+        let params = RenderParams::new(&req, resolve_palette(&req.palette).unwrap());
+        let dir = output_dir("load");
+
+        // an interrupted map isn't used until all strips are finished
+        let options = StripOptions {
+            strip_height: 8,
+            max_strips: Some(1),
+        };
+        let progress = ExpMap::render(&info, &params, &req, &dir, "map", &options).unwrap();
+        assert!(!progress.is_complete());
+        assert!(ExpMap::load(&dir, "map", &info, 0..1).unwrap().is_none());
+        let options = StripOptions {
+            max_strips: None,
+            ..options
+        };
+        let progress = ExpMap::render(&info, &params, &req, &dir, "map", &options).unwrap();
+        assert!(progress.is_complete());
+        assert!(ExpMap::is_rendered(&dir, "map", &info));
+
+        let full = ExpMap::load(&dir, "map", &info, 0..info.height)
+            .unwrap()
+            .unwrap();
+        let band = ExpMap::load(&dir, "map", &info, 5..9).unwrap().unwrap();
+        let width = info.width as usize;
+        assert!(band.pixels == full.pixels[5 * width..9 * width]);
+        assert!(band.contains(&(6..9)));
+        assert!(!band.contains(&(4..9)));
+        assert!(ExpMap::load(&dir, "map", &info, 0..info.height + 1).is_err());
+
+        // the sample of a pixel in the middle of the band is the pixel itself
+        let (row, col) = (7.0, 3.0);
+        let radius = info.radius(row);
+        let angle = col * info.step();
+        let c = ComplexNumber {
+            a: info.center.a + radius * angle.cos(),
+            b: info.center.b + radius * angle.sin(),
+        };
+        assert_eq!(band.sample(&c), full.pixels[7 * width + 3]);
+
+        let other = ExpMapInfo::for_zoom(&req, 1.0, 4.0);
+        assert!(ExpMap::load(&dir, "map", &other, 0..1).unwrap().is_none());
+        assert!(ExpMap::load(&dir, "missing", &info, 0..1)
+            .unwrap()
+            .is_none());
+    }
+}
@@ -1,75 +1,49 @@
 use crate::color::{BLACK, Color};
+use crate::coloring::iteration_value;
 use crate::complex::ComplexNumber;
+use crate::render_engine::RenderParams;
 
-pub fn calc_fractal_color(
-    x: u32,
-    y: u32,
-    re_min: f64,
-    img_min: f64,
-    x_delta: f64,
-    y_delta: f64,
-    max_iterations: u32,
-    colors: &Vec<Color>,
-) -> Color {
-    let mut cnt_iterations = 0;
-    let c = ComplexNumber {
-        a: re_min + x as f64 * x_delta,
-        b: img_min + (y) as f64 * y_delta,
-    };
-
-    let mut z = ComplexNumber::default();
-    while z.length_squared() < 4.0 && cnt_iterations < max_iterations {
-        z = z.pow2() + &c;
-        cnt_iterations += 1;
-    }
-    //info!("z = {}, c = {} ,  cnt_iterations {}, max_iterations {}", &z, &c, cnt_iterations, max_iterations);
+pub fn calc_fractal_color(x: u32, y: u32, params: &RenderParams) -> Color {
+    let c = params.viewport.pixel_to_complex(x as f64, y as f64);
+    calc_point_color(&c, params)
+}
 
-    if cnt_iterations >= max_iterations {
+pub fn calc_point_color(c: &ComplexNumber, params: &RenderParams) -> Color {
+    match calc_point_iterations(c, params) {
         //  info!("BLACK       z = {}, c = {} ,  cnt_iterations {}, max_iterations {}", &z, &c, cnt_iterations, max_iterations);
-        BLACK
-    } else {
-        let idx = cnt_iterations as usize % colors.len();
-        let c: &Color = colors.get(idx).unwrap();
-        //  info!("color    idx {}   z = {}, c = {} ,  cnt_iterations {}, max_iterations {}",idx, &z, &c, cnt_iterations, max_iterations);
-        c.clone()
+        None => BLACK,
+        Some(value) => params
+            .palette
+            .color(params.coloring, value + params.palette_offset),
     }
 }
 
-pub fn calc_fractal_color2(
-    x: u32,
-    y: u32,
-    re_min: f64,
-    img_min: f64,
-    x_delta: f64,
-    y_delta: f64,
-    max_iterations: u32,
-    colors: &Vec<Color>,
-    pixel: &mut Color,
-) {
-    let mut cnt_iterations = 0;
-    let c = ComplexNumber {
-        a: re_min + x as f64 * x_delta,
-        b: img_min + (y) as f64 * y_delta,
-    };
+pub fn calc_fractal_color2(x: u32, y: u32, params: &RenderParams, pixel: &mut Color) {
+    let c = calc_fractal_color(x, y, params);
+    pixel.r = c.r;
+    pixel.g = c.g;
+    pixel.b = c.b;
+}
 
+// None if the point is inside the set, otherwise the value used to pick the color
+pub fn calc_fractal_iterations(x: u32, y: u32, params: &RenderParams) -> Option<f64> {
+    let c = params.viewport.pixel_to_complex(x as f64, y as f64);
+    calc_point_iterations(&c, params)
+}
+
+pub fn calc_point_iterations(c: &ComplexNumber, params: &RenderParams) -> Option<f64> {
+    let mut cnt_iterations = 0;
     let mut z = ComplexNumber::default();
-    while z.length_squared() < 4.0 && cnt_iterations < max_iterations {
-        z = z.pow2() + &c;
+    let bailout = params.coloring.bailout();
+    while z.length_squared() < bailout && cnt_iterations < params.max_iterations {
+        z = z.pow2() + c;
         cnt_iterations += 1;
     }
     //info!("z = {}, c = {} ,  cnt_iterations {}, max_iterations {}", &z, &c, cnt_iterations, max_iterations);
 
-    if cnt_iterations >= max_iterations {
-        //  info!("BLACK       z = {}, c = {} ,  cnt_iterations {}, max_iterations {}", &z, &c, cnt_iterations, max_iterations);
-        pixel.r = BLACK.r;
-        pixel.g = BLACK.g;
-        pixel.b = BLACK.b;
+    if cnt_iterations >= params.max_iterations {
+        None
     } else {
-        let idx = cnt_iterations as usize % colors.len();
-        let c: &Color = colors.get(idx).unwrap();
-        //  info!("color    idx {}   z = {}, c = {} ,  cnt_iterations {}, max_iterations {}",idx, &z, &c, cnt_iterations, max_iterations);
-        pixel.r = c.r;
-        pixel.g = c.g;
-        pixel.b = c.b;
+        Some(iteration_value(params.coloring, cnt_iterations, &z))
     }
 }
@@ -1,5 +1,7 @@
+use crate::coloring::Coloring;
 use crate::complex::ComplexNumber;
-use crate::models::FractalRequest;
+use crate::models::{FractalRequest, PaletteSpec};
+use crate::viewport::AspectPolicy;
 
 pub fn basic(debug: bool) -> (FractalRequest, f64, f64) {
     let center = ComplexNumber { a: -0.8, b: 0.0 };
@@ -24,7 +26,6 @@ pub fn basic(debug: bool) -> (FractalRequest, f64, f64) {
 
     let complex_width = 3.1;
 
-    let colors = 256;
 
     let req = FractalRequest {
         center,
@@ -32,11 +33,16 @@ pub fn basic(debug: bool) -> (FractalRequest, f64, f64) {
         height,
         complex_width,
         max_iterations,
-        colors,
+        palette: PaletteSpec::default(),
+        coloring: Coloring::default(),
+        palette_offset: 0.0,
         x_tiles: 10,
         y_tiles: 10,
         zoom,
+        rotation: 0.0,
+        aspect: AspectPolicy::FitWidth,
         name: "basic".to_string(),
+        output: vec![],
     };
 
     (req, zoom_factor, max_zoom_factor)
@@ -68,7 +74,6 @@ pub fn flower(debug: bool) -> (FractalRequest, f64, f64) {
 
     let complex_width = 4.1;
 
-    let colors = 256;
 
     let req = FractalRequest {
         center,
@@ -76,83 +81,38 @@ pub fn flower(debug: bool) -> (FractalRequest, f64, f64) {
         height,
         complex_width,
         max_iterations,
-        colors,
+        palette: PaletteSpec::default(),
+        coloring: Coloring::default(),
+        palette_offset: 0.0,
         x_tiles: 10,
         y_tiles: 10,
         zoom,
+        rotation: 0.0,
+        aspect: AspectPolicy::FitWidth,
         name: "flower".to_string(),
+        output: vec![],
     };
 
     (req, zoom_factor, max_zoom_factor)
 }
 
 pub fn tendrils(debug: bool) -> (FractalRequest, f64, f64) {
-    let center = ComplexNumber {
-        a: -0.2262667110075,
-        b: 1.11617444253,
-    };
-
-    let center = ComplexNumber {
-        a: -0.22626671100753,
-        b: 1.116174442537,
-    };
-
-    let center = ComplexNumber {
-        a: -0.226266711007581,
-        b: 1.1161744425361,
-    };
-
-    let center = ComplexNumber {
-        a: -0.2262667110075811,
-        b: 1.11617444253611,
-    };
-
-    let center = ComplexNumber {
-        a: -0.2262667110075813,
-        b: 1.11617444253613,
-    };
-
-
-    let center = ComplexNumber {
-        a: -0.2262667110075814,
-        b: 1.116174442536132,
-    };
-
-
-    // 6407226.562
-    let center = ComplexNumber {
-        a: -0.22626671100758141,
-        b: 1.1161744425361321,
-    };
-
-
-    // 6407226.562
-
-    let center = ComplexNumber {
-        a: -0.22626671100758142,
-        b: 1.1161744425361322,
-    };
-
-    // 6407226.562
-    let center = ComplexNumber {
-        a: -0.22626671100758146,
-        b: 1.1161744425361325,
-    };
-
-    // 6407226.562
-    let center = ComplexNumber {
-        a: -0.22626671100758149,
-        b: 1.1161744425361328,
-    };
-
-
-    // 6407226.562
+    // earlier, less precise centers (zoom 6407226.562)
+    //   a: -0.2262667110075, b: 1.11617444253
+    //   a: -0.22626671100753, b: 1.116174442537
+    //   a: -0.226266711007581, b: 1.1161744425361
+    //   a: -0.2262667110075811, b: 1.11617444253611
+    //   a: -0.2262667110075813, b: 1.11617444253613
+    //   a: -0.2262667110075814, b: 1.116174442536132
+    //   a: -0.22626671100758141, b: 1.1161744425361321
+    //   a: -0.22626671100758142, b: 1.1161744425361322
+    //   a: -0.22626671100758146, b: 1.1161744425361325
+    //   a: -0.22626671100758149, b: 1.1161744425361328
     let center = ComplexNumber {
         a: -0.22626671100758155,
         b: 1.116174442536133,
     };
 
-
     let mut zoom = 1.0;
     let mut max_iterations: u32 = 500_000;
     let mut zoom_factor = 1.01;
@@ -163,7 +123,6 @@ pub fn tendrils(debug: bool) -> (FractalRequest, f64, f64) {
 
     let complex_width = 4.1;
 
-    let colors = 256;
 
     if debug {
         zoom = 250_000.0;
@@ -181,12 +140,18 @@ pub fn tendrils(debug: bool) -> (FractalRequest, f64, f64) {
         height,
         complex_width,
         max_iterations,
-        colors,
+        palette: PaletteSpec::default(),
+        coloring: Coloring::default(),
+        palette_offset: 0.0,
         x_tiles: 10,
         y_tiles: 10,
         zoom,
+        rotation: 0.0,
+        aspect: AspectPolicy::FitWidth,
 
         name: "tendrils".to_string(),
+
+        output: vec![],
     };
 
     (req, zoom_factor, max_zoom_factor)
@@ -207,7 +172,6 @@ pub fn julia_island(debug: bool) -> (FractalRequest, f64, f64) {
     let mut height: u32 = 3072 * 2;
 
     let complex_width = 4.1;
-    let colors = 256;
 
     if debug {
         zoom = 100000000.0;
@@ -225,11 +189,16 @@ pub fn julia_island(debug: bool) -> (FractalRequest, f64, f64) {
         height,
         complex_width,
         max_iterations,
-        colors,
+        palette: PaletteSpec::default(),
+        coloring: Coloring::default(),
+        palette_offset: 0.0,
         x_tiles: 10,
         y_tiles: 10,
         zoom,
+        rotation: 0.0,
+        aspect: AspectPolicy::FitWidth,
         name: "julia_island".to_string(),
+        output: vec![],
     };
     (req, zoom_factor, max_zoom_factor)
 }
@@ -249,7 +218,6 @@ pub fn seahorse_valley(debug: bool) -> (FractalRequest, f64, f64) {
     let mut height: u32 = 3072 * 2;
 
     let complex_width = 4.1;
-    let colors = 256;
 
     if debug {
         zoom = 1.0;
@@ -267,12 +235,18 @@ pub fn seahorse_valley(debug: bool) -> (FractalRequest, f64, f64) {
         height,
         complex_width,
         max_iterations,
-        colors,
+        palette: PaletteSpec::default(),
+        coloring: Coloring::default(),
+        palette_offset: 0.0,
         x_tiles: 10,
         y_tiles: 10,
         zoom,
+        rotation: 0.0,
+        aspect: AspectPolicy::FitWidth,
 
         name: "seahorse_valley".to_string(),
+
+        output: vec![],
     };
     (req, zoom_factor, max_zoom_factor)
 }
@@ -292,7 +266,6 @@ pub fn starfish(debug: bool) -> (FractalRequest, f64, f64) {
     let mut height: u32 = 3072 * 2;
 
     let complex_width = 4.1;
-    let colors = 256;
 
     if debug {
         zoom = 1.0;
@@ -310,12 +283,18 @@ pub fn starfish(debug: bool) -> (FractalRequest, f64, f64) {
         height,
         complex_width,
         max_iterations,
-        colors,
+        palette: PaletteSpec::default(),
+        coloring: Coloring::default(),
+        palette_offset: 0.0,
         x_tiles: 10,
         y_tiles: 10,
         zoom,
+        rotation: 0.0,
+        aspect: AspectPolicy::FitWidth,
 
         name: "starfish".to_string(),
+
+        output: vec![],
     };
 
     (req, zoom_factor, max_zoom_factor)
@@ -336,7 +315,6 @@ pub fn sun(debug: bool) -> (FractalRequest, f64, f64) {
     let mut height: u32 = 3072 * 2;
 
     let complex_width = 4.1;
-    let colors = 256;
 
     if debug {
         zoom = 1.0;
@@ -354,11 +332,16 @@ pub fn sun(debug: bool) -> (FractalRequest, f64, f64) {
         height,
         complex_width,
         max_iterations,
-        colors,
+        palette: PaletteSpec::default(),
+        coloring: Coloring::default(),
+        palette_offset: 0.0,
         x_tiles: 10,
         y_tiles: 10,
         zoom,
+        rotation: 0.0,
+        aspect: AspectPolicy::FitWidth,
         name: "sun".to_string(),
+        output: vec![],
     };
 
     (req, zoom_factor, max_zoom_factor)
@@ -388,7 +371,6 @@ pub fn tree(debug: bool) -> (FractalRequest, f64, f64) {
         height = 2160;
     }
 
-    let colors = 256;
 
     let req = FractalRequest {
         center,
@@ -396,12 +378,18 @@ pub fn tree(debug: bool) -> (FractalRequest, f64, f64) {
         height,
         complex_width,
         max_iterations,
-        colors,
+        palette: PaletteSpec::default(),
+        coloring: Coloring::default(),
+        palette_offset: 0.0,
         x_tiles: 10,
         y_tiles: 10,
         zoom,
+        rotation: 0.0,
+        aspect: AspectPolicy::FitWidth,
 
         name: "tree".to_string(),
+
+        output: vec![],
     };
 
     (req, zoom_factor, max_zoom_factor)
@@ -0,0 +1,357 @@
+use serde_derive::{Deserialize, Serialize};
+
+use crate::color::{Color, Hsl, Rgb16};
+
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, Default, PartialEq)]
+pub enum Interpolation {
+    Rgb,
+    Hsl,
+    // perceptually uniform, no muddy grays between complementary colors
+    #[default]
+    OkLab,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct ColorStop {
+    // 0.0 is the start and 1.0 the end of the gradient
+    pub position: f64,
+    pub color: Color,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct Gradient {
+    // sorted by position
+    pub stops: Vec<ColorStop>,
+    #[serde(default)]
+    pub interpolation: Interpolation,
+    // number of iterations for one pass through the gradient
+    #[serde(default = "default_steps")]
+    pub steps: u32,
+}
+
+fn default_steps() -> u32 {
+    256
+}
+
+impl Gradient {
+    pub fn new(colors: &[Color], interpolation: Interpolation, steps: u32) -> Gradient {
+        let last = colors.len().saturating_sub(1).max(1) as f64;
+        let stops = colors
+            .iter()
+            .enumerate()
+            .map(|(idx, color)| ColorStop {
+                position: idx as f64 / last,
+                color: color.clone(),
+            })
+            .collect();
+
+        Gradient {
+            stops,
+            interpolation,
+            steps,
+        }
+    }
+
+    // t outside of 0..1 is clamped to the first or last stop
+    pub fn sample(&self, t: f64) -> Color {
+        match self.segment(t) {
+            Some((from, to, t)) => interpolate(from, to, t, self.interpolation),
+            None => Color::default(),
+        }
+    }
+
+    // like sample, but without rounding to 8 bits
+    pub fn sample16(&self, t: f64) -> Rgb16 {
+        match self.segment(t) {
+            Some((from, to, t)) => interpolate16(from, to, t, self.interpolation),
+            None => Rgb16::default(),
+        }
+    }
+
+    // the two stops around t and the position between them
+    fn segment(&self, t: f64) -> Option<(&Color, &Color, f64)> {
+        let (first, last) = (self.stops.first()?, self.stops.last()?);
+        if t <= first.position {
+            return Some((&first.color, &first.color, 0.0));
+        }
+        if t >= last.position {
+            return Some((&last.color, &last.color, 0.0));
+        }
+
+        let idx = self
+            .stops
+            .iter()
+            .position(|s| s.position > t)
+            .unwrap_or(self.stops.len() - 1);
+        let from = &self.stops[idx - 1];
+        let to = &self.stops[idx];
+        let width = to.position - from.position;
+        let t = if width > 0.0 {
+            (t - from.position) / width
+        } else {
+            1.0
+        };
+
+        Some((&from.color, &to.color, t))
+    }
+
+    // the color for a (possibly fractional) iteration count, repeats every `steps` iterations
+    pub fn color_at(&self, iterations: f64) -> Color {
+        let steps = self.steps.max(1) as f64;
+        self.sample(iterations.rem_euclid(steps) / steps)
+    }
+
+    pub fn color_at16(&self, iterations: f64) -> Rgb16 {
+        let steps = self.steps.max(1) as f64;
+        self.sample16(iterations.rem_euclid(steps) / steps)
+    }
+
+    // `cnt` evenly spaced samples, e.g. to use the gradient like a .MAP palette
+    pub fn to_colors(&self, cnt: usize) -> Vec<Color> {
+        let last = cnt.saturating_sub(1).max(1) as f64;
+        (0..cnt).map(|idx| self.sample(idx as f64 / last)).collect()
+    }
+}
+
+pub fn interpolate(from: &Color, to: &Color, t: f64, interpolation: Interpolation) -> Color {
+    // the conversions to HSL or OKLab and back might be off by one
+    if from == to {
+        return from.clone();
+    }
+    match interpolation {
+        Interpolation::Rgb => Color {
+            r: lerp_u8(from.r, to.r, t),
+            g: lerp_u8(from.g, to.g, t),
+            b: lerp_u8(from.b, to.b, t),
+        },
+        Interpolation::Hsl => Color::from(&interpolate_hsl(from, to, t)),
+        Interpolation::OkLab => Color::from(&interpolate_oklab(from, to, t)),
+    }
+}
+
+pub fn interpolate16(from: &Color, to: &Color, t: f64, interpolation: Interpolation) -> Rgb16 {
+    if from == to {
+        return Rgb16::from(from);
+    }
+    match interpolation {
+        Interpolation::Rgb => {
+            let (from, to) = (Rgb16::from(from), Rgb16::from(to));
+            let lerp_u16 = |from: u16, to: u16| {
+                lerp(from as f64, to as f64, t).round().clamp(0.0, 65535.0) as u16
+            };
+            Rgb16 {
+                r: lerp_u16(from.r, to.r),
+                g: lerp_u16(from.g, to.g),
+                b: lerp_u16(from.b, to.b),
+            }
+        }
+        Interpolation::Hsl => Rgb16::from(&interpolate_hsl(from, to, t)),
+        Interpolation::OkLab => Rgb16::from(&interpolate_oklab(from, to, t)),
+    }
+}
+
+fn interpolate_hsl(from: &Color, to: &Color, t: f64) -> Hsl {
+    let from = Hsl::from(from);
+    let to = Hsl::from(to);
+    // take the shorter way around the color wheel
+    let mut dh = to.h - from.h;
+    if dh > 180.0 {
+        dh -= 360.0;
+    } else if dh < -180.0 {
+        dh += 360.0;
+    }
+    Hsl {
+        h: (from.h + dh * t as f32).rem_euclid(360.0),
+        s: lerp(from.s as f64, to.s as f64, t) as f32,
+        l: lerp(from.l as f64, to.l as f64, t) as f32,
+    }
+}
+
+fn interpolate_oklab(from: &Color, to: &Color, t: f64) -> OkLab {
+    let from = OkLab::from(from);
+    let to = OkLab::from(to);
+    OkLab {
+        l: lerp(from.l, to.l, t),
+        a: lerp(from.a, to.a, t),
+        b: lerp(from.b, to.b, t),
+    }
+}
+
+fn lerp(from: f64, to: f64, t: f64) -> f64 {
+    from + (to - from) * t
+}
+
+fn lerp_u8(from: u8, to: u8, t: f64) -> u8 {
+    lerp(from as f64, to as f64, t).round().clamp(0.0, 255.0) as u8
+}
+
+// https://bottosson.github.io/posts/oklab/
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct OkLab {
+    pub l: f64,
+    pub a: f64,
+    pub b: f64,
+}
+
+impl From<&Color> for OkLab {
+    fn from(c: &Color) -> Self {
+        let r = srgb_to_linear(c.r);
+        let g = srgb_to_linear(c.g);
+        let b = srgb_to_linear(c.b);
+
+        let l = (0.4122214708 * r + 0.5363325363 * g + 0.0514459929 * b).cbrt();
+        let m = (0.2119034982 * r + 0.6806995451 * g + 0.1073969566 * b).cbrt();
+        let s = (0.0883024619 * r + 0.2817188376 * g + 0.6299787005 * b).cbrt();
+
+        OkLab {
+            l: 0.2104542553 * l + 0.7936177850 * m - 0.0040720468 * s,
+            a: 1.9779984951 * l - 2.4285922050 * m + 0.4505937099 * s,
+            b: 0.0259040371 * l + 0.7827717662 * m - 0.8086757660 * s,
+        }
+    }
+}
+
+impl From<&OkLab> for Color {
+    fn from(lab: &OkLab) -> Self {
+        let [r, g, b] = lab.to_linear_rgb();
+        Color {
+            r: linear_to_srgb(r),
+            g: linear_to_srgb(g),
+            b: linear_to_srgb(b),
+        }
+    }
+}
+
+impl From<&OkLab> for Rgb16 {
+    fn from(lab: &OkLab) -> Self {
+        let [r, g, b] = lab.to_linear_rgb();
+        Rgb16::from_unit(linear_to_unit(r), linear_to_unit(g), linear_to_unit(b))
+    }
+}
+
+impl OkLab {
+    fn to_linear_rgb(self) -> [f64; 3] {
+        let l = (self.l + 0.3963377774 * self.a + 0.2158037573 * self.b).powi(3);
+        let m = (self.l - 0.1055613458 * self.a - 0.0638541728 * self.b).powi(3);
+        let s = (self.l - 0.0894841775 * self.a - 1.2914855480 * self.b).powi(3);
+        [
+            4.0767416621 * l - 3.3077115913 * m + 0.2309699292 * s,
+            -1.2684380046 * l + 2.6097574011 * m - 0.3413193965 * s,
+            -0.0041960863 * l - 0.7034186147 * m + 1.7076147010 * s,
+        ]
+    }
+}
+
+fn srgb_to_linear(c: u8) -> f64 {
+    let c = c as f64 / 255.0;
+    if c <= 0.04045 {
+        c / 12.92
+    } else {
+        ((c + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+fn linear_to_srgb(c: f64) -> u8 {
+    (linear_to_unit(c) * 255.0).round().clamp(0.0, 255.0) as u8
+}
+
+// the gamma encoded value in 0..1
+fn linear_to_unit(c: f64) -> f64 {
+    if c <= 0.0031308 {
+        12.92 * c
+    } else {
+        1.055 * c.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::color::{Color, BLACK, BLUE, RED};
+    use crate::gradient::{interpolate, Gradient, Interpolation, OkLab};
+
+    const WHITE: Color = Color {
+        r: 255,
+        g: 255,
+        b: 255,
+    };
+
+    #[test]
+    fn test_oklab_roundtrip() {
+        for c in [
+            BLACK,
+            RED,
+            BLUE,
+            WHITE,
+            Color {
+                r: 12,
+                g: 200,
+                b: 99,
+            },
+        ] {
+            assert_eq!(Color::from(&OkLab::from(&c)), c);
+        }
+    }
+
+    #[test]
+    fn test_sample_stops() {
+        for interpolation in [Interpolation::Rgb, Interpolation::Hsl, Interpolation::OkLab] {
+            let g = Gradient::new(&[RED, BLUE, WHITE], interpolation, 100);
+            assert_eq!(g.sample(-1.0), RED);
+            assert_eq!(g.sample(0.0), RED);
+            assert_eq!(g.sample(0.5), BLUE);
+            assert_eq!(g.sample(1.0), WHITE);
+            assert_eq!(g.sample(2.0), WHITE);
+        }
+    }
+
+    #[test]
+    fn test_interpolate() {
+        let c = interpolate(&BLACK, &WHITE, 0.5, Interpolation::Rgb);
+        assert_eq!(
+            c,
+            Color {
+                r: 128,
+                g: 128,
+                b: 128
+            }
+        );
+
+        // red -> blue goes through magenta, not through green
+        let c = interpolate(&RED, &BLUE, 0.5, Interpolation::Hsl);
+        assert_eq!(
+            c,
+            Color {
+                r: 255,
+                g: 0,
+                b: 255
+            }
+        );
+
+        // oklab lightness is perceptual, its middle gray is darker than the rgb average
+        let c = interpolate(&BLACK, &WHITE, 0.5, Interpolation::OkLab);
+        assert_eq!(
+            c,
+            Color {
+                r: 99,
+                g: 99,
+                b: 99
+            }
+        );
+    }
+
+    #[test]
+    fn test_color_at_repeats() {
+        let g = Gradient::new(&[RED, BLUE], Interpolation::Rgb, 10);
+        assert_eq!(g.color_at(0.0), RED);
+        assert_eq!(g.color_at(5.0), g.color_at(15.0));
+        assert_eq!(g.to_colors(3), vec![RED, g.sample(0.5), BLUE]);
+    }
+
+    #[test]
+    fn test_json() {
+        let json = r#"{"stops":[{"position":0.0,"color":{"r":255,"g":0,"b":0}}]}"#;
+        let g: Gradient = serde_json::from_str(json).unwrap();
+        assert_eq!(g.interpolation, Interpolation::OkLab);
+        assert_eq!(g.steps, 256);
+    }
+}
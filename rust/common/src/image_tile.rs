@@ -6,9 +6,10 @@ use serde_derive::{Deserialize, Serialize};
 use crate::color::Color;
 
 pub fn tiles(width: u32, height: u32, x_tiles: u32, y_tiles: u32) -> CanvasTile {
+    // a tile is at least 1 pixel wide and high, otherwise the iterator would never end
     let c = CanvasTile {
-        x_inc: (width / x_tiles) as usize,
-        y_inc: (height / y_tiles) as usize,
+        x_inc: (width / x_tiles.max(1)).max(1) as usize,
+        y_inc: (height / y_tiles.max(1)).max(1) as usize,
         width: width as usize,
         height: height as usize,
         x: 0,
@@ -273,6 +274,12 @@ mod tests {
         assert_eq!(it.next(), None);
     }
 
+    #[test]
+    fn test_iterator_more_tiles_than_pixels() {
+        let it = tiles(3, 2, 0, 5);
+        assert_eq!(it.count(), 2);
+    }
+
     #[test]
     fn test_iterator2() {
         let mut it = tiles(9, 9, 3, 3);
@@ -0,0 +1,239 @@
+use std::io;
+use std::io::Write;
+
+use rayon::prelude::*;
+use serde_derive::{Deserialize, Serialize};
+
+use crate::color::{Color, Rgb16, BLACK};
+use crate::coloring::{Coloring, Palette};
+use crate::error::{FractalError, FractalResult};
+use crate::fractal::calc_fractal_iterations;
+use crate::render_engine::RenderParams;
+
+// pixels inside the set in the PFM output and the iteration tiles. smooth iterations can be
+// negative for points that escape at once, so no number is free to mark them
+pub const ITERATIONS_INSIDE: f32 = f32::NAN;
+
+// the iteration value of every pixel, None for pixels inside the set.
+// recoloring it is much cheaper than calculating the image again
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct IterationBuffer {
+    pub width: u32,
+    pub height: u32,
+    pub coloring: Coloring,
+    pub values: Vec<Option<f64>>,
+}
+
+impl IterationBuffer {
+    pub fn calc(params: &RenderParams) -> IterationBuffer {
+        let values = (0..params.height)
+            .into_par_iter()
+            .flat_map_iter(|y| {
+                (0..params.width).map(move |x| calc_fractal_iterations(x, y, params))
+            })
+            .collect();
+
+        IterationBuffer {
+            width: params.width,
+            height: params.height,
+            coloring: params.coloring,
+            values,
+        }
+    }
+
+    // the same pixels an engine calculates with this palette and offset
+    pub fn colorize(&self, palette: &Palette, palette_offset: f64) -> Vec<Color> {
+        self.values
+            .par_iter()
+            .map(|value| match value {
+                Some(value) => palette.color(self.coloring, value + palette_offset),
+                None => BLACK,
+            })
+            .collect()
+    }
+
+    pub fn colorize16(&self, palette: &Palette, palette_offset: f64) -> Vec<Rgb16> {
+        self.values
+            .par_iter()
+            .map(|value| match value {
+                Some(value) => palette.color16(self.coloring, value + palette_offset),
+                None => Rgb16::default(),
+            })
+            .collect()
+    }
+
+    // grayscale PFM (http://www.pauldebevec.com/Research/HDR/PFM/) with one f32 per pixel,
+    // little endian and bottom row first. pixels inside the set are written as NaN
+    pub fn write_pfm<W: Write>(&self, mut w: W) -> io::Result<()> {
+        write!(w, "Pf\n{} {}\n-1.0\n", self.width, self.height)?;
+        for row in self.values.chunks(self.width.max(1) as usize).rev() {
+            let bytes: Vec<u8> = row
+                .iter()
+                .flat_map(|value| value.map_or(ITERATIONS_INSIDE, |v| v as f32).to_le_bytes())
+                .collect();
+            w.write_all(&bytes)?;
+        }
+        w.flush()
+    }
+
+    pub fn read_pfm(data: &[u8]) -> FractalResult<IterationBuffer> {
+        let invalid = |message: &str| FractalError::InvalidRequest(format!("invalid PFM: {}", message));
+
+        // four whitespace separated header fields: "Pf", width, height and scale, then a single
+        // whitespace and the data
+        let mut fields = vec![];
+        let mut pos = 0;
+        while fields.len() < 4 {
+            let start = pos;
+            while pos < data.len() && !data[pos].is_ascii_whitespace() {
+                pos += 1;
+            }
+            if pos >= data.len() {
+                return Err(invalid("header is incomplete"));
+            }
+            fields.push(String::from_utf8_lossy(&data[start..pos]).to_string());
+            pos += 1;
+        }
+
+        if fields[0] != "Pf" {
+            return Err(invalid("only grayscale 'Pf' files are supported"));
+        }
+        let width: u32 = fields[1].parse().map_err(|_| invalid("width is not a number"))?;
+        let height: u32 = fields[2].parse().map_err(|_| invalid("height is not a number"))?;
+        let scale: f32 = fields[3].parse().map_err(|_| invalid("scale is not a number"))?;
+
+        let pixels = &data[pos..];
+        if pixels.len() != width as usize * height as usize * 4 {
+            return Err(invalid("size of the data doesn't match width and height"));
+        }
+        let from_bytes = |b: &[u8]| {
+            let b = [b[0], b[1], b[2], b[3]];
+            if scale < 0.0 {
+                f32::from_le_bytes(b)
+            } else {
+                f32::from_be_bytes(b)
+            }
+        };
+        let mut rows: Vec<Vec<Option<f64>>> = pixels
+            .chunks(width.max(1) as usize * 4)
+            .map(|row| {
+                row.chunks(4)
+                    .map(|b| Some(from_bytes(b) as f64).filter(|v| !v.is_nan()))
+                    .collect()
+            })
+            .collect();
+        rows.reverse();
+
+        Ok(IterationBuffer {
+            width,
+            height,
+            coloring: Coloring::Smooth,
+            values: rows.into_iter().flatten().collect(),
+        })
+    }
+
+    // palette cycling: frame i uses the palette shifted by palette_offset + i * step. the frames
+    // are colored one at a time, so an animation never has to be in memory at once
+    pub fn cycle<'a>(
+        &'a self,
+        palette: &'a Palette,
+        palette_offset: f64,
+        frames: u32,
+        step: f64,
+    ) -> impl Iterator<Item = Vec<Color>> + 'a {
+        (0..frames).map(move |frame| self.colorize(palette, palette_offset + frame as f64 * step))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::color::{color16, Rgb16};
+    use crate::coloring::{Coloring, Palette};
+    use crate::fractal_templates::basic;
+    use crate::iteration_buffer::IterationBuffer;
+    use crate::render_engine::{engine_by_name, RenderParams};
+
+    fn params(coloring: Coloring, palette_offset: f64) -> RenderParams {
+        let (mut req, _, _) = basic(true);
+        req.width = 64;
+        req.height = 48;
+        req.max_iterations = 200;
+        req.coloring = coloring;
+        req.palette_offset = palette_offset;
+        RenderParams::new(&req, Palette::Colors(color16()))
+    }
+
+    #[test]
+    fn test_colorize_matches_engine() {
+        for coloring in [Coloring::Modulo, Coloring::Smooth] {
+            let params = params(coloring, 3.0);
+            let engine = engine_by_name("singlethreaded").unwrap();
+            let expected = engine.calc(&params).unwrap();
+
+            let buffer = IterationBuffer::calc(&params);
+            assert_eq!(buffer.values.len(), 64 * 48);
+            assert!(buffer.colorize(&params.palette, 3.0) == expected);
+        }
+    }
+
+    #[test]
+    fn test_colorize16_matches_colorize() {
+        let modulo = params(Coloring::Modulo, 0.0);
+        let buffer = IterationBuffer::calc(&modulo);
+        let colors = buffer.colorize(&modulo.palette, 0.0);
+        let colors16 = buffer.colorize16(&modulo.palette, 0.0);
+        assert!(colors.iter().zip(colors16).all(|(c, c16)| Rgb16::from(c) == c16));
+
+        // smooth coloring has values between the 8 bit colors
+        let smooth = params(Coloring::Smooth, 0.0);
+        let buffer = IterationBuffer::calc(&smooth);
+        let colors16 = buffer.colorize16(&smooth.palette, 0.0);
+        assert!(colors16.iter().any(|c| c.r % 257 != 0));
+    }
+
+    #[test]
+    fn test_pfm_round_trip() {
+        let params = params(Coloring::Smooth, 0.0);
+        let buffer = IterationBuffer::calc(&params);
+
+        let mut pfm = vec![];
+        buffer.write_pfm(&mut pfm).unwrap();
+        assert!(pfm.starts_with(b"Pf\n64 48\n-1.0\n"));
+        assert_eq!(pfm.len(), 14 + 64 * 48 * 4);
+
+        let read = IterationBuffer::read_pfm(&pfm).unwrap();
+        assert_eq!((read.width, read.height), (64, 48));
+        for (value, read) in buffer.values.iter().zip(read.values) {
+            assert_eq!(value.map(|v| v as f32), read.map(|v| v as f32));
+        }
+        assert!(IterationBuffer::read_pfm(b"P6\n1 1\n255\n").is_err());
+        assert!(IterationBuffer::read_pfm(b"Pf\n2 2\n-1.0\n1234").is_err());
+    }
+
+    #[test]
+    fn test_pfm_negative_values() {
+        // smooth iterations of points far outside the set are negative, they are not inside
+        let buffer = IterationBuffer {
+            width: 2,
+            height: 2,
+            coloring: Coloring::Smooth,
+            values: vec![Some(-1.0), None, Some(-0.73), Some(0.0)],
+        };
+        let mut pfm = vec![];
+        buffer.write_pfm(&mut pfm).unwrap();
+        let read = IterationBuffer::read_pfm(&pfm).unwrap();
+        let values: Vec<Option<f32>> = read.values.iter().map(|v| v.map(|v| v as f32)).collect();
+        assert_eq!(values, vec![Some(-1.0), None, Some(-0.73), Some(0.0)]);
+    }
+
+    #[test]
+    fn test_cycle() {
+        let params = params(Coloring::Modulo, 0.0);
+        let buffer = IterationBuffer::calc(&params);
+        let frames: Vec<_> = buffer.cycle(&params.palette, 0.0, 17, 1.0).collect();
+        assert_eq!(frames.len(), 17);
+        // 16 colors, so the palette repeats after 16 frames
+        assert!(frames[0] == frames[16]);
+        assert!(frames[0] != frames[1]);
+    }
+}
@@ -1,11 +1,26 @@
+pub mod animated_image;
+pub mod animation;
 pub mod color;
+pub mod coloring;
 pub mod complex;
+pub mod error;
+pub mod exp_map;
 pub mod fractal;
 pub mod fractal_image;
+pub mod gradient;
 pub mod image_tile;
+pub mod iteration_buffer;
 pub mod models;
+pub mod output;
+pub mod png_metadata;
 pub mod rayon_image;
+pub mod render_engine;
+pub mod strip_render;
+pub mod tile_frame;
+pub mod tile_pyramid;
 pub mod utils;
+pub mod validation;
+pub mod viewport;
 
 pub mod fractal_calculation_crossbeam;
 pub mod fractal_calculation_multi;
@@ -1,8 +1,17 @@
 use serde_derive::{Deserialize, Serialize};
 
+use crate::animated_image::AnimationFormat;
+use crate::color::Color;
+use crate::coloring::{Coloring, Palette};
 use crate::complex::ComplexNumber;
+use crate::error::{FractalError, FractalResult};
 use crate::fractal_image::FractalImage;
-use crate::image_tile::TileData;
+use crate::gradient::Gradient;
+use crate::iteration_buffer::IterationBuffer;
+use crate::output::OutputFormat;
+use crate::tile_frame::{TileEncoding, TileFrame};
+use crate::validation::FieldError;
+use crate::viewport::{AspectPolicy, Viewport};
 
 #[derive(Deserialize, Serialize, Clone, Debug, Default, PartialEq)]
 pub struct FractalRequest {
@@ -11,11 +20,147 @@ pub struct FractalRequest {
     pub height: u32,
     pub complex_width: f64,
     pub max_iterations: u32,
-    pub colors: u32,
+    pub palette: PaletteSpec,
+    #[serde(default)]
+    pub coloring: Coloring,
+    // shifts the palette by this many colors, used for palette cycling
+    #[serde(default)]
+    pub palette_offset: f64,
     pub x_tiles: u32,
     pub y_tiles: u32,
     pub zoom: f64,
+    // degrees counterclockwise around the center
+    #[serde(default)]
+    pub rotation: f64,
+    // how the square of complex_width / zoom is fitted into the image
+    #[serde(default)]
+    pub aspect: AspectPolicy,
     pub name: String,
+    // the formats saved to the output sink, nothing is saved by default
+    #[serde(default)]
+    pub output: Vec<OutputFormat>,
+}
+
+// either the name of a .MAP file in the palette directory, e.g. "basic.map",
+// the colors themselves or a gradient
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+#[serde(untagged)]
+pub enum PaletteSpec {
+    Name(String),
+    Colors(Vec<Color>),
+    Gradient(Gradient),
+}
+
+impl From<Palette> for PaletteSpec {
+    fn from(palette: Palette) -> Self {
+        match palette {
+            Palette::Colors(colors) => PaletteSpec::Colors(colors),
+            Palette::Gradient(gradient) => PaletteSpec::Gradient(gradient),
+        }
+    }
+}
+
+impl Default for PaletteSpec {
+    fn default() -> Self {
+        PaletteSpec::Name("basic.map".to_string())
+    }
+}
+
+// POST api/render, a FractalRequest with the engine next to the other fields
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct RenderRequest {
+    #[serde(default = "default_engine")]
+    pub engine: String,
+    #[serde(flatten)]
+    pub request: FractalRequest,
+}
+
+fn default_engine() -> String {
+    "rayon".to_string()
+}
+
+// palette cycling. the request is rendered again, a finished render can't be passed in
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct CycleRequest {
+    pub request: FractalRequest,
+    pub frames: u32,
+    // the palette offset is increased by step for every frame
+    #[serde(default = "default_cycle_step")]
+    pub step: f64,
+    #[serde(default = "default_cycle_delay_ms")]
+    pub delay_ms: u32,
+    #[serde(default)]
+    pub format: AnimationFormat,
+}
+
+fn default_cycle_step() -> f64 {
+    1.0
+}
+
+fn default_cycle_delay_ms() -> u32 {
+    40
+}
+
+// everything the frontend needs to recolor an image itself. it is sent as binary, json numbers
+// for every pixel would be many times bigger:
+//
+//   0      u32      length n of the header, little endian
+//   4      n bytes  IterationHeader as json
+//   4 + n           the iterations as one deflated TileFrame of the whole image, f32 per pixel
+#[derive(Clone, Debug, PartialEq)]
+pub struct IterationResponse {
+    pub iterations: IterationBuffer,
+    // never a Name, the palette is already resolved
+    pub palette: PaletteSpec,
+    pub palette_offset: f64,
+}
+
+#[derive(Deserialize, Serialize)]
+struct IterationHeader {
+    palette: PaletteSpec,
+    palette_offset: f64,
+    coloring: Coloring,
+}
+
+impl IterationResponse {
+    pub fn encode(&self) -> FractalResult<Vec<u8>> {
+        let header = serde_json::to_vec(&IterationHeader {
+            palette: self.palette.clone(),
+            palette_offset: self.palette_offset,
+            coloring: self.iterations.coloring,
+        })?;
+        let frame = TileFrame::from_buffer(&self.iterations).encode(true);
+        let mut data = Vec::with_capacity(4 + header.len() + frame.len());
+        data.extend((header.len() as u32).to_le_bytes());
+        data.extend(header);
+        data.extend(frame);
+        Ok(data)
+    }
+
+    pub fn decode(data: &[u8]) -> FractalResult<IterationResponse> {
+        let invalid = || FractalError::InvalidRequest("not an iteration response".to_string());
+        let len = data
+            .get(0..4)
+            .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]) as usize)
+            .ok_or_else(invalid)?;
+        let end = len
+            .checked_add(4)
+            .filter(|end| *end <= data.len())
+            .ok_or_else(invalid)?;
+        let header: IterationHeader = serde_json::from_slice(&data[4..end])?;
+        let frame = TileFrame::decode(&data[end..])?;
+        let values = frame.iterations().ok_or_else(invalid)?;
+        Ok(IterationResponse {
+            iterations: IterationBuffer {
+                width: frame.width,
+                height: frame.height,
+                coloring: header.coloring,
+                values,
+            },
+            palette: header.palette,
+            palette_offset: header.palette_offset,
+        })
+    }
 }
 
 #[derive(Deserialize, Serialize, Clone, Debug, Default, PartialEq)]
@@ -25,17 +170,143 @@ pub struct FractalResponse {
     pub fractal: FractalImage,
 }
 
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum JobStatus {
+    Queued,
+    Running,
+    Finished,
+    Failed,
+    Cancelled,
+}
+
+impl JobStatus {
+    pub fn is_done(&self) -> bool {
+        matches!(
+            self,
+            JobStatus::Finished | JobStatus::Failed | JobStatus::Cancelled
+        )
+    }
+}
+
+// the answer of POST api/jobs and GET api/jobs/<id>
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct JobInfo {
+    pub id: u64,
+    pub status: JobStatus,
+    // the calculated rows, from 0 to 1
+    pub progress: f64,
+    pub width: u32,
+    pub height: u32,
+    #[serde(default)]
+    pub duration_ms: Option<u128>,
+    #[serde(default)]
+    pub error: Option<String>,
+}
+
+// the commands of a session on api/crossbeamtiles. a new render cancels the one before
 #[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
 pub enum WebSocketCommand {
     RENDERFRACTAL(FractalRequest),
+    // the last request with another center, zoom, rotation or size
+    RERENDER(Viewport),
+    // the last finished render with another palette, the iterations are not calculated again
+    RECOLOR(RecolorRequest),
+    CANCEL,
+    PING,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, Default, PartialEq)]
+pub struct RecolorRequest {
+    #[serde(default)]
+    pub palette: Option<PaletteSpec>,
+    #[serde(default)]
+    pub palette_offset: Option<f64>,
 }
 
 #[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
 pub struct WebSocketRequest {
     pub command: WebSocketCommand,
+    // the tiles are sent as binary TileFrames. only read with RENDERFRACTAL, the other commands
+    // keep the encoding of the session
+    #[serde(default)]
+    pub encoding: TileEncoding,
+    #[serde(default)]
+    pub compress: bool,
 }
 
-#[derive(Deserialize, Serialize, Debug)]
-pub struct WebSocketResponse {
-    pub tile: Option<TileData>,
+// the text messages of api/crossbeamtiles, the tiles themselves are binary. all tiles between
+// RENDER_STARTED and RENDER_DONE belong to that render
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum WebSocketEvent {
+    RenderStarted {
+        id: u32,
+        width: u32,
+        height: u32,
+        tiles: u32,
+    },
+    Progress {
+        id: u32,
+        tiles_done: u32,
+        tiles: u32,
+    },
+    RenderDone {
+        id: u32,
+        duration_ms: u128,
+        cancelled: bool,
+    },
+    // id is None if the command could not be read
+    Error {
+        id: Option<u32>,
+        message: String,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        fields: Vec<FieldError>,
+    },
+    Pong,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, Default, PartialEq)]
+pub struct ErrorResponse {
+    pub status: u16,
+    pub error: String,
+    pub message: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub fields: Vec<FieldError>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct PaletteInfo {
+    pub name: String,
+    // number of colors or gradient stops
+    pub colors: usize,
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::color::{BLUE, RED};
+    use crate::coloring::Coloring;
+    use crate::iteration_buffer::IterationBuffer;
+    use crate::models::{IterationResponse, PaletteSpec};
+
+    #[test]
+    fn test_iteration_response() {
+        let response = IterationResponse {
+            iterations: IterationBuffer {
+                width: 3,
+                height: 2,
+                coloring: Coloring::Smooth,
+                values: vec![Some(1.5), None, Some(-0.25), Some(0.0), Some(200.0), None],
+            },
+            palette: PaletteSpec::Colors(vec![RED, BLUE]),
+            palette_offset: 2.0,
+        };
+        let data = response.encode().unwrap();
+        assert_eq!(IterationResponse::decode(&data).unwrap(), response);
+
+        assert!(IterationResponse::decode(&data[..3]).is_err());
+        assert!(IterationResponse::decode(&data[..20]).is_err());
+        let mut len = data.clone();
+        len[0..4].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(IterationResponse::decode(&len).is_err());
+    }
 }
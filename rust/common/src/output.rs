@@ -0,0 +1,366 @@
+use std::fs;
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::{Mutex, OnceLock};
+use std::time::Instant;
+
+use chrono::Utc;
+use image::codecs::jpeg::JpegEncoder;
+use log::info;
+use serde_derive::{Deserialize, Serialize};
+
+use crate::color::Color;
+use crate::error::{FractalError, FractalResult};
+use crate::iteration_buffer::IterationBuffer;
+use crate::models::FractalRequest;
+use crate::png_metadata::{write_png16_with_request, write_png_with_request};
+use crate::render_engine::RenderParams;
+
+pub const JPEG_QUALITY: u8 = 90;
+
+// the old file names, placeholders are replaced by the values of the request
+pub const DEFAULT_FILE_NAME_TEMPLATE: &str =
+    "{name}/{timestamp}_{name}___{width}x{height}_zoom_{zoom}_max_iter_{max_iterations}";
+
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum OutputFormat {
+    // 8 bits per channel, the pixels the engine calculated
+    #[default]
+    Png,
+    // 16 bits per channel, smooth coloring without banding
+    Png16,
+    // the raw iteration values as 32 bit floats, for grading in other tools
+    Pfm,
+    Jpeg,
+    // lossless
+    WebP,
+    // binary P6, easy to read for other programs
+    Ppm,
+}
+
+impl OutputFormat {
+    // appended to the file name, so all outputs of a render sit next to each other
+    pub fn suffix(&self) -> &'static str {
+        match self {
+            OutputFormat::Png => ".png",
+            OutputFormat::Png16 => "_16bit.png",
+            OutputFormat::Pfm => ".pfm",
+            OutputFormat::Jpeg => ".jpg",
+            OutputFormat::WebP => ".webp",
+            OutputFormat::Ppm => ".ppm",
+        }
+    }
+
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            OutputFormat::Png | OutputFormat::Png16 => "image/png",
+            OutputFormat::Pfm => "image/x-portable-floatmap",
+            OutputFormat::Jpeg => "image/jpeg",
+            OutputFormat::WebP => "image/webp",
+            OutputFormat::Ppm => "image/x-portable-pixmap",
+        }
+    }
+
+    // the 8 bit pixels of the engine are not enough, the iterations are calculated again
+    pub fn needs_iterations(&self) -> bool {
+        matches!(self, OutputFormat::Png16 | OutputFormat::Pfm)
+    }
+}
+
+// where the encoded images go. file_name is relative, e.g. "basic/1700000000000_basic.png"
+pub trait OutputSink: Send + Sync {
+    fn write(&self, file_name: &str, data: &[u8]) -> FractalResult<()>;
+}
+
+// drops everything
+pub struct NoOutput;
+
+impl OutputSink for NoOutput {
+    fn write(&self, _file_name: &str, _data: &[u8]) -> FractalResult<()> {
+        Ok(())
+    }
+}
+
+pub struct DirectoryOutput {
+    pub dir: PathBuf,
+}
+
+impl DirectoryOutput {
+    pub fn new(dir: PathBuf) -> DirectoryOutput {
+        DirectoryOutput { dir }
+    }
+}
+
+// the images directory next to the rust workspace
+impl Default for DirectoryOutput {
+    fn default() -> Self {
+        DirectoryOutput::new(PathBuf::from(format!(
+            "{}/../../images",
+            env!("CARGO_MANIFEST_DIR")
+        )))
+    }
+}
+
+impl OutputSink for DirectoryOutput {
+    fn write(&self, file_name: &str, data: &[u8]) -> FractalResult<()> {
+        let path = self.dir.join(file_name);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)
+                .map_err(|e| FractalError::io(&parent.to_string_lossy(), e))?;
+        }
+        fs::write(&path, data).map_err(|e| FractalError::io(&path.to_string_lossy(), e))
+    }
+}
+
+// keeps the files, for tests and programs that want the encoded bytes
+#[derive(Default)]
+pub struct MemoryOutput {
+    files: Mutex<Vec<(String, Vec<u8>)>>,
+}
+
+impl MemoryOutput {
+    pub fn take(&self) -> Vec<(String, Vec<u8>)> {
+        std::mem::take(&mut *self.files.lock().unwrap())
+    }
+}
+
+impl OutputSink for MemoryOutput {
+    fn write(&self, file_name: &str, data: &[u8]) -> FractalResult<()> {
+        self.files
+            .lock()
+            .unwrap()
+            .push((file_name.to_string(), data.to_vec()));
+        Ok(())
+    }
+}
+
+struct Output {
+    sink: Box<dyn OutputSink>,
+    template: String,
+}
+
+static OUTPUT: OnceLock<Output> = OnceLock::new();
+
+// has to be called before the first image is saved, afterwards the sink can't be changed
+pub fn set_output_sink(sink: Box<dyn OutputSink>, template: &str) -> FractalResult<()> {
+    let output = Output {
+        sink,
+        template: template.to_string(),
+    };
+    OUTPUT
+        .set(output)
+        .map_err(|_| FractalError::Config("the output sink is already in use".to_string()))
+}
+
+fn output() -> &'static Output {
+    OUTPUT.get_or_init(|| Output {
+        sink: Box::new(DirectoryOutput::default()),
+        template: DEFAULT_FILE_NAME_TEMPLATE.to_string(),
+    })
+}
+
+// writes every format of req.output to the configured sink, nothing if the request has no output
+pub fn save_outputs(
+    pixels: &[Color],
+    params: &RenderParams,
+    req: &FractalRequest,
+) -> FractalResult<()> {
+    if req.output.is_empty() {
+        return Ok(());
+    }
+    let output = output();
+    save_outputs_to(output.sink.as_ref(), &output.template, pixels, params, req)
+}
+
+pub fn save_outputs_to(
+    sink: &dyn OutputSink,
+    template: &str,
+    pixels: &[Color],
+    params: &RenderParams,
+    req: &FractalRequest,
+) -> FractalResult<()> {
+    let start = Instant::now();
+    let base = file_name(template, params, req, Utc::now().timestamp_millis());
+
+    // every format once, in the order of the request
+    let mut formats: Vec<OutputFormat> = vec![];
+    for format in &req.output {
+        if !formats.contains(format) {
+            formats.push(*format);
+        }
+    }
+    let iterations = formats
+        .iter()
+        .any(|f| f.needs_iterations())
+        .then(|| IterationBuffer::calc(params));
+
+    for format in formats {
+        let data = encode_output(format, pixels, params, req, iterations.as_ref())?;
+        sink.write(&format!("{}{}", base, format.suffix()), &data)?;
+    }
+
+    let duration = start.elapsed().as_millis();
+    info!("save ok. took {} ms", duration);
+    Ok(())
+}
+
+pub fn file_name(
+    template: &str,
+    params: &RenderParams,
+    req: &FractalRequest,
+    timestamp: i64,
+) -> String {
+    template
+        .replace("{name}", &req.name)
+        .replace("{timestamp}", &timestamp.to_string())
+        .replace("{width}", &params.width.to_string())
+        .replace("{height}", &params.height.to_string())
+        .replace("{zoom}", &params.zoom.to_string())
+        .replace("{max_iterations}", &params.max_iterations.to_string())
+}
+
+// iterations are only needed for the formats with needs_iterations()
+pub fn encode_output(
+    format: OutputFormat,
+    pixels: &[Color],
+    params: &RenderParams,
+    req: &FractalRequest,
+    iterations: Option<&IterationBuffer>,
+) -> FractalResult<Vec<u8>> {
+    let (width, height) = (params.width, params.height);
+    let iterations = || {
+        iterations.ok_or_else(|| {
+            FractalError::Render(format!("{:?} output needs the iterations", format))
+        })
+    };
+    let rgb = || -> Vec<u8> { pixels.iter().flat_map(|c| [c.r, c.g, c.b]).collect() };
+
+    let mut data = vec![];
+    match format {
+        OutputFormat::Png => write_png_with_request(&mut data, pixels, width, height, req)?,
+        OutputFormat::Png16 => {
+            let pixels = iterations()?.colorize16(&params.palette, params.palette_offset);
+            write_png16_with_request(&mut data, &pixels, width, height, req)?
+        }
+        OutputFormat::Pfm => iterations()?
+            .write_pfm(&mut data)
+            .map_err(|e| FractalError::Encoding(e.to_string()))?,
+        OutputFormat::Jpeg => JpegEncoder::new_with_quality(&mut data, JPEG_QUALITY).encode(
+            &rgb(),
+            width,
+            height,
+            image::ColorType::Rgb8,
+        )?,
+        OutputFormat::WebP => image_webp::WebPEncoder::new(&mut data)
+            .encode(&rgb(), width, height, image_webp::ColorType::Rgb8)
+            .map_err(|e| FractalError::Encoding(e.to_string()))?,
+        OutputFormat::Ppm => {
+            write!(data, "P6\n{} {}\n255\n", width, height)
+                .map_err(|e| FractalError::Encoding(e.to_string()))?;
+            data.extend(rgb());
+        }
+    }
+    Ok(data)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::color::color16;
+    use crate::coloring::{Coloring, Palette};
+    use crate::fractal_templates::basic;
+    use crate::models::FractalRequest;
+    use crate::output::{
+        file_name, save_outputs_to, MemoryOutput, OutputFormat, DEFAULT_FILE_NAME_TEMPLATE,
+    };
+    use crate::png_metadata::read_request;
+    use crate::render_engine::{engine_by_name, RenderParams};
+
+    fn request(output: Vec<OutputFormat>) -> FractalRequest {
+        let (mut req, _, _) = basic(true);
+        req.width = 32;
+        req.height = 24;
+        req.max_iterations = 100;
+        req.coloring = Coloring::Smooth;
+        req.output = output;
+        req
+    }
+
+    #[test]
+    fn test_file_name() {
+        let req = request(vec![]);
+        let params = RenderParams::new(&req, Palette::Colors(color16()));
+        assert_eq!(
+            file_name(DEFAULT_FILE_NAME_TEMPLATE, &params, &req, 1234),
+            "basic/1234_basic___32x24_zoom_0.7_max_iter_100"
+        );
+        assert_eq!(file_name("{name}-{width}", &params, &req, 1234), "basic-32");
+    }
+
+    #[test]
+    fn test_all_formats_decode() {
+        let req = request(vec![
+            OutputFormat::Png,
+            OutputFormat::Png16,
+            OutputFormat::Pfm,
+            OutputFormat::Jpeg,
+            OutputFormat::WebP,
+            OutputFormat::Ppm,
+        ]);
+        let params = RenderParams::new(&req, Palette::Colors(color16()));
+        let pixels = engine_by_name("rayon").unwrap().calc(&params).unwrap();
+
+        let sink = MemoryOutput::default();
+        save_outputs_to(&sink, "{name}", &pixels, &params, &req).unwrap();
+        let files = sink.take();
+        let names: Vec<&str> = files.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(
+            names,
+            vec![
+                "basic.png",
+                "basic_16bit.png",
+                "basic.pfm",
+                "basic.jpg",
+                "basic.webp",
+                "basic.ppm"
+            ]
+        );
+
+        for (name, data) in &files {
+            if name.ends_with(".pfm") {
+                assert!(data.starts_with(b"Pf\n32 24\n"));
+                continue;
+            }
+            let image = image::load_from_memory(data).unwrap();
+            assert_eq!((image.width(), image.height()), (32, 24), "{}", name);
+        }
+        assert_eq!(read_request(&files[0].1).unwrap(), req);
+
+        // lossless formats have the pixels of the engine
+        for idx in [0, 4, 5] {
+            let image = image::load_from_memory(&files[idx].1).unwrap().to_rgb8();
+            let p = &pixels[3 * 32 + 7];
+            assert_eq!(image.get_pixel(7, 3).0, [p.r, p.g, p.b], "{}", files[idx].0);
+        }
+    }
+
+    #[test]
+    fn test_duplicate_formats() {
+        let req = request(vec![OutputFormat::Png, OutputFormat::Ppm, OutputFormat::Png]);
+        let params = RenderParams::new(&req, Palette::Colors(color16()));
+        let pixels = engine_by_name("rayon").unwrap().calc(&params).unwrap();
+
+        let sink = MemoryOutput::default();
+        save_outputs_to(&sink, "{name}", &pixels, &params, &req).unwrap();
+        let names: Vec<String> = sink.take().into_iter().map(|(name, _)| name).collect();
+        assert_eq!(names, vec!["basic.png", "basic.ppm"]);
+    }
+
+    #[test]
+    fn test_no_output_requested() {
+        let req = request(vec![]);
+        let params = RenderParams::new(&req, Palette::Colors(color16()));
+        let sink = MemoryOutput::default();
+        save_outputs_to(&sink, "{name}", &[], &params, &req).unwrap();
+        assert!(sink.take().is_empty());
+    }
+}
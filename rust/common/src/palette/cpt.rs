@@ -0,0 +1,156 @@
+use crate::color::Color;
+use crate::error::FractalResult;
+use crate::gradient::{ColorStop, Gradient, Interpolation};
+use crate::palette::invalid_palette;
+
+// GMT color table. every line is "z0 r g b z1 r g b [label]", colors can also be written as r/g/b.
+// the z values are scaled to 0.0 .. 1.0, background/foreground/NaN colors (B, F, N) are ignored
+pub fn read_cpt(file: &str, text: &str) -> FractalResult<Gradient> {
+    let mut stops = vec![];
+
+    for (idx, line) in text.lines().enumerate() {
+        let line_nr = idx + 1;
+        let line = line.trim();
+        if line.starts_with('#') && line.contains("COLOR_MODEL") && !line.contains("RGB") {
+            return Err(invalid_palette(
+                file,
+                Some(line_nr),
+                "only the RGB color model is supported",
+            ));
+        }
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        if line.starts_with('B') || line.starts_with('F') || line.starts_with('N') {
+            continue;
+        }
+
+        let mut tokens = line.split_whitespace();
+        for _ in 0..2 {
+            let z = tokens
+                .next()
+                .and_then(|t| t.parse::<f64>().ok())
+                .ok_or_else(|| invalid_palette(file, Some(line_nr), "expected a z value"))?;
+            let color = parse_color(&mut tokens).ok_or_else(|| {
+                invalid_palette(file, Some(line_nr), "expected a color r g b or r/g/b")
+            })?;
+            stops.push(ColorStop { position: z, color });
+        }
+    }
+
+    let (min, max) = match (stops.first(), stops.last()) {
+        (Some(first), Some(last)) => (first.position, last.position),
+        _ => return Err(invalid_palette(file, None, "color table has no colors")),
+    };
+    let range = if max > min { max - min } else { 1.0 };
+    for stop in stops.iter_mut() {
+        stop.position = (stop.position - min) / range;
+    }
+
+    Ok(Gradient {
+        stops,
+        interpolation: Interpolation::Rgb,
+        steps: 256,
+    })
+}
+
+fn parse_color<'a>(tokens: &mut impl Iterator<Item = &'a str>) -> Option<Color> {
+    let first = tokens.next()?;
+    let values: Vec<u8> = if first.contains('/') {
+        first
+            .split('/')
+            .map(|v| v.parse::<u8>().ok())
+            .collect::<Option<_>>()?
+    } else {
+        let mut values = vec![first.parse::<u8>().ok()?];
+        for _ in 0..2 {
+            values.push(tokens.next()?.parse::<u8>().ok()?);
+        }
+        values
+    };
+    match values[..] {
+        [r, g, b] => Some(Color { r, g, b }),
+        _ => None,
+    }
+}
+
+pub fn write_cpt(name: &str, gradient: &Gradient) -> String {
+    let mut res = format!("# {}\n# COLOR_MODEL = RGB\n", name);
+    let stops = &gradient.stops;
+    let segments: Vec<(&ColorStop, &ColorStop)> = match stops.len() {
+        1 => vec![(&stops[0], &stops[0])],
+        _ => stops.windows(2).map(|w| (&w[0], &w[1])).collect(),
+    };
+    for (from, to) in segments {
+        let to_position = if from.position == to.position && stops.len() == 1 {
+            1.0
+        } else {
+            to.position
+        };
+        res.push_str(&format!(
+            "{:.6} {} {} {} {:.6} {} {} {}\n",
+            from.position,
+            from.color.r,
+            from.color.g,
+            from.color.b,
+            to_position,
+            to.color.r,
+            to.color.g,
+            to.color.b
+        ));
+    }
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::color::{Color, BLUE, RED};
+    use crate::error::FractalError;
+    use crate::gradient::{Gradient, Interpolation};
+    use crate::palette::cpt::{read_cpt, write_cpt};
+
+    const CPT: &str = "# test
+# COLOR_MODEL = RGB
+-100 255 0 0 0 0 0 255 sea
+0 0/0/255 100 255/255/255
+B 0 0 0
+F 255 255 255
+N 128 128 128
+";
+
+    #[test]
+    fn test_read() {
+        let g = read_cpt("test.cpt", CPT).unwrap();
+        assert_eq!(g.stops.len(), 4);
+        assert_eq!(g.sample(0.0), RED);
+        assert_eq!(g.sample(0.5), BLUE);
+        assert_eq!(
+            g.sample(1.0),
+            Color {
+                r: 255,
+                g: 255,
+                b: 255
+            }
+        );
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        let g = Gradient::new(&[RED, BLUE, RED], Interpolation::Rgb, 256);
+        let read = read_cpt("test.cpt", &write_cpt("test", &g)).unwrap();
+        for t in [0.0, 0.25, 0.5, 0.75, 1.0] {
+            assert_eq!(read.sample(t), g.sample(t));
+        }
+    }
+
+    #[test]
+    fn test_invalid() {
+        let res = read_cpt("test.cpt", "0 0 0 0 1 0 0\n");
+        assert!(matches!(
+            res,
+            Err(FractalError::InvalidPalette { line: Some(1), .. })
+        ));
+        let res = read_cpt("test.cpt", "# COLOR_MODEL = HSV\n0 0 0 0 1 0 0 0\n");
+        assert!(res.is_err());
+    }
+}
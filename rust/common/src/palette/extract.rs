@@ -0,0 +1,279 @@
+use std::io::Cursor;
+
+use image::io::{Limits, Reader};
+use image::ImageError;
+use serde_derive::{Deserialize, Serialize};
+
+use crate::color::Color;
+use crate::error::{FractalError, FractalResult};
+use crate::gradient::{Gradient, Interpolation, OkLab};
+use crate::palette::invalid_palette;
+
+// k-means over every pixel of a large photo is slow and doesn't change the result much
+const MAX_SAMPLES: usize = 16384;
+const MAX_ROUNDS: usize = 32;
+pub const MAX_EXTRACTED_COLORS: usize = 256;
+
+// colors below this chroma are treated as grays without a hue
+const GRAY_CHROMA: f64 = 0.02;
+
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, Default, PartialEq)]
+pub enum PaletteOrder {
+    // dark to bright
+    #[default]
+    Luminance,
+    // grays first, then around the color wheel
+    Hue,
+}
+
+// reads a PNG or JPEG and derives a gradient with up to `colors` stops from its dominant colors.
+// images with more than max_pixels pixels are rejected before they are decoded
+pub fn extract_palette(
+    file: &str,
+    data: &[u8],
+    colors: usize,
+    order: PaletteOrder,
+    max_pixels: u64,
+) -> FractalResult<Gradient> {
+    let cant_read =
+        |e: ImageError| invalid_palette(file, None, &format!("can't read the image: {}", e));
+    let reader = || {
+        Reader::new(Cursor::new(data))
+            .with_guessed_format()
+            .map_err(|e| cant_read(e.into()))
+    };
+    let (width, height) = reader()?.into_dimensions().map_err(cant_read)?;
+    if width as u64 * height as u64 > max_pixels {
+        return Err(FractalError::InvalidRequest(format!(
+            "image has {}x{} pixels, but at most {} pixels are allowed",
+            width, height, max_pixels
+        )));
+    }
+    // the header could lie about the size, the decoder must not allocate more than it says
+    let mut limits = Limits::default();
+    limits.max_image_width = Some(width);
+    limits.max_image_height = Some(height);
+    let mut reader = reader()?;
+    reader.limits(limits);
+    let image = reader.decode().map_err(cant_read)?.to_rgb8();
+    let pixels: Vec<Color> = image
+        .pixels()
+        .map(|p| Color {
+            r: p[0],
+            g: p[1],
+            b: p[2],
+        })
+        .collect();
+
+    let colors = extract_colors(&pixels, colors, order)?;
+    Ok(Gradient::new(&colors, Interpolation::OkLab, 256))
+}
+
+// k-means in OKLab, so the clusters follow perceived and not numeric color differences
+pub fn extract_colors(
+    pixels: &[Color],
+    colors: usize,
+    order: PaletteOrder,
+) -> FractalResult<Vec<Color>> {
+    if colors == 0 || colors > MAX_EXTRACTED_COLORS {
+        return Err(FractalError::InvalidRequest(format!(
+            "colors must be between 1 and {}",
+            MAX_EXTRACTED_COLORS
+        )));
+    }
+    if pixels.is_empty() {
+        return Err(FractalError::InvalidRequest(
+            "the image has no pixels".to_string(),
+        ));
+    }
+
+    let stride = pixels.len().div_ceil(MAX_SAMPLES);
+    let samples: Vec<OkLab> = pixels.iter().step_by(stride).map(OkLab::from).collect();
+
+    let mut centers = initial_centers(&samples, colors);
+    let mut assignment = vec![usize::MAX; samples.len()];
+    for _ in 0..MAX_ROUNDS {
+        let mut changed = false;
+        for (sample, assigned) in samples.iter().zip(assignment.iter_mut()) {
+            let nearest = nearest_center(sample, &centers);
+            if nearest != *assigned {
+                *assigned = nearest;
+                changed = true;
+            }
+        }
+        if !changed {
+            break;
+        }
+
+        let mut sums = vec![(0.0, 0.0, 0.0, 0usize); centers.len()];
+        for (sample, &assigned) in samples.iter().zip(assignment.iter()) {
+            let sum = &mut sums[assigned];
+            sum.0 += sample.l;
+            sum.1 += sample.a;
+            sum.2 += sample.b;
+            sum.3 += 1;
+        }
+        for (center, (l, a, b, cnt)) in centers.iter_mut().zip(sums) {
+            if cnt > 0 {
+                let cnt = cnt as f64;
+                *center = OkLab {
+                    l: l / cnt,
+                    a: a / cnt,
+                    b: b / cnt,
+                };
+            }
+        }
+    }
+
+    // centers without samples are duplicates of other centers
+    let mut used = vec![false; centers.len()];
+    assignment.iter().for_each(|&idx| used[idx] = true);
+    let mut centers: Vec<OkLab> = centers
+        .into_iter()
+        .zip(used)
+        .filter_map(|(center, used)| used.then_some(center))
+        .collect();
+
+    match order {
+        PaletteOrder::Luminance => centers.sort_by(|x, y| x.l.total_cmp(&y.l)),
+        PaletteOrder::Hue => centers.sort_by(|x, y| hue_key(x).partial_cmp(&hue_key(y)).unwrap()),
+    }
+
+    let mut res: Vec<Color> = centers.iter().map(Color::from).collect();
+    res.dedup();
+    Ok(res)
+}
+
+// evenly spaced along the lightness, deterministic unlike random seeds
+fn initial_centers(samples: &[OkLab], colors: usize) -> Vec<OkLab> {
+    let mut sorted = samples.to_vec();
+    sorted.sort_by(|x, y| x.l.total_cmp(&y.l));
+    (0..colors)
+        .map(|idx| {
+            let pos = ((idx as f64 + 0.5) / colors as f64 * sorted.len() as f64) as usize;
+            sorted[pos.min(sorted.len() - 1)]
+        })
+        .collect()
+}
+
+fn nearest_center(sample: &OkLab, centers: &[OkLab]) -> usize {
+    centers
+        .iter()
+        .map(|c| (sample.l - c.l).powi(2) + (sample.a - c.a).powi(2) + (sample.b - c.b).powi(2))
+        .enumerate()
+        .min_by(|(_, x), (_, y)| x.total_cmp(y))
+        .map(|(idx, _)| idx)
+        .unwrap_or(0)
+}
+
+fn hue_key(lab: &OkLab) -> (u8, f64, f64) {
+    let chroma = lab.a.hypot(lab.b);
+    if chroma < GRAY_CHROMA {
+        (0, lab.l, 0.0)
+    } else {
+        let hue = lab.b.atan2(lab.a).to_degrees().rem_euclid(360.0);
+        (1, hue, lab.l)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use image::{ImageOutputFormat, RgbImage};
+
+    use crate::color::Color;
+    use crate::coloring::Palette;
+    use crate::palette::extract::{extract_colors, extract_palette, PaletteOrder};
+    use crate::palette::map::parse_map;
+    use crate::palette::{write_palette_file, PaletteFormat};
+
+    fn rgb(r: u8, g: u8, b: u8) -> Color {
+        Color { r, g, b }
+    }
+
+    // a striped image with a known set of colors
+    fn stripes(colors: &[Color]) -> Vec<Color> {
+        colors
+            .iter()
+            .flat_map(|c| std::iter::repeat_n(c.clone(), 100))
+            .collect()
+    }
+
+    #[test]
+    fn test_extract_finds_the_colors() {
+        let pixels = stripes(&[rgb(255, 255, 255), rgb(0, 0, 0), rgb(200, 30, 30)]);
+        let colors = extract_colors(&pixels, 3, PaletteOrder::Luminance).unwrap();
+        assert_eq!(
+            colors,
+            vec![rgb(0, 0, 0), rgb(200, 30, 30), rgb(255, 255, 255)]
+        );
+    }
+
+    #[test]
+    fn test_extract_drops_duplicates() {
+        let pixels = stripes(&[rgb(10, 20, 30), rgb(250, 240, 230)]);
+        let colors = extract_colors(&pixels, 8, PaletteOrder::Luminance).unwrap();
+        assert_eq!(colors, vec![rgb(10, 20, 30), rgb(250, 240, 230)]);
+    }
+
+    #[test]
+    fn test_extract_hue_order() {
+        let pixels = stripes(&[
+            rgb(0, 0, 255),
+            rgb(128, 128, 128),
+            rgb(255, 0, 0),
+            rgb(0, 200, 0),
+        ]);
+        let colors = extract_colors(&pixels, 4, PaletteOrder::Hue).unwrap();
+        // grays first, then red, green and blue by OKLab hue angle
+        assert_eq!(
+            colors,
+            vec![
+                rgb(128, 128, 128),
+                rgb(255, 0, 0),
+                rgb(0, 200, 0),
+                rgb(0, 0, 255)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_extract_rejects_invalid_input() {
+        assert!(extract_colors(&[], 4, PaletteOrder::Luminance).is_err());
+        assert!(extract_colors(&[rgb(1, 2, 3)], 0, PaletteOrder::Luminance).is_err());
+        assert!(extract_colors(&[rgb(1, 2, 3)], 1000, PaletteOrder::Luminance).is_err());
+        assert!(
+            extract_palette("nope.png", b"not an image", 4, PaletteOrder::Luminance, 100).is_err()
+        );
+    }
+
+    #[test]
+    fn test_extract_palette_from_png_writes_map() {
+        let mut image = RgbImage::new(20, 10);
+        for (x, _, p) in image.enumerate_pixels_mut() {
+            *p = if x < 10 {
+                image::Rgb([20, 40, 160])
+            } else {
+                image::Rgb([240, 200, 40])
+            };
+        }
+        let mut png = Cursor::new(vec![]);
+        image.write_to(&mut png, ImageOutputFormat::Png).unwrap();
+
+        let too_large =
+            extract_palette("brand.png", png.get_ref(), 2, PaletteOrder::Luminance, 199);
+        assert!(too_large.is_err());
+
+        let gradient =
+            extract_palette("brand.png", png.get_ref(), 2, PaletteOrder::Luminance, 200).unwrap();
+        assert_eq!(gradient.stops.len(), 2);
+        assert_eq!(gradient.stops[0].color, rgb(20, 40, 160));
+        assert_eq!(gradient.stops[1].color, rgb(240, 200, 40));
+
+        let map = write_palette_file(PaletteFormat::Map, "brand", &Palette::Gradient(gradient));
+        let palette = parse_map("brand.map", map.as_bytes()).unwrap();
+        assert_eq!(palette.colors.len(), 256);
+        assert!(palette.warnings.is_empty());
+    }
+}
@@ -0,0 +1,189 @@
+use crate::color::Color;
+use crate::error::FractalResult;
+use crate::gradient::{interpolate, ColorStop, Gradient, Interpolation};
+use crate::palette::{invalid_palette, u8_to_unit, unit_to_u8};
+
+// GIMP gradient. every segment is
+// "left middle right  r g b a (left)  r g b a (right)  blending coloring"
+// with positions and colors between 0.0 and 1.0. alpha, blending and coloring are ignored
+pub fn read_ggr(file: &str, text: &str) -> FractalResult<Gradient> {
+    let mut lines = text.lines().enumerate().map(|(idx, l)| (idx + 1, l.trim()));
+    if lines.next().map(|(_, l)| l) != Some("GIMP Gradient") {
+        return Err(invalid_palette(file, Some(1), "expected 'GIMP Gradient'"));
+    }
+
+    let mut segments = None;
+    let mut stops = vec![];
+    for (line_nr, line) in lines {
+        if line.is_empty() || line.starts_with('#') || line.starts_with("Name:") {
+            continue;
+        }
+        if segments.is_none() {
+            let cnt = line.parse::<usize>().map_err(|_| {
+                invalid_palette(file, Some(line_nr), "expected the number of segments")
+            })?;
+            segments = Some(cnt);
+            continue;
+        }
+
+        let values = line
+            .split_whitespace()
+            .map(|v| v.parse::<f64>())
+            .collect::<Result<Vec<f64>, _>>()
+            .map_err(|e| invalid_palette(file, Some(line_nr), &e.to_string()))?;
+        if values.len() < 11 {
+            return Err(invalid_palette(
+                file,
+                Some(line_nr),
+                "a segment needs at least 11 values",
+            ));
+        }
+
+        let left = Color {
+            r: unit_to_u8(values[3]),
+            g: unit_to_u8(values[4]),
+            b: unit_to_u8(values[5]),
+        };
+        let right = Color {
+            r: unit_to_u8(values[7]),
+            g: unit_to_u8(values[8]),
+            b: unit_to_u8(values[9]),
+        };
+        // the middle point moves the half way color between left and right
+        let middle = interpolate(&left, &right, 0.5, Interpolation::Rgb);
+
+        stops.push(ColorStop {
+            position: values[0],
+            color: left,
+        });
+        stops.push(ColorStop {
+            position: values[1],
+            color: middle,
+        });
+        stops.push(ColorStop {
+            position: values[2],
+            color: right,
+        });
+    }
+
+    let segments = segments.unwrap_or(0);
+    if segments == 0 || segments * 3 != stops.len() {
+        return Err(invalid_palette(
+            file,
+            None,
+            &format!("expected {} segments, got {}", segments, stops.len() / 3),
+        ));
+    }
+
+    Ok(Gradient {
+        stops,
+        interpolation: Interpolation::Rgb,
+        steps: 256,
+    })
+}
+
+pub fn write_ggr(name: &str, gradient: &Gradient) -> String {
+    // gimp gradients have to cover 0.0 to 1.0
+    let mut stops = gradient.stops.clone();
+    if let Some(first) = stops.first().cloned() {
+        if first.position > 0.0 {
+            stops.insert(
+                0,
+                ColorStop {
+                    position: 0.0,
+                    ..first
+                },
+            );
+        }
+    }
+    if let Some(last) = stops.last().cloned() {
+        if last.position < 1.0 || stops.len() == 1 {
+            stops.push(ColorStop {
+                position: 1.0,
+                ..last
+            });
+        }
+    }
+
+    let segments: Vec<String> = stops
+        .windows(2)
+        .map(|w| {
+            let (left, right) = (&w[0], &w[1]);
+            format!(
+                "{:.6} {:.6} {:.6} {} 1.000000 {} 1.000000 0 0",
+                left.position,
+                (left.position + right.position) / 2.0,
+                right.position,
+                unit_rgb(&left.color),
+                unit_rgb(&right.color)
+            )
+        })
+        .collect();
+
+    format!(
+        "GIMP Gradient\nName: {}\n{}\n{}\n",
+        name,
+        segments.len(),
+        segments.join("\n")
+    )
+}
+
+fn unit_rgb(c: &Color) -> String {
+    format!(
+        "{:.6} {:.6} {:.6}",
+        u8_to_unit(c.r),
+        u8_to_unit(c.g),
+        u8_to_unit(c.b)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::color::{Color, BLUE, RED};
+    use crate::error::FractalError;
+    use crate::gradient::{Gradient, Interpolation};
+    use crate::palette::ggr::{read_ggr, write_ggr};
+
+    const GGR: &str = "GIMP Gradient
+Name: Red to blue
+2
+0.000000 0.250000 0.500000 1.000000 0.000000 0.000000 1.000000 0.000000 0.000000 1.000000 1.000000 0 0
+0.500000 0.750000 1.000000 0.000000 0.000000 1.000000 1.000000 1.000000 1.000000 1.000000 1.000000 0 0 0 0
+";
+
+    #[test]
+    fn test_read() {
+        let g = read_ggr("test.ggr", GGR).unwrap();
+        assert_eq!(g.stops.len(), 6);
+        assert_eq!(g.sample(0.0), RED);
+        assert_eq!(g.sample(0.5), BLUE);
+        assert_eq!(
+            g.sample(1.0),
+            Color {
+                r: 255,
+                g: 255,
+                b: 255
+            }
+        );
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        let g = Gradient::new(&[RED, BLUE, RED], Interpolation::Rgb, 256);
+        let text = write_ggr("test", &g);
+        let read = read_ggr("test.ggr", &text).unwrap();
+        for t in [0.0, 0.2, 0.5, 0.8, 1.0] {
+            assert_eq!(read.sample(t), g.sample(t));
+        }
+    }
+
+    #[test]
+    fn test_invalid() {
+        let res = read_ggr("test.ggr", "GIMP Gradient\nName: x\n1\n0.0 0.5 1.0 1 1 1\n");
+        assert!(matches!(
+            res,
+            Err(FractalError::InvalidPalette { line: Some(4), .. })
+        ));
+        assert!(read_ggr("test.ggr", "GIMP Palette\n").is_err());
+    }
+}
@@ -0,0 +1,77 @@
+use crate::color::Color;
+use crate::error::FractalResult;
+use crate::palette::invalid_palette;
+
+// GIMP palette, "r g b name" per line after the header
+pub fn read_gpl(file: &str, text: &str) -> FractalResult<Vec<Color>> {
+    let mut lines = text.lines().enumerate().map(|(idx, l)| (idx + 1, l.trim()));
+    if lines.next().map(|(_, l)| l) != Some("GIMP Palette") {
+        return Err(invalid_palette(file, Some(1), "expected 'GIMP Palette'"));
+    }
+
+    let mut colors = vec![];
+    for (line_nr, line) in lines {
+        if line.is_empty()
+            || line.starts_with('#')
+            || line.starts_with("Name:")
+            || line.starts_with("Columns:")
+        {
+            continue;
+        }
+
+        let mut tokens = line.split_whitespace();
+        let mut component = || {
+            tokens
+                .next()
+                .and_then(|t| t.parse::<u8>().ok())
+                .ok_or_else(|| {
+                    invalid_palette(file, Some(line_nr), "expected 3 values between 0 and 255")
+                })
+        };
+        let r = component()?;
+        let g = component()?;
+        let b = component()?;
+        colors.push(Color { r, g, b });
+    }
+
+    if colors.is_empty() {
+        return Err(invalid_palette(file, None, "palette has no colors"));
+    }
+    Ok(colors)
+}
+
+pub fn write_gpl(name: &str, colors: &[Color]) -> String {
+    let mut res = format!("GIMP Palette\nName: {}\nColumns: 16\n#\n", name);
+    for (idx, c) in colors.iter().enumerate() {
+        res.push_str(&format!(
+            "{:>3} {:>3} {:>3}\tIndex {}\n",
+            c.r, c.g, c.b, idx
+        ));
+    }
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::color::{BLUE, RED};
+    use crate::error::FractalError;
+    use crate::palette::gpl::{read_gpl, write_gpl};
+
+    #[test]
+    fn test_roundtrip() {
+        let text = write_gpl("test", &[RED, BLUE]);
+        assert_eq!(read_gpl("test.gpl", &text).unwrap(), vec![RED, BLUE]);
+    }
+
+    #[test]
+    fn test_invalid() {
+        let res = read_gpl(
+            "test.gpl",
+            "GIMP Palette\nName: x\n#\n255 0 0 red\n300 0 0 nope\n",
+        );
+        assert!(matches!(
+            res,
+            Err(FractalError::InvalidPalette { line: Some(5), .. })
+        ));
+    }
+}
@@ -0,0 +1,236 @@
+use std::fmt::{Display, Formatter};
+
+use crate::color::Color;
+use crate::error::FractalResult;
+use crate::palette::invalid_palette;
+
+// Fractint uses at most 256 colors, everything after that is ignored
+pub const MAX_MAP_COLORS: usize = 256;
+
+// DOS end of file marker, some of the old files still have it
+const CTRL_Z: u8 = 0x1a;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct MapDiagnostic {
+    pub line: usize,
+    pub message: String,
+}
+
+impl Display for MapDiagnostic {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct MapPalette {
+    pub colors: Vec<Color>,
+    // problems that were fixed while parsing, e.g. values > 255
+    pub warnings: Vec<MapDiagnostic>,
+}
+
+// every line is "r g b [comment]". empty lines and lines starting with ';' or '#' are skipped.
+// the files are DOS encoded, so the input is bytes and not a str
+pub fn parse_map(file: &str, data: &[u8]) -> FractalResult<MapPalette> {
+    let data = match data.iter().position(|b| *b == CTRL_Z) {
+        Some(end) => &data[..end],
+        None => data,
+    };
+    let text = String::from_utf8_lossy(data);
+
+    let mut colors = vec![];
+    let mut warnings = vec![];
+
+    for (idx, line) in text.lines().enumerate() {
+        let line_nr = idx + 1;
+        let trimmed = line.trim();
+        if trimmed.is_empty() || trimmed.starts_with(';') || trimmed.starts_with('#') {
+            continue;
+        }
+
+        if colors.len() == MAX_MAP_COLORS {
+            warnings.push(MapDiagnostic {
+                line: line_nr,
+                message: format!("ignoring everything after {} colors", MAX_MAP_COLORS),
+            });
+            break;
+        }
+
+        let mut tokens = trimmed.split_whitespace();
+        let mut component = |name: &str| -> FractalResult<u8> {
+            let token = tokens.next().ok_or_else(|| {
+                invalid_palette(file, Some(line_nr), &format!("{} is missing", name))
+            })?;
+            let value = token.parse::<u32>().map_err(|_| {
+                let message = format!("{} '{}' is not a number", name, token);
+                invalid_palette(file, Some(line_nr), &message)
+            })?;
+            if value > 255 {
+                warnings.push(MapDiagnostic {
+                    line: line_nr,
+                    message: format!("{} {} is bigger than 255, using 255", name, value),
+                });
+            }
+            Ok(value.min(255) as u8)
+        };
+
+        let r = component("red")?;
+        let g = component("green")?;
+        let b = component("blue")?;
+        // anything after the 3 values is a comment
+        colors.push(Color { r, g, b });
+    }
+
+    if colors.is_empty() {
+        return Err(invalid_palette(file, None, "palette has no colors"));
+    }
+
+    Ok(MapPalette { colors, warnings })
+}
+
+pub fn write_map(colors: &[Color]) -> String {
+    colors
+        .iter()
+        .take(MAX_MAP_COLORS)
+        .map(|c| format!("{:>3} {:>3} {:>3}\n", c.r, c.g, c.b))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{read, read_dir};
+
+    use crate::color::Color;
+    use crate::error::FractalError;
+    use crate::palette::map::{parse_map, write_map, MAX_MAP_COLORS};
+
+    fn palette_files() -> Vec<(String, Vec<u8>)> {
+        let path = format!("{}/../../palette", env!("CARGO_MANIFEST_DIR"));
+        read_dir(path)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .filter(|p| {
+                p.extension()
+                    .map(|e| e.eq_ignore_ascii_case("map"))
+                    .unwrap_or(false)
+            })
+            .map(|p| {
+                let name = p.file_name().unwrap().to_string_lossy().to_string();
+                (name, read(&p).unwrap())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_all_palette_files() {
+        let files = palette_files();
+        assert!(files.len() > 300);
+
+        for (name, data) in files {
+            let palette = parse_map(&name, &data).unwrap_or_else(|e| panic!("{}", e));
+            assert!(
+                !palette.colors.is_empty() && palette.colors.len() <= MAX_MAP_COLORS,
+                "{} has {} colors",
+                name,
+                palette.colors.len()
+            );
+        }
+    }
+
+    #[test]
+    fn test_known_files() {
+        let files = palette_files();
+        let parse = |name: &str| {
+            let (_, data) = files.iter().find(|(n, _)| n == name).unwrap();
+            parse_map(name, data).unwrap()
+        };
+
+        // last line ends with ctrl-z
+        let p = parse("4ZEBBOW.MAP");
+        assert_eq!(p.colors.len(), 256);
+        assert_eq!(
+            p.colors[255],
+            Color {
+                r: 255,
+                g: 255,
+                b: 255
+            }
+        );
+
+        // 16 color palette
+        assert_eq!(parse("PAINTJET.MAP").colors.len(), 16);
+
+        // text after the 256 colors
+        let p = parse("LANDSCAP.MAP");
+        assert_eq!(p.colors.len(), 256);
+        assert_eq!(p.warnings.len(), 1);
+
+        // values up to 1000
+        let p = parse("ROYGBV.MAP");
+        assert!(p.warnings.iter().any(|w| w.line == 141));
+        assert_eq!(p.colors[140], Color { r: 0, g: 255, b: 4 });
+    }
+
+    #[test]
+    fn test_write() {
+        let colors = vec![Color {
+            r: 0,
+            g: 10,
+            b: 255,
+        }];
+        assert_eq!(write_map(&colors), "  0  10 255\n");
+        assert_eq!(
+            parse_map("test.map", write_map(&colors).as_bytes())
+                .unwrap()
+                .colors,
+            colors
+        );
+    }
+
+    #[test]
+    fn test_comments_and_trailing_text() {
+        let data = b"; my palette\r\n  0 0 0  black\r\n\r\n# more comments\r\n255 128 1 orange\r\n";
+        let p = parse_map("test.map", data).unwrap();
+        assert_eq!(
+            p.colors,
+            vec![
+                Color { r: 0, g: 0, b: 0 },
+                Color {
+                    r: 255,
+                    g: 128,
+                    b: 1
+                }
+            ]
+        );
+        assert!(p.warnings.is_empty());
+    }
+
+    #[test]
+    fn test_errors_have_line_numbers() {
+        let res = parse_map("test.map", b"0 0 0\n1 2 x\n");
+        match res {
+            Err(FractalError::InvalidPalette {
+                file,
+                line,
+                message,
+            }) => {
+                assert_eq!(file, "test.map");
+                assert_eq!(line, Some(2));
+                assert_eq!(message, "blue 'x' is not a number");
+            }
+            _ => panic!("expected an error, got {:?}", res),
+        }
+
+        let res = parse_map("test.map", b"0 0 0\n1 2\n");
+        assert!(matches!(
+            res,
+            Err(FractalError::InvalidPalette { line: Some(2), .. })
+        ));
+
+        let res = parse_map("test.map", b"; nothing\n");
+        assert!(matches!(
+            res,
+            Err(FractalError::InvalidPalette { line: None, .. })
+        ));
+    }
+}
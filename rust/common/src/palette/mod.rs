@@ -0,0 +1,313 @@
+pub mod cpt;
+pub mod extract;
+pub mod ggr;
+pub mod gpl;
+pub mod map;
+pub mod ugr;
+
+use std::collections::HashMap;
+use std::env;
+use std::fs::{read, read_dir};
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+use log::{debug, warn};
+use serde_derive::{Deserialize, Serialize};
+
+use crate::color::Color;
+use crate::coloring::Palette;
+use crate::error::{FractalError, FractalResult};
+use crate::models::PaletteSpec;
+use crate::palette::cpt::{read_cpt, write_cpt};
+use crate::palette::ggr::{read_ggr, write_ggr};
+use crate::palette::gpl::{read_gpl, write_gpl};
+use crate::palette::map::{parse_map, write_map};
+use crate::palette::ugr::{read_ugr, write_ugr};
+
+// the colors every engine uses for a request
+pub fn resolve_palette(spec: &PaletteSpec) -> FractalResult<Palette> {
+    match spec {
+        PaletteSpec::Name(name) => palette_library()?
+            .get(&name.to_lowercase())
+            .cloned()
+            .map(Palette::Colors)
+            .ok_or_else(|| FractalError::PaletteNotFound(name.to_string())),
+        PaletteSpec::Colors(colors) => Ok(Palette::Colors(colors.clone())),
+        PaletteSpec::Gradient(gradient) => Ok(Palette::Gradient(gradient.clone())),
+    }
+}
+
+// used if the palette directory is not available, e.g. when the binary is deployed alone
+pub const EMBEDDED_PALETTES: &[(&str, &[u8])] = &[
+    ("basic.map", include_bytes!("../../../../palette/BASIC.MAP")),
+    ("wild.map", include_bytes!("../../../../palette/WILD.MAP")),
+];
+
+static PALETTE_LIBRARY: OnceLock<HashMap<String, Vec<Color>>> = OnceLock::new();
+
+// the palettes are read only once, later calls return the cached palettes
+pub fn palette_library() -> FractalResult<&'static HashMap<String, Vec<Color>>> {
+    if let Some(library) = PALETTE_LIBRARY.get() {
+        return Ok(library);
+    }
+    let library = read_palette()?;
+    Ok(PALETTE_LIBRARY.get_or_init(|| library))
+}
+
+pub const PALETTE_DIR_ENV: &str = "FRACTAL_PALETTE_DIR";
+
+pub fn palette_dir() -> PathBuf {
+    match env::var_os(PALETTE_DIR_ENV) {
+        Some(dir) => PathBuf::from(dir),
+        None => PathBuf::from(format!("{}/../../palette", env!("CARGO_MANIFEST_DIR"))),
+    }
+}
+
+// all palettes by lowercase file name, e.g. "basic.map"
+pub fn read_palette() -> FractalResult<HashMap<String, Vec<Color>>> {
+    let dir = palette_dir();
+    if env::var_os(PALETTE_DIR_ENV).is_none() && !dir.is_dir() {
+        warn!(
+            "palette directory {} not found, using the embedded palettes",
+            dir.display()
+        );
+        return Ok(read_palette_bytes(EMBEDDED_PALETTES));
+    }
+    read_palette_dir(&dir)
+}
+
+pub fn read_palette_dir(dir: &Path) -> FractalResult<HashMap<String, Vec<Color>>> {
+    let dir_name = dir.display().to_string();
+    let entries = read_dir(dir).map_err(|e| FractalError::io(&dir_name, e))?;
+
+    let mut files = vec![];
+    for entry in entries {
+        let path = entry.map_err(|e| FractalError::io(&dir_name, e))?.path();
+        let is_map = path
+            .extension()
+            .map(|e| e.eq_ignore_ascii_case("map"))
+            .unwrap_or(false);
+        if is_map {
+            match read(&path) {
+                Ok(data) => {
+                    let name = path
+                        .file_name()
+                        .unwrap_or_default()
+                        .to_string_lossy()
+                        .to_string();
+                    files.push((name, data));
+                }
+                Err(e) => warn!("skipping palette {}: {}", path.display(), e),
+            }
+        }
+    }
+
+    let files: Vec<(&str, &[u8])> = files
+        .iter()
+        .map(|(name, data)| (name.as_str(), data.as_slice()))
+        .collect();
+    Ok(read_palette_bytes(&files))
+}
+
+// a broken file is skipped, the other palettes are still available
+pub fn read_palette_bytes(files: &[(&str, &[u8])]) -> HashMap<String, Vec<Color>> {
+    let mut res = HashMap::new();
+    for (name, data) in files {
+        let palette = match parse_map(name, data) {
+            Ok(palette) => palette,
+            Err(e) => {
+                warn!("skipping palette {}", e);
+                continue;
+            }
+        };
+        for warning in &palette.warnings {
+            debug!("palette {} {}", name, warning);
+        }
+        res.insert(name.to_lowercase(), palette.colors);
+    }
+    res
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq)]
+pub enum PaletteFormat {
+    // Fractint
+    Map,
+    // GIMP gradient
+    Ggr,
+    // GIMP palette
+    Gpl,
+    // UltraFractal gradient
+    Ugr,
+    // GMT color table
+    Cpt,
+}
+
+impl PaletteFormat {
+    pub fn from_file_name(name: &str) -> Option<PaletteFormat> {
+        let extension = Path::new(name)
+            .extension()?
+            .to_string_lossy()
+            .to_lowercase();
+        match extension.as_str() {
+            "map" => Some(PaletteFormat::Map),
+            "ggr" => Some(PaletteFormat::Ggr),
+            "gpl" => Some(PaletteFormat::Gpl),
+            "ugr" => Some(PaletteFormat::Ugr),
+            "cpt" => Some(PaletteFormat::Cpt),
+            _ => None,
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            PaletteFormat::Map => "map",
+            PaletteFormat::Ggr => "ggr",
+            PaletteFormat::Gpl => "gpl",
+            PaletteFormat::Ugr => "ugr",
+            PaletteFormat::Cpt => "cpt",
+        }
+    }
+}
+
+// the format is taken from the file extension
+pub fn read_palette_file(file: &str, data: &[u8]) -> FractalResult<Palette> {
+    let format = PaletteFormat::from_file_name(file).ok_or_else(|| {
+        invalid_palette(
+            file,
+            None,
+            "unknown format, expected .map, .ggr, .gpl, .ugr or .cpt",
+        )
+    })?;
+    if format == PaletteFormat::Map {
+        return Ok(Palette::Colors(parse_map(file, data)?.colors));
+    }
+
+    let text = String::from_utf8_lossy(data);
+    let palette = match format {
+        PaletteFormat::Ggr => Palette::Gradient(read_ggr(file, &text)?),
+        PaletteFormat::Gpl => Palette::Colors(read_gpl(file, &text)?),
+        PaletteFormat::Ugr => Palette::Gradient(read_ugr(file, &text)?),
+        PaletteFormat::Cpt => Palette::Gradient(read_cpt(file, &text)?),
+        PaletteFormat::Map => unreachable!(),
+    };
+    Ok(palette)
+}
+
+// gradients are sampled for the formats that only know colors and vice versa
+pub fn write_palette_file(format: PaletteFormat, name: &str, palette: &Palette) -> String {
+    match format {
+        PaletteFormat::Map => write_map(&palette.to_colors()),
+        PaletteFormat::Gpl => write_gpl(name, &palette.to_colors()),
+        PaletteFormat::Ggr => write_ggr(name, &palette.to_gradient()),
+        PaletteFormat::Ugr => write_ugr(name, &palette.to_gradient()),
+        PaletteFormat::Cpt => write_cpt(name, &palette.to_gradient()),
+    }
+}
+
+pub(crate) fn invalid_palette(file: &str, line: Option<usize>, message: &str) -> FractalError {
+    FractalError::InvalidPalette {
+        file: file.to_string(),
+        line,
+        message: message.to_string(),
+    }
+}
+
+pub(crate) fn unit_to_u8(v: f64) -> u8 {
+    (v * 255.0).round().clamp(0.0, 255.0) as u8
+}
+
+pub(crate) fn u8_to_unit(v: u8) -> f64 {
+    v as f64 / 255.0
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::color::Color;
+    use crate::coloring::Palette;
+    use crate::error::FractalError;
+    use crate::models::PaletteSpec;
+    use crate::palette::{
+        palette_library, read_palette, read_palette_bytes, read_palette_file, resolve_palette,
+        write_palette_file, PaletteFormat, EMBEDDED_PALETTES,
+    };
+
+    #[test]
+    fn test_resolve_by_name() {
+        let palette = read_palette().unwrap();
+        let colors = resolve_palette(&PaletteSpec::Name("BASIC.MAP".to_string())).unwrap();
+        assert_eq!(
+            colors,
+            Palette::Colors(palette.get("basic.map").unwrap().clone())
+        );
+    }
+
+    #[test]
+    fn test_resolve_unknown_name() {
+        let res = resolve_palette(&PaletteSpec::Name("nope.map".to_string()));
+        assert!(matches!(res, Err(FractalError::PaletteNotFound(_))));
+    }
+
+    #[test]
+    fn test_resolve_inline_colors() {
+        let colors = vec![Color { r: 1, g: 2, b: 3 }, Color { r: 4, g: 5, b: 6 }];
+        let res = resolve_palette(&PaletteSpec::Colors(colors.clone())).unwrap();
+        assert_eq!(res, Palette::Colors(colors));
+    }
+
+    #[test]
+    fn test_inline_colors_json() {
+        let spec: PaletteSpec = serde_json::from_str(r#"[{"r":1,"g":2,"b":3}]"#).unwrap();
+        assert_eq!(spec, PaletteSpec::Colors(vec![Color { r: 1, g: 2, b: 3 }]));
+        let spec: PaletteSpec = serde_json::from_str(r#""wild.map""#).unwrap();
+        assert_eq!(spec, PaletteSpec::Name("wild.map".to_string()));
+    }
+
+    #[test]
+    fn test_palette_library_is_cached() {
+        let first = palette_library().unwrap();
+        let second = palette_library().unwrap();
+        assert!(std::ptr::eq(first, second));
+        assert_eq!(first, &read_palette().unwrap());
+    }
+
+    #[test]
+    fn test_embedded_palettes() {
+        let embedded = read_palette_bytes(EMBEDDED_PALETTES);
+        let palette = read_palette().unwrap();
+        assert_eq!(embedded.len(), 2);
+        for (name, colors) in embedded {
+            assert_eq!(palette.get(&name), Some(&colors));
+        }
+    }
+
+    #[test]
+    fn test_broken_palette_is_skipped() {
+        let (name, data) = EMBEDDED_PALETTES[0];
+        let files: [(&str, &[u8]); 2] = [("broken.map", b"0 0 0\n1 2\n"), (name, data)];
+        let palettes = read_palette_bytes(&files);
+        assert_eq!(palettes.len(), 1);
+        assert!(palettes.contains_key(name));
+    }
+
+    #[test]
+    fn test_convert_between_formats() {
+        let palette = read_palette().unwrap();
+        let colors = Palette::Colors(palette.get("basic.map").unwrap().clone());
+
+        for format in [PaletteFormat::Map, PaletteFormat::Gpl] {
+            let file = format!("basic.{}", format.extension());
+            let text = write_palette_file(format, "basic", &colors);
+            assert_eq!(read_palette_file(&file, text.as_bytes()).unwrap(), colors);
+        }
+
+        for format in [PaletteFormat::Ggr, PaletteFormat::Ugr, PaletteFormat::Cpt] {
+            let file = format!("basic.{}", format.extension());
+            let text = write_palette_file(format, "basic", &colors);
+            let read = read_palette_file(&file, text.as_bytes()).unwrap();
+            assert!(matches!(read, Palette::Gradient(_)), "{}", file);
+            assert_eq!(read.to_colors().len(), 256);
+        }
+
+        assert!(read_palette_file("basic.txt", b"").is_err());
+    }
+}
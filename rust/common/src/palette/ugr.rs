@@ -0,0 +1,138 @@
+use crate::color::Color;
+use crate::error::FractalResult;
+use crate::gradient::{ColorStop, Gradient, Interpolation};
+use crate::palette::invalid_palette;
+
+// UltraFractal gradients have 400 slots
+const UGR_SLOTS: f64 = 400.0;
+
+// UltraFractal gradient file. only the first gradient in the file is used.
+// colors are stored as b * 65536 + g * 256 + r
+pub fn read_ugr(file: &str, text: &str) -> FractalResult<Gradient> {
+    let mut in_gradient = false;
+    let mut index = None;
+    let mut stops = vec![];
+
+    for (idx, line) in text.lines().enumerate() {
+        let line_nr = idx + 1;
+        let line = line.trim();
+        if line == "gradient:" {
+            in_gradient = true;
+            continue;
+        }
+        if !in_gradient {
+            continue;
+        }
+        if line == "opacity:" || line == "}" {
+            break;
+        }
+
+        for token in line.split_whitespace() {
+            if let Some(value) = token.strip_prefix("index=") {
+                let value = value.parse::<f64>().map_err(|_| {
+                    invalid_palette(file, Some(line_nr), &format!("invalid index '{}'", value))
+                })?;
+                index = Some(value);
+            } else if let Some(value) = token.strip_prefix("color=") {
+                let value = value.parse::<u32>().map_err(|_| {
+                    invalid_palette(file, Some(line_nr), &format!("invalid color '{}'", value))
+                })?;
+                let position = index.take().ok_or_else(|| {
+                    invalid_palette(file, Some(line_nr), "color without an index")
+                })?;
+                stops.push(ColorStop {
+                    position: position.rem_euclid(UGR_SLOTS) / UGR_SLOTS,
+                    color: Color {
+                        r: (value & 0xff) as u8,
+                        g: ((value >> 8) & 0xff) as u8,
+                        b: ((value >> 16) & 0xff) as u8,
+                    },
+                });
+            }
+        }
+    }
+
+    if stops.is_empty() {
+        return Err(invalid_palette(file, None, "no gradient with colors found"));
+    }
+    stops.sort_by(|a, b| a.position.total_cmp(&b.position));
+
+    Ok(Gradient {
+        stops,
+        interpolation: Interpolation::Rgb,
+        steps: 256,
+    })
+}
+
+pub fn write_ugr(name: &str, gradient: &Gradient) -> String {
+    let title: String = name
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect();
+
+    let mut res = format!("{} {{\ngradient:\n  title=\"{}\" smooth=no\n", title, name);
+    for stop in &gradient.stops {
+        let index = (stop.position * (UGR_SLOTS - 1.0)).round() as u32;
+        let color =
+            stop.color.r as u32 + ((stop.color.g as u32) << 8) + ((stop.color.b as u32) << 16);
+        res.push_str(&format!("  index={} color={}\n", index, color));
+    }
+    res.push_str("}\n");
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::color::{Color, BLUE, RED};
+    use crate::error::FractalError;
+    use crate::gradient::{Gradient, Interpolation};
+    use crate::palette::ugr::{read_ugr, write_ugr};
+
+    const UGR: &str = r#"Sunset {
+gradient:
+  title="Sunset" smooth=no rotation=1
+  index=200 color=16711680
+  index=0 color=255
+opacity:
+  smooth=no index=0 opacity=255
+}
+Other {
+gradient:
+  title="Other" smooth=no
+  index=0 color=65280
+}
+"#;
+
+    #[test]
+    fn test_read() {
+        let g = read_ugr("test.ugr", UGR).unwrap();
+        assert_eq!(g.stops.len(), 2);
+        assert_eq!(g.stops[0].color, RED);
+        assert_eq!(g.stops[1].position, 0.5);
+        assert_eq!(g.stops[1].color, BLUE);
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        let g = Gradient::new(
+            &[RED, Color { r: 1, g: 2, b: 3 }, BLUE],
+            Interpolation::Rgb,
+            256,
+        );
+        let read = read_ugr("test.ugr", &write_ugr("my gradient", &g)).unwrap();
+        assert_eq!(read.stops.len(), 3);
+        for (a, b) in read.stops.iter().zip(g.stops.iter()) {
+            assert_eq!(a.color, b.color);
+            assert!((a.position - b.position).abs() < 0.01);
+        }
+    }
+
+    #[test]
+    fn test_invalid() {
+        let res = read_ugr("test.ugr", "x {\ngradient:\n  index=0 color=red\n}\n");
+        assert!(matches!(
+            res,
+            Err(FractalError::InvalidPalette { line: Some(3), .. })
+        ));
+    }
+}
@@ -0,0 +1,190 @@
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+
+use crate::color::{Color, Rgb16};
+use crate::error::{FractalError, FractalResult};
+use crate::models::FractalRequest;
+
+// the iTXt chunk with the complete request as JSON, the other chunks are only for image viewers
+pub const REQUEST_KEYWORD: &str = "FractalRequest";
+const SOFTWARE: &str = "FractalThingi";
+
+pub fn write_png_with_request<W: Write>(
+    w: W,
+    pixels: &[Color],
+    width: u32,
+    height: u32,
+    req: &FractalRequest,
+) -> FractalResult<()> {
+    let rgb: Vec<u8> = pixels.iter().flat_map(|c| [c.r, c.g, c.b]).collect();
+    write_png(w, &rgb, width, height, png::BitDepth::Eight, req)
+}
+
+// 16 bits per channel, PNG stores them big endian
+pub fn write_png16_with_request<W: Write>(
+    w: W,
+    pixels: &[Rgb16],
+    width: u32,
+    height: u32,
+    req: &FractalRequest,
+) -> FractalResult<()> {
+    let rgb: Vec<u8> = pixels
+        .iter()
+        .flat_map(|c| [c.r.to_be_bytes(), c.g.to_be_bytes(), c.b.to_be_bytes()])
+        .flatten()
+        .collect();
+    write_png(w, &rgb, width, height, png::BitDepth::Sixteen, req)
+}
+
+fn write_png<W: Write>(
+    w: W,
+    data: &[u8],
+    width: u32,
+    height: u32,
+    depth: png::BitDepth,
+    req: &FractalRequest,
+) -> FractalResult<()> {
+    let encoder = png_encoder(w, width, height, depth, req)?;
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(data)?;
+    writer.finish()?;
+    Ok(())
+}
+
+// an RGB encoder with the request in the metadata
+pub(crate) fn png_encoder<W: Write>(
+    w: W,
+    width: u32,
+    height: u32,
+    depth: png::BitDepth,
+    req: &FractalRequest,
+) -> FractalResult<png::Encoder<'static, W>> {
+    let json = serde_json::to_string(req)?;
+
+    let mut encoder = png::Encoder::new(w, width, height);
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(depth);
+    encoder.add_text_chunk("Software".to_string(), SOFTWARE.to_string())?;
+    encoder.add_itxt_chunk("Title".to_string(), req.name.clone())?;
+    encoder.add_text_chunk(
+        "Description".to_string(),
+        format!(
+            "center {}  complex width {}  zoom {}  max iterations {}",
+            req.center, req.complex_width, req.zoom, req.max_iterations
+        ),
+    )?;
+    encoder.add_itxt_chunk(REQUEST_KEYWORD.to_string(), json)?;
+    Ok(encoder)
+}
+
+pub fn encode_png_with_request(
+    pixels: &[Color],
+    width: u32,
+    height: u32,
+    req: &FractalRequest,
+) -> FractalResult<Vec<u8>> {
+    let mut png = vec![];
+    write_png_with_request(&mut png, pixels, width, height, req)?;
+    Ok(png)
+}
+
+// the request a PNG was rendered from. rendering it again gives the same image
+pub fn read_request(data: &[u8]) -> FractalResult<FractalRequest> {
+    let invalid = |message: String| FractalError::InvalidRequest(message);
+
+    let decoder = png::Decoder::new(data);
+    let reader = decoder
+        .read_info()
+        .map_err(|e| invalid(format!("can't read the PNG: {}", e)))?;
+    let info = reader.info();
+
+    let json = match info
+        .utf8_text
+        .iter()
+        .find(|chunk| chunk.keyword == REQUEST_KEYWORD)
+    {
+        Some(chunk) => chunk
+            .get_text()
+            .map_err(|e| invalid(format!("can't read the PNG: {}", e)))?,
+        // written by other tools as plain tEXt
+        None => info
+            .uncompressed_latin1_text
+            .iter()
+            .find(|chunk| chunk.keyword == REQUEST_KEYWORD)
+            .map(|chunk| chunk.text.clone())
+            .ok_or_else(|| {
+                invalid(format!(
+                    "the PNG has no {} metadata, it was not rendered by this server",
+                    REQUEST_KEYWORD
+                ))
+            })?,
+    };
+
+    serde_json::from_str(&json)
+        .map_err(|e| invalid(format!("the {} metadata is invalid: {}", REQUEST_KEYWORD, e)))
+}
+
+pub fn read_request_file(path: &Path) -> FractalResult<FractalRequest> {
+    let data = fs::read(path).map_err(|e| FractalError::io(&path.to_string_lossy(), e))?;
+    read_request(&data)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::color::{Color, Rgb16};
+    use crate::fractal_templates::basic;
+    use crate::gradient::{Gradient, Interpolation};
+    use crate::models::PaletteSpec;
+    use crate::png_metadata::{encode_png_with_request, read_request, write_png16_with_request};
+    use crate::utils::encode_png;
+
+    fn pixels(width: u32, height: u32) -> Vec<Color> {
+        (0..width * height)
+            .map(|idx| Color {
+                r: idx as u8,
+                g: 0,
+                b: 255,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_request_round_trip() {
+        let (mut req, _, _) = basic(true);
+        req.width = 8;
+        req.height = 4;
+        req.palette_offset = 12.5;
+        req.palette = PaletteSpec::Gradient(Gradient::new(
+            &[Color { r: 1, g: 2, b: 3 }, Color { r: 250, g: 128, b: 0 }],
+            Interpolation::Hsl,
+            100,
+        ));
+
+        let png = encode_png_with_request(&pixels(8, 4), 8, 4, &req).unwrap();
+        assert_eq!(read_request(&png).unwrap(), req);
+
+        // the pixels are untouched by the metadata
+        let image = image::load_from_memory(&png).unwrap().to_rgb8();
+        assert_eq!(image.get_pixel(5, 0).0, [5, 0, 255]);
+    }
+
+    #[test]
+    fn test_png16() {
+        let (req, _, _) = basic(true);
+        let pixels = vec![Rgb16 { r: 1, g: 0x1234, b: 65535 }; 6];
+        let mut png = vec![];
+        write_png16_with_request(&mut png, &pixels, 3, 2, &req).unwrap();
+
+        assert_eq!(read_request(&png).unwrap(), req);
+        let image = image::load_from_memory(&png).unwrap().to_rgb16();
+        assert_eq!(image.get_pixel(2, 1).0, [1, 0x1234, 65535]);
+    }
+
+    #[test]
+    fn test_png_without_metadata() {
+        let png = encode_png(&pixels(4, 4), 4, 4).unwrap();
+        assert!(read_request(&png).is_err());
+        assert!(read_request(b"not a png").is_err());
+    }
+}
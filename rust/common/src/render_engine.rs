@@ -0,0 +1,228 @@
+use std::time::Instant;
+
+use log::info;
+
+use crate::color::Color;
+use crate::coloring::{Coloring, Palette};
+use crate::error::{FractalError, FractalResult};
+use crate::fractal_calculation_crossbeam::CrossbeamTilesEngine;
+use crate::fractal_calculation_multi::{
+    MultiThreadedEngine, MultiThreadedOpt1Engine, MultiThreadedOpt2Engine,
+};
+use crate::fractal_calculation_rayon::RayonEngine;
+use crate::fractal_calculation_single::SingleThreadedEngine;
+use crate::fractal_image::FractalImage;
+use crate::models::FractalRequest;
+use crate::palette::resolve_palette;
+use crate::output::save_outputs;
+use crate::utils::print_debug;
+use crate::viewport::Viewport;
+
+// everything an engine needs to calculate the pixels of one image
+pub struct RenderParams {
+    pub viewport: Viewport,
+    pub zoom: f64,
+    pub width: u32,
+    pub height: u32,
+    pub max_iterations: u32,
+    pub palette: Palette,
+    pub coloring: Coloring,
+    pub palette_offset: f64,
+    pub x_tiles: u32,
+    pub y_tiles: u32,
+}
+
+impl RenderParams {
+    pub fn new(req: &FractalRequest, palette: Palette) -> RenderParams {
+        RenderParams {
+            viewport: Viewport::from_request(req),
+            zoom: req.zoom,
+            width: req.width,
+            height: req.height,
+            max_iterations: req.max_iterations,
+            palette,
+            coloring: req.coloring,
+            palette_offset: req.palette_offset,
+            x_tiles: req.x_tiles,
+            y_tiles: req.y_tiles,
+        }
+    }
+}
+
+pub struct RenderResult {
+    pub fractal: FractalImage,
+    pub duration_ms: u128,
+    pub threads: usize,
+}
+
+pub trait RenderEngine: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    fn threads(&self) -> usize {
+        1
+    }
+
+    fn calc(&self, params: &RenderParams) -> FractalResult<Vec<Color>>;
+
+    fn render(&self, req: &FractalRequest) -> FractalResult<RenderResult> {
+        let params = RenderParams::new(req, resolve_palette(&req.palette)?);
+        self.render_params(req, &params)
+    }
+
+    // for callers that need the params of the request afterwards, e.g. to encode the image
+    fn render_params(
+        &self,
+        req: &FractalRequest,
+        params: &RenderParams,
+    ) -> FractalResult<RenderResult> {
+        print_debug(params);
+
+        let start = Instant::now();
+        let pixels = self.calc(params)?;
+        let duration_ms = start.elapsed().as_millis();
+        info!("engine {} took {} ms", self.name(), duration_ms);
+
+        save_outputs(&pixels, params, req)?;
+
+        let fractal = FractalImage {
+            width: params.width,
+            height: params.height,
+            pixels,
+        };
+
+        Ok(RenderResult {
+            fractal,
+            duration_ms,
+            threads: self.threads(),
+        })
+    }
+}
+
+pub fn engines() -> Vec<Box<dyn RenderEngine>> {
+    vec![
+        Box::new(SingleThreadedEngine),
+        Box::new(MultiThreadedEngine),
+        Box::new(MultiThreadedOpt1Engine),
+        Box::new(MultiThreadedOpt2Engine),
+        Box::new(RayonEngine),
+        Box::new(CrossbeamTilesEngine),
+    ]
+}
+
+pub fn engine_by_name(name: &str) -> Option<Box<dyn RenderEngine>> {
+    engines().into_iter().find(|e| e.name() == name)
+}
+
+pub fn engine(name: &str) -> FractalResult<Box<dyn RenderEngine>> {
+    engine_by_name(name).ok_or_else(|| FractalError::UnknownEngine(name.to_string()))
+}
+
+pub fn engine_names() -> Vec<&'static str> {
+    engines().iter().map(|e| e.name()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::color::{color16, BLUE, RED};
+    use crate::coloring::{Coloring, Palette};
+    use crate::gradient::{Gradient, Interpolation};
+    use crate::complex::ComplexNumber;
+    use crate::error::FractalError;
+    use crate::models::{FractalRequest, PaletteSpec};
+    use crate::render_engine::{engine_by_name, engine_names, engines, RenderParams};
+    use crate::viewport::AspectPolicy;
+
+    fn request() -> FractalRequest {
+        FractalRequest {
+            center: ComplexNumber { a: -0.8, b: 0.0 },
+            width: 64,
+            height: 48,
+            complex_width: 3.1,
+            max_iterations: 100,
+            palette: PaletteSpec::Name("wild.map".to_string()),
+            coloring: Coloring::Modulo,
+            palette_offset: 0.0,
+            x_tiles: 4,
+            y_tiles: 3,
+            zoom: 0.7,
+            rotation: 0.0,
+            aspect: AspectPolicy::FitWidth,
+            name: "test".to_string(),
+            output: vec![],
+        }
+    }
+
+    #[test]
+    fn test_engine_names_are_unique() {
+        let mut names = engine_names();
+        let cnt = names.len();
+        names.sort();
+        names.dedup();
+        assert_eq!(names.len(), cnt);
+        assert!(engine_by_name("rayon").is_some());
+        assert!(engine_by_name("does-not-exist").is_none());
+    }
+
+    #[test]
+    fn test_unknown_palette() {
+        let mut req = request();
+        req.palette = PaletteSpec::Name("does-not-exist.map".to_string());
+        let res = engine_by_name("singlethreaded").unwrap().render(&req);
+        assert!(matches!(res, Err(FractalError::PaletteNotFound(_))));
+    }
+
+    #[test]
+    fn test_engines_calculate_the_same_pixels() {
+        let params = RenderParams::new(&request(), Palette::Colors(color16()));
+        let expected = engine_by_name("singlethreaded").unwrap().calc(&params).unwrap();
+        assert_eq!(expected.len(), 64 * 48);
+
+        for engine in engines() {
+            let pixels = engine.calc(&params).unwrap();
+            assert!(pixels == expected, "engine {} differs", engine.name());
+        }
+    }
+
+    #[test]
+    fn test_engines_smooth_gradient() {
+        let mut req = request();
+        req.coloring = Coloring::Smooth;
+        let gradient = Gradient::new(&[RED, BLUE], Interpolation::OkLab, 32);
+        let params = RenderParams::new(&req, Palette::Gradient(gradient));
+        let expected = engine_by_name("singlethreaded").unwrap().calc(&params).unwrap();
+
+        for engine in engines() {
+            let pixels = engine.calc(&params).unwrap();
+            assert!(pixels == expected, "engine {} differs", engine.name());
+        }
+    }
+
+    #[test]
+    fn test_engines_rotated() {
+        let mut req = request();
+        req.rotation = 90.0;
+        req.width = 48;
+        req.height = 48;
+        let params = RenderParams::new(&req, Palette::Colors(color16()));
+        let expected = engine_by_name("singlethreaded").unwrap().calc(&params).unwrap();
+        for engine in engines() {
+            let pixels = engine.calc(&params).unwrap();
+            assert!(pixels == expected, "engine {} differs", engine.name());
+        }
+
+        // the set is symmetric to the real axis, so a quarter turn around a center on it
+        // swaps rows and columns. pixels on the border of the set may differ by rounding
+        req.rotation = 0.0;
+        let params = RenderParams::new(&req, Palette::Colors(color16()));
+        let unrotated = engine_by_name("singlethreaded").unwrap().calc(&params).unwrap();
+        let mut turned = 0;
+        for y in 1..47 {
+            for x in 1..47 {
+                if expected[y * 48 + x] == unrotated[x * 48 + y] {
+                    turned += 1;
+                }
+            }
+        }
+        assert!(turned > 46 * 46 * 9 / 10, "{}", turned);
+    }
+}
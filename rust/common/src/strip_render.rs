@@ -0,0 +1,403 @@
+use std::fs;
+use std::fs::File;
+use std::io;
+use std::io::{BufReader, BufWriter};
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+use log::info;
+use rayon::prelude::*;
+use serde_derive::{Deserialize, Serialize};
+
+use crate::error::{FractalError, FractalResult};
+use crate::fractal::calc_fractal_color;
+use crate::models::FractalRequest;
+use crate::palette::resolve_palette;
+use crate::png_metadata::png_encoder;
+use crate::render_engine::RenderParams;
+
+// images too big for the memory are calculated in horizontal strips. every finished strip is kept
+// as raw RGB in <output>.strips/ until the PNG is written, so an interrupted render only
+// calculates the missing strips when it is started again
+#[derive(Clone, Debug, PartialEq)]
+pub struct StripOptions {
+    pub strip_height: u32,
+    // calculate at most this many strips now, e.g. to spread a render over several nights
+    pub max_strips: Option<u32>,
+}
+
+impl Default for StripOptions {
+    fn default() -> Self {
+        StripOptions {
+            strip_height: 64,
+            max_strips: None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct StripProgress {
+    pub strips: u32,
+    pub finished: u32,
+    // calculated by this call, the others were left over from an earlier one
+    pub calculated: u32,
+}
+
+impl StripProgress {
+    pub fn is_complete(&self) -> bool {
+        self.finished == self.strips
+    }
+}
+
+const REQUEST_FILE: &str = "request.json";
+
+pub fn strip_dir(output: &Path) -> PathBuf {
+    let mut dir = output.as_os_str().to_owned();
+    dir.push(".strips");
+    PathBuf::from(dir)
+}
+
+// an image that is calculated strip by strip, e.g. a render or an exponential map
+pub trait StripImage: Sync {
+    fn width(&self) -> u32;
+    fn height(&self) -> u32;
+    // stored in the PNG metadata
+    fn request(&self) -> &FractalRequest;
+    // strips are only used again for an image with the same description
+    fn description(&self) -> FractalResult<serde_json::Value>;
+    // the RGB bytes of the rows y0..y1
+    fn calc_rows(&self, y0: u32, y1: u32) -> Vec<u8>;
+}
+
+struct RequestImage<'a> {
+    req: &'a FractalRequest,
+    params: RenderParams,
+}
+
+impl StripImage for RequestImage<'_> {
+    fn width(&self) -> u32 {
+        self.params.width
+    }
+
+    fn height(&self) -> u32 {
+        self.params.height
+    }
+
+    fn request(&self) -> &FractalRequest {
+        self.req
+    }
+
+    fn description(&self) -> FractalResult<serde_json::Value> {
+        Ok(serde_json::to_value(self.req)?)
+    }
+
+    fn calc_rows(&self, y0: u32, y1: u32) -> Vec<u8> {
+        calc_strip(&self.params, y0, y1)
+    }
+}
+
+// writes the PNG once all strips are finished and removes the strips
+pub fn render_strips(
+    req: &FractalRequest,
+    output: &Path,
+    options: &StripOptions,
+) -> FractalResult<StripProgress> {
+    let image = RequestImage {
+        req,
+        params: RenderParams::new(req, resolve_palette(&req.palette)?),
+    };
+    render_image_strips(&image, output, options)
+}
+
+pub fn render_image_strips(
+    image: &dyn StripImage,
+    output: &Path,
+    options: &StripOptions,
+) -> FractalResult<StripProgress> {
+    if options.strip_height == 0 {
+        return Err(FractalError::InvalidRequest(
+            "strip height must be at least 1".to_string(),
+        ));
+    }
+    let (width, height) = (image.width(), image.height());
+    let dir = strip_dir(output);
+    prepare_strip_dir(&dir, &image.description()?, options.strip_height)?;
+
+    let strips = height.div_ceil(options.strip_height);
+    let mut progress = StripProgress {
+        strips,
+        finished: 0,
+        calculated: 0,
+    };
+    for strip in 0..strips {
+        let (y0, y1) = strip_rows(strip, options.strip_height, height);
+        let path = strip_path(&dir, strip);
+        if is_finished(&path, width, y1 - y0) {
+            progress.finished += 1;
+            continue;
+        }
+        if options
+            .max_strips
+            .is_some_and(|max| progress.calculated >= max)
+        {
+            continue;
+        }
+
+        let start = Instant::now();
+        let rgb = image.calc_rows(y0, y1);
+        // a strip is only complete once it was renamed, a half written one is calculated again
+        let tmp = path.with_extension("tmp");
+        fs::write(&tmp, rgb).map_err(|e| FractalError::io(&tmp.to_string_lossy(), e))?;
+        fs::rename(&tmp, &path).map_err(|e| FractalError::io(&path.to_string_lossy(), e))?;
+        progress.finished += 1;
+        progress.calculated += 1;
+        info!(
+            "strip {} / {}  rows {}..{}  took {} ms",
+            strip + 1,
+            strips,
+            y0,
+            y1,
+            start.elapsed().as_millis()
+        );
+    }
+
+    if progress.is_complete() {
+        assemble_png(image, &dir, output, options.strip_height)?;
+        fs::remove_dir_all(&dir).map_err(|e| FractalError::io(&dir.to_string_lossy(), e))?;
+    }
+    Ok(progress)
+}
+
+// what the strips in the directory were calculated for
+#[derive(Deserialize, Serialize, Debug)]
+struct StripState {
+    strip_height: u32,
+    image: serde_json::Value,
+}
+
+// the strips of a different image or strip height must not end up in this image
+fn prepare_strip_dir(
+    dir: &Path,
+    description: &serde_json::Value,
+    strip_height: u32,
+) -> FractalResult<()> {
+    let request_file = dir.join(REQUEST_FILE);
+    let json = serde_json::to_string_pretty(&StripState {
+        strip_height,
+        image: description.clone(),
+    })?;
+    match fs::read_to_string(&request_file) {
+        Ok(existing) if existing == json => Ok(()),
+        Ok(existing) => match serde_json::from_str::<StripState>(&existing) {
+            Ok(state) if state.image == *description => Err(FractalError::InvalidRequest(format!(
+                "the strips in {} are {} rows high, not {}, delete it to start again",
+                dir.display(),
+                state.strip_height,
+                strip_height
+            ))),
+            _ => Err(FractalError::InvalidRequest(format!(
+                "{} contains the strips of a different request, delete it to start again",
+                dir.display()
+            ))),
+        },
+        Err(_) => {
+            fs::create_dir_all(dir).map_err(|e| FractalError::io(&dir.to_string_lossy(), e))?;
+            fs::write(&request_file, json)
+                .map_err(|e| FractalError::io(&request_file.to_string_lossy(), e))
+        }
+    }
+}
+
+pub fn strip_rows(strip: u32, strip_height: u32, height: u32) -> (u32, u32) {
+    let y0 = strip * strip_height;
+    (y0, (y0 + strip_height).min(height))
+}
+
+fn strip_path(dir: &Path, strip: u32) -> PathBuf {
+    dir.join(format!("strip_{:06}.rgb", strip))
+}
+
+fn is_finished(path: &Path, width: u32, rows: u32) -> bool {
+    fs::metadata(path).is_ok_and(|m| m.len() == width as u64 * rows as u64 * 3)
+}
+
+// the RGB bytes of the rows y0..y1
+pub fn calc_strip(params: &RenderParams, y0: u32, y1: u32) -> Vec<u8> {
+    (y0..y1)
+        .into_par_iter()
+        .flat_map_iter(|y| {
+            (0..params.width).flat_map(move |x| {
+                let c = calc_fractal_color(x, y, params);
+                [c.r, c.g, c.b]
+            })
+        })
+        .collect()
+}
+
+// only one strip at a time is read, the PNG encoder streams the rows to the file
+fn assemble_png(
+    image: &dyn StripImage,
+    dir: &Path,
+    output: &Path,
+    strip_height: u32,
+) -> FractalResult<()> {
+    let start = Instant::now();
+    let (width, height) = (image.width(), image.height());
+    let tmp = output.with_extension("png.tmp");
+    let file = File::create(&tmp).map_err(|e| FractalError::io(&tmp.to_string_lossy(), e))?;
+
+    let encoder = png_encoder(
+        BufWriter::new(file),
+        width,
+        height,
+        png::BitDepth::Eight,
+        image.request(),
+    )?;
+    let mut writer = encoder.write_header()?;
+    let mut stream = writer.stream_writer_with_size(width as usize * 3 * 16)?;
+    for strip in 0..height.div_ceil(strip_height) {
+        let path = strip_path(dir, strip);
+        let file = File::open(&path).map_err(|e| FractalError::io(&path.to_string_lossy(), e))?;
+        io::copy(&mut BufReader::new(file), &mut stream)
+            .map_err(|e| FractalError::Encoding(e.to_string()))?;
+    }
+    stream.finish()?;
+    writer.finish()?;
+
+    fs::rename(&tmp, output).map_err(|e| FractalError::io(&output.to_string_lossy(), e))?;
+    info!(
+        "wrote {} in {} ms",
+        output.display(),
+        start.elapsed().as_millis()
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::PathBuf;
+
+    use crate::fractal_templates::basic;
+    use crate::models::FractalRequest;
+    use crate::png_metadata::read_request;
+    use crate::render_engine::engine_by_name;
+    use crate::strip_render::{render_strips, strip_dir, StripOptions};
+
+    fn request() -> FractalRequest {
+        let (mut req, _, _) = basic(true);
+        req.width = 40;
+        req.height = 30;
+        req.max_iterations = 100;
+        req
+    }
+
+    fn output(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("strip_render_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let output = dir.join(name);
+        let _ = fs::remove_file(&output);
+        let _ = fs::remove_dir_all(strip_dir(&output));
+        output
+    }
+
+    fn assert_matches_engine(req: &FractalRequest, png: &[u8]) {
+        let expected = engine_by_name("rayon").unwrap().render(req).unwrap();
+        let image = image::load_from_memory(png).unwrap().to_rgb8();
+        assert_eq!((image.width(), image.height()), (40, 30));
+        let pixels: Vec<u8> = expected
+            .fractal
+            .pixels
+            .iter()
+            .flat_map(|c| [c.r, c.g, c.b])
+            .collect();
+        assert!(image.into_raw() == pixels);
+    }
+
+    #[test]
+    fn test_strips_match_engine() {
+        let req = request();
+        let output = output("complete.png");
+        let options = StripOptions {
+            strip_height: 7,
+            max_strips: None,
+        };
+
+        let progress = render_strips(&req, &output, &options).unwrap();
+        assert_eq!((progress.strips, progress.calculated), (5, 5));
+        assert!(progress.is_complete());
+        assert!(!strip_dir(&output).exists());
+
+        let png = fs::read(&output).unwrap();
+        assert_eq!(read_request(&png).unwrap(), req);
+        assert_matches_engine(&req, &png);
+    }
+
+    #[test]
+    fn test_interrupted_render_continues() {
+        let req = request();
+        let output = output("resumed.png");
+        let options = StripOptions {
+            strip_height: 8,
+            max_strips: Some(3),
+        };
+
+        let progress = render_strips(&req, &output, &options).unwrap();
+        assert_eq!((progress.finished, progress.calculated), (3, 3));
+        assert!(!output.exists());
+
+        // a strip that was cut off is calculated again
+        let dir = strip_dir(&output);
+        fs::write(dir.join("strip_000001.rgb"), [0, 0, 0]).unwrap();
+        let progress = render_strips(&req, &output, &options).unwrap();
+        assert_eq!(
+            (progress.strips, progress.finished, progress.calculated),
+            (4, 4, 2)
+        );
+
+        assert_matches_engine(&req, &fs::read(&output).unwrap());
+    }
+
+    #[test]
+    fn test_strips_of_another_request() {
+        let req = request();
+        let output = output("other.png");
+        let options = StripOptions {
+            strip_height: 8,
+            max_strips: Some(1),
+        };
+        render_strips(&req, &output, &options).unwrap();
+
+        let mut other = req.clone();
+        other.max_iterations = 200;
+        assert!(render_strips(&other, &output, &options).is_err());
+        assert!(render_strips(&req, &output, &options).is_ok());
+    }
+
+    #[test]
+    fn test_strips_of_another_strip_height() {
+        let req = request();
+        let output = output("strip_height.png");
+        let options = StripOptions {
+            strip_height: 8,
+            max_strips: Some(2),
+        };
+        let progress = render_strips(&req, &output, &options).unwrap();
+        assert_eq!(progress.finished, 2);
+
+        // the size check alone isn't enough: the last strip of 8 rows has rows 24..30, with 6 rows
+        // per strip it would be taken for rows 18..24
+        let six_rows = StripOptions {
+            strip_height: 6,
+            max_strips: None,
+        };
+        assert!(render_strips(&req, &output, &six_rows).is_err());
+
+        let options = StripOptions {
+            max_strips: None,
+            ..options
+        };
+        render_strips(&req, &output, &options).unwrap();
+        assert_matches_engine(&req, &fs::read(&output).unwrap());
+    }
+}
@@ -0,0 +1,383 @@
+use serde_derive::{Deserialize, Serialize};
+
+use crate::color::{Color, BLACK};
+use crate::error::{FractalError, FractalResult};
+use crate::image_tile::Tile;
+use crate::iteration_buffer::{IterationBuffer, ITERATIONS_INSIDE};
+use crate::render_engine::RenderParams;
+
+// the binary websocket messages of api/crossbeamtiles, one tile per message. all numbers are
+// little endian:
+//
+//   0  4 bytes  magic "FTIL"
+//   4  u8       version, TILE_FRAME_VERSION
+//   5  u8       encoding, see TileEncoding
+//   6  u8       flags, bit 0: the pixels are deflate compressed
+//   7  u8       reserved, 0
+//   8  u32      idx of the tile
+//  12  u32 x4   x, y, width, height of the tile in the image
+//  28           the pixels row by row, width * height * bytes_per_pixel bytes before compression
+pub const TILE_FRAME_VERSION: u8 = 1;
+const MAGIC: &[u8; 4] = b"FTIL";
+const HEADER_LEN: usize = 28;
+const FLAG_DEFLATE: u8 = 1;
+
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum TileEncoding {
+    #[default]
+    Rgb,
+    Rgba,
+    // f32 per pixel, NaN inside the set. the client colors the tile itself
+    Iterations,
+}
+
+impl TileEncoding {
+    pub fn bytes_per_pixel(&self) -> usize {
+        match self {
+            TileEncoding::Rgb => 3,
+            TileEncoding::Rgba | TileEncoding::Iterations => 4,
+        }
+    }
+
+    fn to_byte(self) -> u8 {
+        match self {
+            TileEncoding::Rgb => 0,
+            TileEncoding::Rgba => 1,
+            TileEncoding::Iterations => 2,
+        }
+    }
+
+    fn from_byte(b: u8) -> Option<TileEncoding> {
+        match b {
+            0 => Some(TileEncoding::Rgb),
+            1 => Some(TileEncoding::Rgba),
+            2 => Some(TileEncoding::Iterations),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct TileFrame {
+    pub idx: u32,
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+    pub encoding: TileEncoding,
+    // uncompressed
+    pub data: Vec<u8>,
+}
+
+impl TileFrame {
+    // the iterations of the tile, row by row, e.g. a part of an IterationBuffer
+    pub fn from_iterations(tile: &Tile, values: &[Option<f64>]) -> TileFrame {
+        TileFrame {
+            idx: tile.get_idx() as u32,
+            x: tile.x_from() as u32,
+            y: tile.y_from() as u32,
+            width: (tile.x_to() - tile.x_from()) as u32,
+            height: (tile.y_to() - tile.y_from()) as u32,
+            encoding: TileEncoding::Iterations,
+            data: iteration_bytes(values),
+        }
+    }
+
+    // the whole image as one tile
+    pub fn from_buffer(buffer: &IterationBuffer) -> TileFrame {
+        TileFrame {
+            idx: 0,
+            x: 0,
+            y: 0,
+            width: buffer.width,
+            height: buffer.height,
+            encoding: TileEncoding::Iterations,
+            data: iteration_bytes(&buffer.values),
+        }
+    }
+
+    // the tile in the encoding from the f64 iterations, the colors are the same as the colors
+    // of the whole image. only the Iterations encoding has f32 values
+    pub fn from_values(
+        tile: &Tile,
+        values: &[Option<f64>],
+        encoding: TileEncoding,
+        params: &RenderParams,
+    ) -> TileFrame {
+        let frame = TileFrame::from_iterations(tile, values);
+        let data = match encoding {
+            TileEncoding::Iterations => return frame,
+            TileEncoding::Rgb => color_values(values, params)
+                .iter()
+                .flat_map(|c| [c.r, c.g, c.b])
+                .collect(),
+            TileEncoding::Rgba => color_values(values, params)
+                .iter()
+                .flat_map(|c| [c.r, c.g, c.b, 255])
+                .collect(),
+        };
+        TileFrame {
+            encoding,
+            data,
+            ..frame
+        }
+    }
+
+    pub fn encode(&self, compress: bool) -> Vec<u8> {
+        let payload = if compress {
+            miniz_oxide::deflate::compress_to_vec(&self.data, 1)
+        } else {
+            self.data.clone()
+        };
+        let mut frame = Vec::with_capacity(HEADER_LEN + payload.len());
+        frame.extend(MAGIC);
+        frame.extend([
+            TILE_FRAME_VERSION,
+            self.encoding.to_byte(),
+            if compress { FLAG_DEFLATE } else { 0 },
+            0,
+        ]);
+        for n in [self.idx, self.x, self.y, self.width, self.height] {
+            frame.extend(n.to_le_bytes());
+        }
+        frame.extend(payload);
+        frame
+    }
+
+    pub fn decode(frame: &[u8]) -> FractalResult<TileFrame> {
+        let invalid = |message: String| FractalError::InvalidRequest(message);
+        if frame.len() < HEADER_LEN || &frame[0..4] != MAGIC {
+            return Err(invalid("not a tile frame".to_string()));
+        }
+        if frame[4] != TILE_FRAME_VERSION {
+            return Err(invalid(format!(
+                "tile frame version {} is not supported, expected {}",
+                frame[4], TILE_FRAME_VERSION
+            )));
+        }
+        let encoding = TileEncoding::from_byte(frame[5])
+            .ok_or_else(|| invalid(format!("unknown tile encoding {}", frame[5])))?;
+        let u32_at = |pos: usize| u32::from_le_bytes(frame[pos..pos + 4].try_into().unwrap());
+        let (idx, x, y, width, height) =
+            (u32_at(8), u32_at(12), u32_at(16), u32_at(20), u32_at(24));
+
+        let len = width as usize * height as usize * encoding.bytes_per_pixel();
+        let payload = &frame[HEADER_LEN..];
+        let data = if frame[6] & FLAG_DEFLATE != 0 {
+            miniz_oxide::inflate::decompress_to_vec_with_limit(payload, len)
+                .map_err(|e| invalid(format!("can't decompress tile {}: {}", idx, e)))?
+        } else {
+            payload.to_vec()
+        };
+        if data.len() != len {
+            return Err(invalid(format!(
+                "tile {} has {} bytes instead of {}",
+                idx,
+                data.len(),
+                len
+            )));
+        }
+        Ok(TileFrame {
+            idx,
+            x,
+            y,
+            width,
+            height,
+            encoding,
+            data,
+        })
+    }
+
+    // RGBA for the canvas, None for iterations
+    pub fn rgba(&self) -> Option<Vec<u8>> {
+        match self.encoding {
+            TileEncoding::Rgb => Some(
+                self.data
+                    .chunks_exact(3)
+                    .flat_map(|c| [c[0], c[1], c[2], 255])
+                    .collect(),
+            ),
+            TileEncoding::Rgba => Some(self.data.clone()),
+            TileEncoding::Iterations => None,
+        }
+    }
+
+    // None for pixels inside the set, only for iterations
+    pub fn iterations(&self) -> Option<Vec<Option<f64>>> {
+        match self.encoding {
+            TileEncoding::Iterations => Some(
+                self.data
+                    .chunks_exact(4)
+                    .map(|b| {
+                        let value = f32::from_le_bytes([b[0], b[1], b[2], b[3]]);
+                        (!value.is_nan()).then_some(value as f64)
+                    })
+                    .collect(),
+            ),
+            _ => None,
+        }
+    }
+
+    // the colors of the tile, iterations are colored with the palette of params
+    pub fn colors(&self, params: &RenderParams) -> Vec<Color> {
+        match (self.rgba(), self.iterations()) {
+            (Some(rgba), _) => rgba
+                .chunks_exact(4)
+                .map(|c| Color {
+                    r: c[0],
+                    g: c[1],
+                    b: c[2],
+                })
+                .collect(),
+            (None, Some(iterations)) => color_values(&iterations, params),
+            (None, None) => vec![],
+        }
+    }
+}
+
+fn iteration_bytes(values: &[Option<f64>]) -> Vec<u8> {
+    values
+        .iter()
+        .flat_map(|v| v.map_or(ITERATIONS_INSIDE, |v| v as f32).to_le_bytes())
+        .collect()
+}
+
+fn color_values(values: &[Option<f64>], params: &RenderParams) -> Vec<Color> {
+    values
+        .iter()
+        .map(|value| match value {
+            Some(value) => params
+                .palette
+                .color(params.coloring, value + params.palette_offset),
+            None => BLACK,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::coloring::Coloring;
+    use crate::complex::ComplexNumber;
+    use crate::fractal::calc_fractal_iterations;
+    use crate::fractal_templates::basic;
+    use crate::image_tile::{tiles, Tile};
+    use crate::palette::resolve_palette;
+    use crate::render_engine::{engine_by_name, RenderParams};
+    use crate::tile_frame::{TileEncoding, TileFrame};
+
+    const ENCODINGS: [TileEncoding; 3] = [
+        TileEncoding::Rgb,
+        TileEncoding::Rgba,
+        TileEncoding::Iterations,
+    ];
+
+    fn params() -> RenderParams {
+        let (mut req, _, _) = basic(true);
+        req.width = 40;
+        req.height = 30;
+        req.max_iterations = 200;
+        RenderParams::new(&req, resolve_palette(&req.palette).unwrap())
+    }
+
+    // the tile like the websocket session calculates it
+    fn calc(tile: &Tile, params: &RenderParams, encoding: TileEncoding) -> TileFrame {
+        let values: Vec<_> = (tile.y_from()..tile.y_to())
+            .flat_map(|y| (tile.x_from()..tile.x_to()).map(move |x| (x as u32, y as u32)))
+            .map(|(x, y)| calc_fractal_iterations(x, y, params))
+            .collect();
+        TileFrame::from_values(tile, &values, encoding, params)
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let params = params();
+        let tile = tiles(params.width, params.height, 3, 2).nth(5).unwrap();
+        for encoding in ENCODINGS {
+            let frame = calc(&tile, &params, encoding);
+            assert_eq!(
+                (frame.x, frame.y, frame.width, frame.height),
+                (13, 15, 13, 15)
+            );
+            assert_eq!(frame.data.len(), 13 * 15 * encoding.bytes_per_pixel());
+            for compress in [false, true] {
+                let decoded = TileFrame::decode(&frame.encode(compress)).unwrap();
+                assert_eq!(decoded, frame);
+            }
+        }
+    }
+
+    #[test]
+    fn test_colors_match_engine() {
+        let params = params();
+        let expected = engine_by_name("rayon").unwrap().calc(&params).unwrap();
+        for encoding in ENCODINGS {
+            let mut pixels = vec![Default::default(); expected.len()];
+            for tile in tiles(params.width, params.height, 3, 2) {
+                let frame = calc(&tile, &params, encoding);
+                let colors = TileFrame::decode(&frame.encode(true))
+                    .unwrap()
+                    .colors(&params);
+                for (i, c) in colors.into_iter().enumerate() {
+                    let x = frame.x + i as u32 % frame.width;
+                    let y = frame.y + i as u32 / frame.width;
+                    pixels[(y * params.width + x) as usize] = c;
+                }
+            }
+            assert!(pixels == expected, "{:?}", encoding);
+        }
+    }
+
+    #[test]
+    fn test_smooth_far_outside() {
+        // smooth iterations of points that escape at once are negative, they are not inside
+        let (mut req, _, _) = basic(true);
+        req.width = 40;
+        req.height = 30;
+        req.max_iterations = 200;
+        req.coloring = Coloring::Smooth;
+        req.center = ComplexNumber { a: 0.0, b: 0.0 };
+        req.complex_width = 400.0;
+        req.zoom = 1.0;
+        let params = RenderParams::new(&req, resolve_palette(&req.palette).unwrap());
+
+        for tile in tiles(params.width, params.height, 2, 2) {
+            let frame = calc(&tile, &params, TileEncoding::Iterations);
+            let iterations = TileFrame::decode(&frame.encode(false)).unwrap();
+            let values = iterations.iterations().unwrap();
+            assert!(values.iter().any(|v| v.is_some_and(|v| v < 0.0)));
+            let rgb = calc(&tile, &params, TileEncoding::Rgb);
+            assert_eq!(iterations.colors(&params), rgb.colors(&params));
+        }
+    }
+
+    #[test]
+    fn test_from_iterations() {
+        let params = params();
+        let tile = tiles(params.width, params.height, 2, 2).nth(3).unwrap();
+        let iterations = calc(&tile, &params, TileEncoding::Iterations);
+        let values = iterations.iterations().unwrap();
+        assert!(values.iter().any(|v| v.is_none()));
+        assert_eq!(TileFrame::from_iterations(&tile, &values), iterations);
+
+        // colors can't be turned back into iterations
+        let rgb = calc(&tile, &params, TileEncoding::Rgb);
+        assert_eq!(rgb.iterations(), None);
+        assert_eq!(iterations.rgba(), None);
+    }
+
+    #[test]
+    fn test_invalid_frames() {
+        let params = params();
+        let tile = tiles(params.width, params.height, 1, 1).next().unwrap();
+        let frame = calc(&tile, &params, TileEncoding::Rgb).encode(false);
+
+        assert!(TileFrame::decode(&frame[..10]).is_err());
+        assert!(TileFrame::decode(&frame[..frame.len() - 1]).is_err());
+        let mut version = frame.clone();
+        version[4] = 2;
+        assert!(TileFrame::decode(&version).is_err());
+        let mut encoding = frame.clone();
+        encoding[5] = 9;
+        assert!(TileFrame::decode(&encoding).is_err());
+    }
+}
@@ -0,0 +1,378 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use image::imageops::FilterType;
+use image::ImageOutputFormat;
+use log::info;
+use serde_derive::{Deserialize, Serialize};
+
+use crate::coloring::Coloring;
+use crate::complex::ComplexNumber;
+use crate::error::{FractalError, FractalResult};
+use crate::fractal_calculation_rayon::RayonEngine;
+use crate::models::{FractalRequest, PaletteSpec};
+use crate::palette::resolve_palette;
+use crate::png_metadata::encode_png_with_request;
+use crate::render_engine::{RenderEngine, RenderParams};
+use crate::utils::{stable_hash, write_file_atomic};
+use crate::viewport::AspectPolicy;
+
+pub const TILE_SIZE: u32 = 256;
+// f64 runs out of precision a bit after this
+pub const MAX_TILE_ZOOM: u32 = 40;
+// every level has 4 times the tiles of the one before, level 8 already has 65536
+pub const MAX_PYRAMID_ZOOM: u32 = 8;
+
+// zoom level 0 is a single tile showing this square, every level halves the width of a tile
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct TileRegion {
+    pub center: ComplexNumber,
+    pub complex_width: f64,
+}
+
+impl Default for TileRegion {
+    fn default() -> Self {
+        TileRegion {
+            center: ComplexNumber { a: -0.75, b: 0.0 },
+            complex_width: 4.0,
+        }
+    }
+}
+
+// everything but the position, the same for all tiles of a pyramid
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct TileSettings {
+    pub max_iterations: u32,
+    #[serde(default)]
+    pub palette: PaletteSpec,
+    #[serde(default)]
+    pub coloring: Coloring,
+    #[serde(default)]
+    pub palette_offset: f64,
+}
+
+impl Default for TileSettings {
+    fn default() -> Self {
+        TileSettings {
+            max_iterations: 1000,
+            palette: PaletteSpec::default(),
+            coloring: Coloring::Smooth,
+            palette_offset: 0.0,
+        }
+    }
+}
+
+// x and y count from the top left corner like in XYZ slippy maps
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct TileCoord {
+    pub z: u32,
+    pub x: u32,
+    pub y: u32,
+}
+
+impl TileCoord {
+    pub fn new(z: u32, x: u32, y: u32) -> FractalResult<TileCoord> {
+        if z > MAX_TILE_ZOOM {
+            return Err(FractalError::InvalidRequest(format!(
+                "zoom level {} is bigger than {}",
+                z, MAX_TILE_ZOOM
+            )));
+        }
+        let tiles = 1u64 << z;
+        if x as u64 >= tiles || y as u64 >= tiles {
+            return Err(FractalError::InvalidRequest(format!(
+                "tile {}/{} is outside of zoom level {} with {} tiles per row",
+                x, y, z, tiles
+            )));
+        }
+        Ok(TileCoord { z, x, y })
+    }
+}
+
+// the request that renders one tile
+pub fn tile_request(
+    settings: &TileSettings,
+    region: &TileRegion,
+    coord: TileCoord,
+) -> FractalRequest {
+    let width = region.complex_width / (1u64 << coord.z) as f64;
+    let re_min = region.center.a - region.complex_width / 2.0;
+    let img_max = region.center.b + region.complex_width / 2.0;
+
+    FractalRequest {
+        center: ComplexNumber {
+            a: re_min + (coord.x as f64 + 0.5) * width,
+            b: img_max - (coord.y as f64 + 0.5) * width,
+        },
+        width: TILE_SIZE,
+        height: TILE_SIZE,
+        complex_width: width,
+        max_iterations: settings.max_iterations,
+        palette: settings.palette.clone(),
+        coloring: settings.coloring,
+        palette_offset: settings.palette_offset,
+        x_tiles: 1,
+        y_tiles: 1,
+        zoom: 1.0,
+        rotation: 0.0,
+        aspect: AspectPolicy::FitWidth,
+        name: format!("tile_{}_{}_{}", coord.z, coord.x, coord.y),
+        output: vec![],
+    }
+}
+
+pub fn render_tile(
+    settings: &TileSettings,
+    region: &TileRegion,
+    coord: TileCoord,
+) -> FractalResult<Vec<u8>> {
+    let req = tile_request(settings, region, coord);
+    let params = RenderParams::new(&req, resolve_palette(&req.palette)?);
+    let pixels = RayonEngine.calc(&params)?;
+    encode_png_with_request(&pixels, TILE_SIZE, TILE_SIZE, &req)
+}
+
+// rendered tiles as PNG files in <dir>/<hash of settings and region>/<z>/<x>/<y>.png
+#[derive(Clone, Debug)]
+pub struct TileCache {
+    pub dir: PathBuf,
+}
+
+impl TileCache {
+    pub fn new(dir: PathBuf) -> TileCache {
+        TileCache { dir }
+    }
+
+    pub fn path(&self, settings: &TileSettings, region: &TileRegion, coord: TileCoord) -> PathBuf {
+        self.dir
+            .join(format!("{:016x}", pyramid_hash(settings, region)))
+            .join(coord.z.to_string())
+            .join(coord.x.to_string())
+            .join(format!("{}.png", coord.y))
+    }
+
+    pub fn get_or_render(
+        &self,
+        settings: &TileSettings,
+        region: &TileRegion,
+        coord: TileCoord,
+    ) -> FractalResult<Vec<u8>> {
+        let path = self.path(settings, region, coord);
+        if let Ok(png) = fs::read(&path) {
+            return Ok(png);
+        }
+
+        let png = render_tile(settings, region, coord)?;
+        write_file_atomic(&path, &png)?;
+        Ok(png)
+    }
+}
+
+fn pyramid_hash(settings: &TileSettings, region: &TileRegion) -> u64 {
+    let json = serde_json::json!({ "settings": settings, "region": region }).to_string();
+    stable_hash(json.as_bytes())
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, Default, PartialEq)]
+pub enum PyramidLayout {
+    // <dir>/<z>/<x>/<y>.png like OpenStreetMap
+    #[default]
+    Xyz,
+    // <dir>/<name>.dzi and <dir>/<name>_files/<level>/<column>_<row>.png for OpenSeadragon
+    DeepZoom,
+}
+
+// all tiles of the levels 0..=max_zoom, returns the number of files written
+pub fn generate_pyramid(
+    settings: &TileSettings,
+    region: &TileRegion,
+    max_zoom: u32,
+    dir: &Path,
+    layout: PyramidLayout,
+    name: &str,
+) -> FractalResult<usize> {
+    if max_zoom > MAX_PYRAMID_ZOOM {
+        return Err(FractalError::InvalidRequest(format!(
+            "a pyramid has at most {} levels",
+            MAX_PYRAMID_ZOOM + 1
+        )));
+    }
+
+    let mut files = 0;
+    for z in 0..=max_zoom {
+        for x in 0..1 << z {
+            for y in 0..1 << z {
+                let coord = TileCoord { z, x, y };
+                let png = render_tile(settings, region, coord)?;
+                let path = match layout {
+                    PyramidLayout::Xyz => dir.join(format!("{}/{}/{}.png", z, x, y)),
+                    PyramidLayout::DeepZoom => deep_zoom_tile(dir, name, z + TILE_LEVELS, x, y),
+                };
+                write_file_atomic(&path, &png)?;
+                files += 1;
+            }
+        }
+        info!("pyramid level {} finished, {} tiles", z, 1u64 << (2 * z));
+    }
+
+    if layout == PyramidLayout::DeepZoom {
+        files += write_deep_zoom_levels(max_zoom, dir, name)?;
+    }
+    Ok(files)
+}
+
+// deep zoom level 8 is the first with a full 256x256 tile
+const TILE_LEVELS: u32 = 8;
+
+fn deep_zoom_tile(dir: &Path, name: &str, level: u32, column: u32, row: u32) -> PathBuf {
+    dir.join(format!("{}_files/{}/{}_{}.png", name, level, column, row))
+}
+
+// deep zoom starts with a 1x1 image, the levels below a full tile are the scaled down first tile
+fn write_deep_zoom_levels(max_zoom: u32, dir: &Path, name: &str) -> FractalResult<usize> {
+    let size = TILE_SIZE << max_zoom;
+    let dzi = format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+         <Image xmlns=\"http://schemas.microsoft.com/deepzoom/2008\" Format=\"png\" Overlap=\"0\" TileSize=\"{}\">\n\
+         \x20 <Size Width=\"{}\" Height=\"{}\"/>\n\
+         </Image>\n",
+        TILE_SIZE, size, size
+    );
+    write_file_atomic(&dir.join(format!("{}.dzi", name)), dzi.as_bytes())?;
+
+    // the first full tile was written by generate_pyramid
+    let first = image::open(deep_zoom_tile(dir, name, TILE_LEVELS, 0, 0))?.to_rgb8();
+    for level in 0..TILE_LEVELS {
+        let size = 1 << level;
+        let scaled = image::imageops::resize(&first, size, size, FilterType::Triangle);
+        let mut png = std::io::Cursor::new(vec![]);
+        scaled.write_to(&mut png, ImageOutputFormat::Png)?;
+        write_file_atomic(&deep_zoom_tile(dir, name, level, 0, 0), png.get_ref())?;
+    }
+    Ok(TILE_LEVELS as usize + 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use crate::tile_pyramid::{
+        generate_pyramid, tile_request, PyramidLayout, TileCache, TileCoord, TileRegion,
+        TileSettings,
+    };
+    use crate::viewport::Viewport;
+
+    fn settings() -> TileSettings {
+        TileSettings {
+            max_iterations: 50,
+            ..TileSettings::default()
+        }
+    }
+
+    #[test]
+    fn test_tile_coord() {
+        assert!(TileCoord::new(0, 0, 0).is_ok());
+        assert!(TileCoord::new(0, 1, 0).is_err());
+        assert!(TileCoord::new(3, 7, 7).is_ok());
+        assert!(TileCoord::new(3, 8, 0).is_err());
+        assert!(TileCoord::new(41, 0, 0).is_err());
+    }
+
+    #[test]
+    fn test_tiles_are_adjacent() {
+        let region = TileRegion::default();
+        let left = Viewport::from_request(&tile_request(
+            &settings(),
+            &region,
+            TileCoord { z: 2, x: 1, y: 2 },
+        ));
+        let right = Viewport::from_request(&tile_request(
+            &settings(),
+            &region,
+            TileCoord { z: 2, x: 2, y: 2 },
+        ));
+        let below = Viewport::from_request(&tile_request(
+            &settings(),
+            &region,
+            TileCoord { z: 2, x: 1, y: 3 },
+        ));
+
+        assert!((left.re_max() - right.re_min()).abs() < 1e-12);
+        assert!((left.img_min() - below.img_max()).abs() < 1e-12);
+        assert!((left.complex_width - 1.0).abs() < 1e-12);
+        // the top left tile starts at the top left corner of the region
+        let first = Viewport::from_request(&tile_request(
+            &settings(),
+            &region,
+            TileCoord { z: 5, x: 0, y: 0 },
+        ));
+        assert!((first.re_min() + 2.75).abs() < 1e-12);
+        assert!((first.img_max() - 2.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_cache() {
+        let dir = std::env::temp_dir().join(format!("tile_cache_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let cache = TileCache::new(dir.clone());
+        let region = TileRegion::default();
+        let coord = TileCoord { z: 1, x: 1, y: 0 };
+
+        let png = cache.get_or_render(&settings(), &region, coord).unwrap();
+        let path = cache.path(&settings(), &region, coord);
+        assert_eq!(fs::read(&path).unwrap(), png);
+
+        // the cached file is returned without rendering
+        fs::write(&path, b"cached").unwrap();
+        assert_eq!(
+            cache.get_or_render(&settings(), &region, coord).unwrap(),
+            b"cached"
+        );
+
+        // other settings are other files
+        let other = TileSettings {
+            max_iterations: 60,
+            ..settings()
+        };
+        assert_ne!(cache.path(&other, &region, coord), path);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_generate_pyramid() {
+        let dir = std::env::temp_dir().join(format!("tile_pyramid_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let region = TileRegion::default();
+
+        let files = generate_pyramid(
+            &settings(),
+            &region,
+            1,
+            &dir.join("xyz"),
+            PyramidLayout::Xyz,
+            "m",
+        )
+        .unwrap();
+        assert_eq!(files, 5);
+        assert!(dir.join("xyz/1/1/0.png").exists());
+
+        let files = generate_pyramid(
+            &settings(),
+            &region,
+            1,
+            &dir.join("dz"),
+            PyramidLayout::DeepZoom,
+            "m",
+        )
+        .unwrap();
+        assert_eq!(files, 5 + 9);
+        let dzi = fs::read_to_string(dir.join("dz/m.dzi")).unwrap();
+        assert!(dzi.contains("<Size Width=\"512\" Height=\"512\"/>"));
+        assert!(dir.join("dz/m_files/9/1_0.png").exists());
+        let smallest = image::open(dir.join("dz/m_files/0/0_0.png")).unwrap();
+        assert_eq!((smallest.width(), smallest.height()), (1, 1));
+
+        assert!(generate_pyramid(&settings(), &region, 9, &dir, PyramidLayout::Xyz, "m").is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
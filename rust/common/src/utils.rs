@@ -1,11 +1,17 @@
-use std::{fs, time::Instant};
+use std::fs;
+use std::io::Cursor;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Instant;
 
 use chrono::Utc;
-use image::{ImageBuffer, RgbImage};
+use image::{ImageBuffer, ImageOutputFormat, RgbImage};
 use log::{error, info};
 
 use crate::color::Color;
-use crate::complex::ComplexNumber;
+use crate::error::{FractalError, FractalResult};
+use crate::render_engine::RenderParams;
 
 pub fn save_png(pixels: &[Color], width: u32, height: u32) {
     let start = Instant::now();
@@ -16,7 +22,7 @@ pub fn save_png(pixels: &[Color], width: u32, height: u32) {
     for p in pixels.iter() {
         let pixel = image::Rgb([p.r, p.g, p.b]);
         // info!("pixels_vec = {:?}, pixel = {:?}", p, pixel);
-        image.put_pixel(x, y as u32, pixel);
+        image.put_pixel(x, y, pixel);
         x += 1;
         if x % width == 0 {
             y += 1;
@@ -38,68 +44,78 @@ pub fn save_png(pixels: &[Color], width: u32, height: u32) {
     }
 }
 
-pub fn save_png2(
-    pixels: &[Color],
-    width: u32,
-    height: u32,
-    center: &ComplexNumber,
-    tl: &ComplexNumber,
-    br: &ComplexNumber,
-    zoom: f64,
-    max_iterations: u32,
-    name: String,
-) {
-    let start = Instant::now();
-    let mut x = 0;
-    let mut y = 0;
-    let mut image: RgbImage = ImageBuffer::new(width, height);
+pub fn encode_png(pixels: &[Color], width: u32, height: u32) -> FractalResult<Vec<u8>> {
+    let rgb: Vec<u8> = pixels.iter().flat_map(|c| [c.r, c.g, c.b]).collect();
+    let image = RgbImage::from_raw(width, height, rgb).ok_or_else(|| {
+        FractalError::Render(format!("image does not have {}x{} pixels", width, height))
+    })?;
 
-    for p in pixels.iter() {
-        let pixel = image::Rgb([p.r, p.g, p.b]);
-        // info!("pixels_vec = {:?}, pixel = {:?}", p, pixel);
-        image.put_pixel(x, y as u32, pixel);
-        x += 1;
-        if x % width == 0 {
-            y += 1;
-            x = 0;
-        }
-    }
-    let now = Utc::now();
-    let c = format!("center_a_{}_b_{}", center.a, center.b);
+    let mut png = Cursor::new(vec![]);
+    image.write_to(&mut png, ImageOutputFormat::Png)?;
+    Ok(png.into_inner())
+}
 
-    let path = env!("CARGO_MANIFEST_DIR");
-    // println!("CARGO_MANIFEST_DIR   {path}");
-    let path = format!("{}/../../images/{}", path, name);
-    fs::create_dir_all(&path).expect("create dir should work");
+pub fn print_debug(params: &RenderParams) {
+    let vp = &params.viewport;
+    let ratio = params.width as f64 / params.height as f64;
+    info!("width {}, height: {}, zoom  {},  complex_width {},  complex_height {}   ratio {ratio},  center {},  max_iterations {}",
+        params.width, params.height, params.zoom, vp.complex_width, vp.complex_height, vp.center, params.max_iterations);
+    info!("re_min {}, re_max {},  img_min {}   img_max {}  x_delta {}  y_delta  {} ",
+        vp.re_min(), vp.re_max(), vp.img_min(), vp.img_max(), vp.x_delta(), vp.y_delta());
+}
 
-    let filename = format!(
-        "{}/{}_{}___{}x{}_zoom_{}_max_iter_{}.png",
-        path,
-        now.timestamp_millis(),
-        name,
-        width,
-        height,
-        zoom,
-        max_iterations
-    );
-    let res = image.save(filename);
-    let duration = start.elapsed().as_millis();
-    match res {
-        Ok(_) => info!("save ok. took {} ms", duration),
-        Err(e) => error!("error saving file {}. took {} ms", e, duration),
-    }
+// FNV-1a, unlike DefaultHasher the hash stays the same between Rust versions, so it can be used
+// for file names
+pub fn stable_hash(data: &[u8]) -> u64 {
+    data.iter().fold(0xcbf29ce484222325, |hash, b| {
+        (hash ^ *b as u64).wrapping_mul(0x100000001b3)
+    })
 }
 
-pub fn print_debug(
-    width: u32,
-    height: u32,
-    zoom: f64,
-    center: &ComplexNumber,
-    complex_width: f64,
-    complex_height: f64,
-    ratio: f64,
-    max_iterations: u32,
-) {
-    info!("width {width}, height: {height}, zoom  {zoom},  complex_width {complex_width},  complex_height {complex_height}   ratio {ratio},  center {center},  max_iterations {max_iterations}");
+// written to a temporary file first, so a crash never leaves half a file behind. every call has
+// its own temporary file, two threads writing the same path must not rename each other's file
+pub fn write_file_atomic(path: &Path, data: &[u8]) -> FractalResult<()> {
+    static NEXT_TMP: AtomicU64 = AtomicU64::new(0);
+
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).map_err(|e| FractalError::io(&parent.to_string_lossy(), e))?;
+    }
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(format!(
+        ".{}.{}.tmp",
+        process::id(),
+        NEXT_TMP.fetch_add(1, Ordering::Relaxed)
+    ));
+    let tmp = PathBuf::from(tmp);
+    fs::write(&tmp, data).map_err(|e| FractalError::io(&tmp.to_string_lossy(), e))?;
+    fs::rename(&tmp, path).map_err(|e| FractalError::io(&path.to_string_lossy(), e))
 }
 
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::thread;
+
+    use crate::utils::write_file_atomic;
+
+    #[test]
+    fn test_concurrent_atomic_writes() {
+        let dir = std::env::temp_dir().join(format!("write_file_atomic_{}", std::process::id()));
+        let path = dir.join("tile.png");
+        let threads: Vec<_> = (0..8u8)
+            .map(|idx| {
+                let path = path.clone();
+                thread::spawn(move || write_file_atomic(&path, &[idx; 1000]))
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap().unwrap();
+        }
+
+        // one of the writes won, no temporary file is left
+        let data = fs::read(&path).unwrap();
+        assert!(data.iter().all(|b| *b == data[0]));
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
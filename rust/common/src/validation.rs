@@ -0,0 +1,372 @@
+use std::fmt::{Display, Formatter};
+
+use serde_derive::{Deserialize, Serialize};
+
+use crate::gradient::Gradient;
+use crate::models::{CycleRequest, FractalRequest, PaletteSpec};
+
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct RequestLimits {
+    pub max_width: u32,
+    pub max_height: u32,
+    pub max_pixels: u64,
+    pub max_iterations: u32,
+    pub max_tiles: u32,
+    pub max_palette_colors: usize,
+    pub max_frames: u32,
+    // width * height * frames of an animation
+    pub max_animation_pixels: u64,
+}
+
+impl Default for RequestLimits {
+    // big enough for the 8192x6144 renders of the fractal_templates
+    fn default() -> Self {
+        RequestLimits {
+            max_width: 16384,
+            max_height: 16384,
+            max_pixels: 8192 * 6144,
+            max_iterations: 50_000_000,
+            max_tiles: 10_000,
+            max_palette_colors: 4096,
+            max_frames: 1000,
+            // 100 frames in full HD
+            max_animation_pixels: 100 * 1920 * 1080,
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct FieldError {
+    pub field: String,
+    pub message: String,
+}
+
+impl FieldError {
+    pub fn new(field: &str, message: String) -> FieldError {
+        FieldError {
+            field: field.to_string(),
+            message,
+        }
+    }
+}
+
+impl Display for FieldError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.field, self.message)
+    }
+}
+
+impl FractalRequest {
+    pub fn validate(&self, limits: &RequestLimits) -> Result<(), Vec<FieldError>> {
+        let mut errors = vec![];
+
+        validate_size(&mut errors, "width", self.width, limits.max_width);
+        validate_size(&mut errors, "height", self.height, limits.max_height);
+        let pixels = self.width as u64 * self.height as u64;
+        if pixels > limits.max_pixels {
+            errors.push(FieldError::new(
+                "width",
+                format!(
+                    "{}x{} = {} pixels, but at most {} pixels are allowed",
+                    self.width, self.height, pixels, limits.max_pixels
+                ),
+            ));
+        }
+
+        if self.max_iterations == 0 || self.max_iterations > limits.max_iterations {
+            errors.push(FieldError::new(
+                "max_iterations",
+                format!(
+                    "must be between 1 and {}, got {}",
+                    limits.max_iterations, self.max_iterations
+                ),
+            ));
+        }
+
+        validate_tiles(&mut errors, "x_tiles", self.x_tiles, self.width);
+        validate_tiles(&mut errors, "y_tiles", self.y_tiles, self.height);
+        let tiles = self.x_tiles as u64 * self.y_tiles as u64;
+        if tiles > limits.max_tiles as u64 {
+            errors.push(FieldError::new(
+                "x_tiles",
+                format!(
+                    "{}x{} = {} tiles, but at most {} tiles are allowed",
+                    self.x_tiles, self.y_tiles, tiles, limits.max_tiles
+                ),
+            ));
+        }
+
+        validate_positive(&mut errors, "zoom", self.zoom);
+        validate_positive(&mut errors, "complex_width", self.complex_width);
+        validate_finite(&mut errors, "palette_offset", self.palette_offset);
+        validate_finite(&mut errors, "rotation", self.rotation);
+        if !self.center.a.is_finite() || !self.center.b.is_finite() {
+            errors.push(FieldError::new(
+                "center",
+                format!("must be a finite number, got {}", self.center),
+            ));
+        }
+
+        match &self.palette {
+            PaletteSpec::Name(name) if name.is_empty() => {
+                errors.push(FieldError::new(
+                    "palette",
+                    "name must not be empty".to_string(),
+                ));
+            }
+            PaletteSpec::Colors(colors)
+                if colors.is_empty() || colors.len() > limits.max_palette_colors =>
+            {
+                errors.push(FieldError::new(
+                    "palette",
+                    format!(
+                        "must have between 1 and {} colors, got {}",
+                        limits.max_palette_colors,
+                        colors.len()
+                    ),
+                ));
+            }
+            PaletteSpec::Gradient(gradient) => validate_gradient(&mut errors, gradient, limits),
+            _ => {}
+        }
+
+        // the name is used as directory name when saving the image
+        let valid_name = self.name.len() <= 64
+            && self
+                .name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+        if !valid_name {
+            errors.push(FieldError::new(
+                "name",
+                "must be at most 64 characters of a-z, A-Z, 0-9, '_' or '-'".to_string(),
+            ));
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+}
+
+impl CycleRequest {
+    pub fn validate(&self, limits: &RequestLimits) -> Result<(), Vec<FieldError>> {
+        let mut errors = match self.request.validate(limits) {
+            Ok(()) => vec![],
+            Err(errors) => errors,
+        };
+        if self.frames == 0 || self.frames > limits.max_frames {
+            errors.push(FieldError::new(
+                "frames",
+                format!(
+                    "must be between 1 and {}, got {}",
+                    limits.max_frames, self.frames
+                ),
+            ));
+        }
+        // the route renders the request before it recolors it, that counts as one more frame
+        validate_animation_pixels(
+            &mut errors,
+            &self.request,
+            self.frames.saturating_add(1),
+            limits,
+        );
+        validate_finite(&mut errors, "step", self.step);
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+}
+
+fn validate_gradient(errors: &mut Vec<FieldError>, gradient: &Gradient, limits: &RequestLimits) {
+    let stops = &gradient.stops;
+    if stops.is_empty() || stops.len() > limits.max_palette_colors {
+        errors.push(FieldError::new(
+            "palette",
+            format!(
+                "gradient must have between 1 and {} stops, got {}",
+                limits.max_palette_colors,
+                stops.len()
+            ),
+        ));
+    }
+    let in_range = stops
+        .iter()
+        .all(|s| s.position.is_finite() && (0.0..=1.0).contains(&s.position));
+    let sorted = stops.windows(2).all(|w| w[0].position <= w[1].position);
+    if !in_range || !sorted {
+        errors.push(FieldError::new(
+            "palette",
+            "gradient stop positions must be sorted and between 0 and 1".to_string(),
+        ));
+    }
+    if gradient.steps == 0 {
+        errors.push(FieldError::new(
+            "palette",
+            "gradient steps must be at least 1".to_string(),
+        ));
+    }
+}
+
+// every frame of an animation has the size of the request
+pub(crate) fn validate_animation_pixels(
+    errors: &mut Vec<FieldError>,
+    req: &FractalRequest,
+    frames: u32,
+    limits: &RequestLimits,
+) {
+    let pixels = req.width as u64 * req.height as u64 * frames as u64;
+    if pixels > limits.max_animation_pixels {
+        errors.push(FieldError::new(
+            "frames",
+            format!(
+                "{} frames of {}x{} = {} pixels, but at most {} pixels are allowed",
+                frames, req.width, req.height, pixels, limits.max_animation_pixels
+            ),
+        ));
+    }
+}
+
+fn validate_size(errors: &mut Vec<FieldError>, field: &str, value: u32, max: u32) {
+    if value == 0 || value > max {
+        errors.push(FieldError::new(
+            field,
+            format!("must be between 1 and {}, got {}", max, value),
+        ));
+    }
+}
+
+fn validate_tiles(errors: &mut Vec<FieldError>, field: &str, tiles: u32, size: u32) {
+    if tiles == 0 || tiles > size.max(1) {
+        errors.push(FieldError::new(
+            field,
+            format!("must be between 1 and {}, got {}", size.max(1), tiles),
+        ));
+    }
+}
+
+pub(crate) fn validate_finite(errors: &mut Vec<FieldError>, field: &str, value: f64) {
+    if !value.is_finite() {
+        errors.push(FieldError::new(
+            field,
+            format!("must be a finite number, got {}", value),
+        ));
+    }
+}
+
+pub(crate) fn validate_positive(errors: &mut Vec<FieldError>, field: &str, value: f64) {
+    if !value.is_finite() || value <= 0.0 {
+        errors.push(FieldError::new(
+            field,
+            format!("must be a positive number, got {}", value),
+        ));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::color::Color;
+    use crate::fractal_templates::{basic, flower};
+    use crate::gradient::{Gradient, Interpolation};
+    use crate::models::{CycleRequest, PaletteSpec};
+    use crate::validation::RequestLimits;
+
+    fn fields(errors: Vec<crate::validation::FieldError>) -> Vec<String> {
+        errors.into_iter().map(|e| e.field).collect()
+    }
+
+    #[test]
+    fn test_templates_are_valid() {
+        let limits = RequestLimits::default();
+        assert_eq!(basic(true).0.validate(&limits), Ok(()));
+        assert_eq!(basic(false).0.validate(&limits), Ok(()));
+        assert_eq!(flower(false).0.validate(&limits), Ok(()));
+    }
+
+    #[test]
+    fn test_invalid_values() {
+        let (mut req, _, _) = basic(true);
+        req.width = 0;
+        req.x_tiles = 0;
+        req.zoom = -1.0;
+        req.max_iterations = 0;
+        req.name = "../etc".to_string();
+
+        let errors = req.validate(&RequestLimits::default()).unwrap_err();
+        assert_eq!(
+            fields(errors),
+            vec!["width", "max_iterations", "x_tiles", "zoom", "name"]
+        );
+    }
+
+    #[test]
+    fn test_palette() {
+        let (mut req, _, _) = basic(true);
+        req.palette = PaletteSpec::Colors(vec![]);
+        let errors = req.validate(&RequestLimits::default()).unwrap_err();
+        assert_eq!(fields(errors), vec!["palette"]);
+
+        req.palette = PaletteSpec::Colors(vec![Color::default(); 2]);
+        assert_eq!(req.validate(&RequestLimits::default()), Ok(()));
+
+        let mut gradient = Gradient::new(&vec![Color::default(); 3], Interpolation::Hsl, 64);
+        req.palette = PaletteSpec::Gradient(gradient.clone());
+        assert_eq!(req.validate(&RequestLimits::default()), Ok(()));
+
+        gradient.stops[0].position = 0.9;
+        gradient.steps = 0;
+        req.palette = PaletteSpec::Gradient(gradient);
+        let errors = req.validate(&RequestLimits::default()).unwrap_err();
+        assert_eq!(fields(errors), vec!["palette", "palette"]);
+    }
+
+    #[test]
+    fn test_limits() {
+        let (mut req, _, _) = basic(true);
+        req.width = 100_000;
+        req.height = 100_000;
+
+        let errors = req.validate(&RequestLimits::default()).unwrap_err();
+        assert_eq!(fields(errors), vec!["width", "height", "width"]);
+
+        let limits = RequestLimits {
+            max_pixels: 100,
+            ..RequestLimits::default()
+        };
+        let (mut req, _, _) = basic(true);
+        req.width = 10;
+        req.height = 10;
+        req.x_tiles = 2;
+        req.y_tiles = 2;
+        assert_eq!(req.validate(&limits), Ok(()));
+        req.width = 11;
+        assert!(req.validate(&limits).is_err());
+    }
+
+    #[test]
+    fn test_cycle_pixels() {
+        let (mut request, _, _) = basic(true);
+        request.width = 8192;
+        request.height = 6144;
+        let mut req = CycleRequest {
+            request,
+            frames: 1000,
+            step: 1.0,
+            delay_ms: 40,
+            format: Default::default(),
+        };
+        let errors = req.validate(&RequestLimits::default()).unwrap_err();
+        assert_eq!(fields(errors), vec!["frames"]);
+
+        // 4 frames would fit, but not with the render in front of them
+        req.frames = 4;
+        assert!(req.validate(&RequestLimits::default()).is_err());
+        req.frames = 3;
+        assert_eq!(req.validate(&RequestLimits::default()), Ok(()));
+    }
+}
@@ -0,0 +1,343 @@
+use serde_derive::{Deserialize, Serialize};
+
+use crate::complex::ComplexNumber;
+use crate::models::FractalRequest;
+
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, Default, PartialEq)]
+pub enum AspectPolicy {
+    // keep the complex width, derive the height from the image ratio
+    #[default]
+    FitWidth,
+    // keep the complex height, derive the width from the image ratio
+    FitHeight,
+    // grow one side so the whole complex region is visible with square pixels
+    Contain,
+    // use the region as is, pixels are not square if the ratios differ
+    Stretch,
+}
+
+// maps pixels to points in the complex plane and back.
+// pixel (0, 0) is the top left corner of the image, which is (re_min, img_max) without rotation.
+// re_min .. img_max describe the region before it is rotated around the center
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct Viewport {
+    pub center: ComplexNumber,
+    pub complex_width: f64,
+    pub complex_height: f64,
+    pub width: u32,
+    pub height: u32,
+    // degrees counterclockwise, the image shows the plane turned clockwise by this angle
+    #[serde(default)]
+    pub rotation: f64,
+}
+
+impl Viewport {
+    pub fn new(center: ComplexNumber, complex_width: f64, width: u32, height: u32) -> Viewport {
+        Viewport::with_aspect(
+            center,
+            complex_width,
+            complex_width,
+            width,
+            height,
+            AspectPolicy::FitWidth,
+        )
+    }
+
+    pub fn with_aspect(
+        center: ComplexNumber,
+        complex_width: f64,
+        complex_height: f64,
+        width: u32,
+        height: u32,
+        policy: AspectPolicy,
+    ) -> Viewport {
+        let ratio = width as f64 / height as f64;
+        let (complex_width, complex_height) = match policy {
+            AspectPolicy::FitWidth => (complex_width, complex_width / ratio),
+            AspectPolicy::FitHeight => (complex_height * ratio, complex_height),
+            AspectPolicy::Contain => {
+                if complex_width / complex_height > ratio {
+                    (complex_width, complex_width / ratio)
+                } else {
+                    (complex_height * ratio, complex_height)
+                }
+            }
+            AspectPolicy::Stretch => (complex_width, complex_height),
+        };
+
+        Viewport {
+            center,
+            complex_width,
+            complex_height,
+            width,
+            height,
+            rotation: 0.0,
+        }
+    }
+
+    pub fn from_request(req: &FractalRequest) -> Viewport {
+        let size = req.complex_width / req.zoom;
+        Viewport::with_aspect(
+            req.center.clone(),
+            size,
+            size,
+            req.width,
+            req.height,
+            req.aspect,
+        )
+        .rotated(req.rotation)
+    }
+
+    // turned by degrees counterclockwise around the center
+    pub fn rotated(&self, degrees: f64) -> Viewport {
+        Viewport {
+            rotation: self.rotation + degrees,
+            ..self.clone()
+        }
+    }
+
+    // the same request, but centered and zoomed like this viewport. the zoom is taken from the
+    // side the aspect policy of req keeps
+    pub fn to_request(&self, req: &FractalRequest) -> FractalRequest {
+        let size = match req.aspect {
+            AspectPolicy::FitWidth | AspectPolicy::Stretch => self.complex_width,
+            AspectPolicy::FitHeight => self.complex_height,
+            AspectPolicy::Contain => self.complex_width.min(self.complex_height),
+        };
+        FractalRequest {
+            center: self.center.clone(),
+            zoom: req.complex_width / size,
+            rotation: self.rotation,
+            width: self.width,
+            height: self.height,
+            ..req.clone()
+        }
+    }
+
+    pub fn re_min(&self) -> f64 {
+        self.center.a - self.complex_width / 2.0
+    }
+
+    pub fn re_max(&self) -> f64 {
+        self.center.a + self.complex_width / 2.0
+    }
+
+    pub fn img_min(&self) -> f64 {
+        self.center.b - self.complex_height / 2.0
+    }
+
+    pub fn img_max(&self) -> f64 {
+        self.center.b + self.complex_height / 2.0
+    }
+
+    pub fn x_delta(&self) -> f64 {
+        self.complex_width / self.width as f64
+    }
+
+    pub fn y_delta(&self) -> f64 {
+        self.complex_height / self.height as f64
+    }
+
+    // the point at the top left pixel
+    pub fn tl(&self) -> ComplexNumber {
+        self.pixel_to_complex(0.0, 0.0)
+    }
+
+    // the point at the bottom right corner
+    pub fn br(&self) -> ComplexNumber {
+        self.pixel_to_complex(self.width as f64, self.height as f64)
+    }
+
+    pub fn pixel_to_complex(&self, x: f64, y: f64) -> ComplexNumber {
+        // without rotation exactly the calculation of the old engines, so the images don't change
+        if self.rotation == 0.0 {
+            return ComplexNumber {
+                a: self.re_min() + x * self.x_delta(),
+                b: self.img_max() - y * self.y_delta(),
+            };
+        }
+        let dx = (x - self.width as f64 / 2.0) * self.x_delta();
+        let dy = (self.height as f64 / 2.0 - y) * self.y_delta();
+        let (sin, cos) = self.rotation.to_radians().sin_cos();
+        ComplexNumber {
+            a: self.center.a + dx * cos - dy * sin,
+            b: self.center.b + dx * sin + dy * cos,
+        }
+    }
+
+    pub fn complex_to_pixel(&self, c: &ComplexNumber) -> (f64, f64) {
+        if self.rotation == 0.0 {
+            let x = (c.a - self.re_min()) / self.x_delta();
+            let y = (self.img_max() - c.b) / self.y_delta();
+            return (x, y);
+        }
+        let (da, db) = (c.a - self.center.a, c.b - self.center.b);
+        let (sin, cos) = self.rotation.to_radians().sin_cos();
+        let dx = da * cos + db * sin;
+        let dy = -da * sin + db * cos;
+        (
+            dx / self.x_delta() + self.width as f64 / 2.0,
+            self.height as f64 / 2.0 - dy / self.y_delta(),
+        )
+    }
+
+    // zoom in (factor > 1) or out (factor < 1) while `point` stays at the same pixel
+    pub fn zoom_at(&self, point: &ComplexNumber, factor: f64) -> Viewport {
+        let center = ComplexNumber {
+            a: point.a + (self.center.a - point.a) / factor,
+            b: point.b + (self.center.b - point.b) / factor,
+        };
+
+        Viewport {
+            center,
+            complex_width: self.complex_width / factor,
+            complex_height: self.complex_height / factor,
+            ..self.clone()
+        }
+    }
+
+    // move the visible region by dx/dy pixels
+    pub fn pan(&self, dx: f64, dy: f64) -> Viewport {
+        let center = self.pixel_to_complex(
+            self.width as f64 / 2.0 + dx,
+            self.height as f64 / 2.0 + dy,
+        );
+
+        Viewport {
+            center,
+            ..self.clone()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::complex::ComplexNumber;
+    use crate::fractal_templates::basic;
+    use crate::viewport::{AspectPolicy, Viewport};
+
+    fn assert_close(a: f64, b: f64) {
+        assert!((a - b).abs() < 1e-9, "{} != {}", a, b);
+    }
+
+    fn viewport() -> Viewport {
+        Viewport::new(ComplexNumber { a: -0.5, b: 0.25 }, 4.0, 400, 200)
+    }
+
+    #[test]
+    fn test_row_zero_is_img_max() {
+        let vp = viewport();
+        let tl = vp.pixel_to_complex(0.0, 0.0);
+        assert_close(tl.a, -2.5);
+        assert_close(tl.b, 1.25);
+
+        let br = vp.pixel_to_complex(400.0, 200.0);
+        assert_close(br.a, 1.5);
+        assert_close(br.b, -0.75);
+
+        assert_eq!(vp.tl(), tl);
+        assert_eq!(vp.br(), br);
+    }
+
+    #[test]
+    fn test_complex_to_pixel_roundtrip() {
+        let vp = viewport();
+        let c = vp.pixel_to_complex(123.0, 45.0);
+        let (x, y) = vp.complex_to_pixel(&c);
+        assert_close(x, 123.0);
+        assert_close(y, 45.0);
+    }
+
+    #[test]
+    fn test_zoom_at_keeps_point_fixed() {
+        let vp = viewport();
+        let point = vp.pixel_to_complex(300.0, 50.0);
+        let zoomed = vp.zoom_at(&point, 4.0);
+
+        assert_close(zoomed.complex_width, 1.0);
+        let (x, y) = zoomed.complex_to_pixel(&point);
+        assert_close(x, 300.0);
+        assert_close(y, 50.0);
+    }
+
+    #[test]
+    fn test_pan() {
+        let vp = viewport();
+        let panned = vp.pan(100.0, 50.0);
+        assert_close(panned.center.a, 0.5);
+        assert_close(panned.center.b, -0.25);
+    }
+
+    #[test]
+    fn test_rotation() {
+        let vp = viewport().rotated(90.0);
+        let center = vp.pixel_to_complex(200.0, 100.0);
+        assert_close(center.a, -0.5);
+        assert_close(center.b, 0.25);
+
+        // right of the center in the image is up in the plane
+        let right = vp.pixel_to_complex(300.0, 100.0);
+        assert_close(right.a, -0.5);
+        assert_close(right.b, 1.25);
+        let top = vp.pixel_to_complex(200.0, 0.0);
+        assert_close(top.a, -1.5);
+        assert_close(top.b, 0.25);
+
+        let vp = viewport().rotated(33.0);
+        let c = vp.pixel_to_complex(123.0, 45.0);
+        let (x, y) = vp.complex_to_pixel(&c);
+        assert_close(x, 123.0);
+        assert_close(y, 45.0);
+
+        // the rotation is kept when zooming and panning
+        let point = vp.pixel_to_complex(300.0, 50.0);
+        let zoomed = vp.zoom_at(&point, 4.0);
+        let (x, y) = zoomed.complex_to_pixel(&point);
+        assert_close(x, 300.0);
+        assert_close(y, 50.0);
+        let panned = vp.pan(100.0, 50.0);
+        assert_eq!(panned.center, vp.pixel_to_complex(300.0, 150.0));
+    }
+
+    #[test]
+    fn test_aspect_policies() {
+        let center = ComplexNumber::default();
+        let vp = Viewport::with_aspect(center.clone(), 4.0, 4.0, 400, 200, AspectPolicy::FitWidth);
+        assert_close(vp.complex_height, 2.0);
+
+        let vp = Viewport::with_aspect(center.clone(), 4.0, 4.0, 400, 200, AspectPolicy::FitHeight);
+        assert_close(vp.complex_width, 8.0);
+
+        let vp = Viewport::with_aspect(center.clone(), 4.0, 1.0, 400, 200, AspectPolicy::Contain);
+        assert_close(vp.complex_width, 4.0);
+        assert_close(vp.complex_height, 2.0);
+
+        let vp = Viewport::with_aspect(center.clone(), 1.0, 4.0, 400, 200, AspectPolicy::Contain);
+        assert_close(vp.complex_width, 8.0);
+        assert_close(vp.complex_height, 4.0);
+
+        let vp = Viewport::with_aspect(center, 4.0, 4.0, 400, 200, AspectPolicy::Stretch);
+        assert_close(vp.complex_height, 4.0);
+    }
+
+    #[test]
+    fn test_request_aspect() {
+        let (mut req, _, _) = basic(true);
+        req.width = 400;
+        req.height = 200;
+        req.complex_width = 4.0;
+        req.zoom = 2.0;
+        for (aspect, width, height) in [
+            (AspectPolicy::FitWidth, 2.0, 1.0),
+            (AspectPolicy::FitHeight, 4.0, 2.0),
+            (AspectPolicy::Contain, 4.0, 2.0),
+            (AspectPolicy::Stretch, 2.0, 2.0),
+        ] {
+            req.aspect = aspect;
+            let vp = Viewport::from_request(&req);
+            assert_close(vp.complex_width, width);
+            assert_close(vp.complex_height, height);
+            assert_close(vp.zoom_at(&vp.center, 2.0).to_request(&req).zoom, 4.0);
+        }
+    }
+}
@@ -1,3 +1,5 @@
+use std::cell::RefCell;
+
 use reqwasm::http::Request;
 use sycamore::futures::spawn_local_scoped;
 use sycamore::prelude::*;
@@ -5,13 +7,18 @@ use wasm_bindgen::prelude::*;
 use web_sys::{
     CanvasRenderingContext2d, HtmlCanvasElement, HtmlParagraphElement, ImageData, MouseEvent,
 };
-use web_sys::{ErrorEvent, MessageEvent, WebSocket};
+use web_sys::{BinaryType, ErrorEvent, MessageEvent, WebSocket};
 
+use common::color::Color;
 use common::fractal_templates::basic;
-use common::image_tile::TileData;
 use common::models::{
-    FractalRequest, FractalResponse, WebSocketCommand, WebSocketRequest, WebSocketResponse,
+    ErrorResponse, FractalRequest, FractalResponse, IterationResponse, PaletteInfo, PaletteSpec,
+    RecolorRequest, RenderRequest, WebSocketCommand, WebSocketEvent, WebSocketRequest,
 };
+use common::palette::resolve_palette;
+use common::tile_frame::{TileEncoding, TileFrame};
+use common::tile_pyramid::{TileCoord, MAX_TILE_ZOOM, TILE_SIZE};
+use common::viewport::{AspectPolicy, Viewport};
 
 #[macro_export]
 macro_rules! console_log {
@@ -37,80 +44,57 @@ pub fn draw_to_canvas(
 
     console_log!("duration {}", fractal_response.duration_calculation);
 
-    let image_data = context
-        .get_image_data(0.0, 0.0, width.into(), height.into())
-        .unwrap();
-
     console_log!(
         "writing pixels into image_data  width {}, height {}",
         width,
         height
     );
+    draw_pixels(&fractal_response.fractal.pixels, width, height, context);
 
-    let mut data = image_data.data();
-    for x in 0..width {
-        for y in 0..height {
-            let idx_pixel = y * width + x;
-            let idx = y * width * 4 + x * 4;
-            data[idx as usize] = fractal_response.fractal.pixels[idx_pixel as usize].r;
-            data[idx as usize + 1] = fractal_response.fractal.pixels[idx_pixel as usize].g;
-            data[idx as usize + 2] = fractal_response.fractal.pixels[idx_pixel as usize].b;
-            data[idx as usize + 3] = 255;
-        }
-    }
+    console_log!("updated data");
+}
+
+// the canvas must already have the size width x height
+pub fn draw_pixels(pixels: &[Color], width: u32, height: u32, context: &CanvasRenderingContext2d) {
+    let data: Vec<u8> = pixels.iter().flat_map(|c| [c.r, c.g, c.b, 255]).collect();
+    draw_rgba(&data, width, height, context);
+}
+
+pub fn draw_rgba(data: &[u8], width: u32, height: u32, context: &CanvasRenderingContext2d) {
     let data =
-        ImageData::new_with_u8_clamped_array_and_sh(wasm_bindgen::Clamped(&data), width, height)
+        ImageData::new_with_u8_clamped_array_and_sh(wasm_bindgen::Clamped(data), width, height)
             .unwrap();
     let res = context.put_image_data(&data, 0.0, 0.0);
-    match res {
-        Ok(_) => console_log!("writing data successful"),
-        Err(e) => console_log!("error writing image data {:?}", e),
+    if let Err(e) = res {
+        console_log!("error writing image data {:?}", e);
     }
-
-    console_log!("updated data");
 }
 
-pub fn draw_to_canvas_tiles(
-    tile: &TileData,
-    context: &CanvasRenderingContext2d,
-    width: u32,
-    height: u32,
-) {
-    console_log!(
-        "draw tile to canvas {:?}.    width {}, height {}",
-        tile,
-        width,
-        height
-    );
-
-    let image_data = context
-        .get_image_data(0.0, 0.0, width.into(), height.into())
-        .unwrap();
-
+pub fn draw_to_canvas_tiles(tile: &TileFrame, context: &CanvasRenderingContext2d) {
     console_log!(
-        "writing pixels into image_data from tile width {}, height {}",
-        width,
-        height
+        "draw tile {} to canvas at {}/{}, width {}, height {}",
+        tile.idx,
+        tile.x,
+        tile.y,
+        tile.width,
+        tile.height
     );
-    let mut data = image_data.data();
-    tile.points.iter().for_each(|p| {
-        let idx = p.y * width * 4 + p.x * 4;
-        data[idx as usize] = p.c.r;
-        data[idx as usize + 1] = p.c.g;
-        data[idx as usize + 2] = p.c.b;
-        data[idx as usize + 3] = 255;
-    });
 
-    let data =
-        ImageData::new_with_u8_clamped_array_and_sh(wasm_bindgen::Clamped(&data), width, height)
-            .unwrap();
-    let res = context.put_image_data(&data, 0.0, 0.0);
-    match res {
+    // only the tile is written, the rest of the canvas keeps the tiles drawn before
+    let Some(rgba) = tile.rgba() else {
+        console_log!("can't draw a tile with {:?}", tile.encoding);
+        return;
+    };
+    let data = ImageData::new_with_u8_clamped_array_and_sh(
+        wasm_bindgen::Clamped(&rgba),
+        tile.width,
+        tile.height,
+    )
+    .unwrap();
+    match context.put_image_data(&data, tile.x as f64, tile.y as f64) {
         Ok(_r) => console_log!("writing data form tile successfull "),
         Err(e) => console_log!("error writing tile image data {:?}", e),
     }
-
-    console_log!("updated canvas from tile");
 }
 
 pub fn clear_canvas(canvas: &HtmlCanvasElement) {
@@ -143,6 +127,15 @@ pub fn get_canvas_context() -> (CanvasRenderingContext2d, HtmlCanvasElement) {
 }
 
 pub fn set_info_text(fractal_response: FractalResponse, id: &str) {
+    show_duration(
+        fractal_response.fractal.width,
+        fractal_response.fractal.height,
+        fractal_response.duration_ms,
+        id,
+    );
+}
+
+pub fn show_duration(width: u32, height: u32, duration_ms: u128, id: &str) {
     console_log!("trying to find element id {id}");
     let document = web_sys::window().unwrap().document().unwrap();
     let p = document.get_element_by_id(id).unwrap();
@@ -151,109 +144,97 @@ pub fn set_info_text(fractal_response: FractalResponse, id: &str) {
         .map_err(|_| ())
         .unwrap();
 
-    let pixels_per_msec = (fractal_response.fractal.height * fractal_response.fractal.width) as f64
-        / fractal_response.duration_ms as f64;
+    let pixels_per_msec = (height * width) as f64 / duration_ms as f64;
     let txt = format!(
         "Duration: {} ms, Speed: {:.4} Pixels / ms",
-        fractal_response.duration_ms, pixels_per_msec
+        duration_ms, pixels_per_msec
     );
     p.set_inner_text(&txt);
 }
 
+// the backend answers with an ErrorResponse if the request could not be rendered
+fn parse_fractal_response(response: &str) -> Option<FractalResponse> {
+    if let Ok(fractal_response) = serde_json::from_str::<FractalResponse>(response) {
+        return Some(fractal_response);
+    }
+    match serde_json::from_str::<ErrorResponse>(response) {
+        Ok(e) => {
+            console_log!("server returned an error {}: {}", e.status, e.message);
+            for field in e.fields {
+                console_log!("    invalid field {}", field);
+            }
+        }
+        Err(e) => console_log!("response is neither a fractal nor an error {:?}", e),
+    }
+    None
+}
+
 const SERVER: &str = "http://localhost:3000";
 const API_URL_SINGLE_THREADED: &str = "/api/singlethreaded";
 const API_URL_MULTI_THREADED: &str = "/api/multithreaded";
-const API_URL_RAYON: &str = "/api/rayon";
+const API_URL_RENDER: &str = "/api/render";
+
+const API_URL_ITERATIONS: &str = "/api/iterations";
+const API_URL_PALETTES: &str = "/api/palettes";
+const TILES_URL: &str = "/tiles";
 
 const JAVA_SERVER: &str = "http://localhost:4000";
 
 async fn post_single_threaded() -> Result<(), reqwasm::Error> {
     console_log!("post_single_threaded!");
-
-    let (context, canvas) = get_canvas_context();
-    clear_canvas(&canvas);
-
-    let fractal_request = dummy_request();
-    let fractal_request = serde_json::json!(fractal_request).to_string();
-    let url = format!("{}{}", SERVER, API_URL_SINGLE_THREADED);
-
-    let re = Request::post(&url)
-        .body(fractal_request)
-        .header("content-type", "application/json")
-        .send()
-        .await?
-        .text()
-        .await;
-
-    let response = re.expect("should be a valid Response/Body !!!");
-    let fractal_response: serde_json::error::Result<FractalResponse> =
-        serde_json::from_str(&response);
-
-    let fractal_response = fractal_response.unwrap();
-    draw_to_canvas(&fractal_response, &context, &canvas);
-    console_log!("updated data");
-
-    set_info_text(fractal_response, "rust-single-threaded");
-    Ok(())
+    post_render("singlethreaded", "rust-single-threaded").await
 }
 
 async fn post_multi_threaded() -> Result<(), reqwasm::Error> {
     console_log!("post_multi_threaded!");
-    let (context, canvas) = get_canvas_context();
-    clear_canvas(&canvas);
-
-    let fractal_request = dummy_request();
-    let fractal_request = serde_json::json!(fractal_request).to_string();
-    console_log!("post_multi_threaded!    {}", fractal_request);
-
-    let url = format!("{}{}", SERVER, API_URL_MULTI_THREADED);
-
-    let re = Request::post(&url)
-        .body(fractal_request)
-        .header("content-type", "application/json")
-        .send()
-        .await?
-        .text()
-        .await;
-
-    let response = re.expect("should be a valid Response/Body !!!");
-    let fractal_response: serde_json::error::Result<FractalResponse> =
-        serde_json::from_str(&response);
-
-    let fractal_response = fractal_response.unwrap();
-    draw_to_canvas(&fractal_response, &context, &canvas);
-    console_log!("updated data");
-
-    set_info_text(fractal_response, "rust-multi-threaded");
-    Ok(())
+    post_render("multithreaded", "rust-multi-threaded").await
 }
 
 async fn post_rayon() -> Result<(), reqwasm::Error> {
     console_log!("post_rayon!");
+    post_render("rayon", "rust-rayon").await
+}
 
+// the raw RGBA bytes instead of the json, a fraction of the size and nothing to parse
+async fn post_render(engine: &str, id: &str) -> Result<(), reqwasm::Error> {
     let (context, canvas) = get_canvas_context();
+    cancel_tiles();
     clear_canvas(&canvas);
 
-    let fractal_request = dummy_request();
-    let fractal_request = serde_json::json!(fractal_request).to_string();
-    let url = format!("{}{}", SERVER, API_URL_RAYON);
+    let render_request = RenderRequest {
+        engine: engine.to_string(),
+        request: current_request(),
+    };
+    let render_request = serde_json::json!(render_request).to_string();
+    let url = format!("{}{}", SERVER, API_URL_RENDER);
 
-    let re = Request::post(&url)
-        .body(fractal_request)
+    let response = Request::post(&url)
+        .body(render_request)
         .header("content-type", "application/json")
+        .header("accept", "application/octet-stream")
         .send()
-        .await?
-        .text()
-        .await;
+        .await?;
+    if !response.ok() {
+        parse_fractal_response(&response.text().await?);
+        return Ok(());
+    }
 
-    let response = re.expect("should be a valid Response/Body !!!");
-    let fractal_response: serde_json::error::Result<FractalResponse> =
-        serde_json::from_str(&response);
+    let header = |name: &str| response.headers().get(name).and_then(|v| v.parse::<u128>().ok());
+    let (Some(width), Some(height), Some(duration_ms)) = (
+        header("x-fractal-width"),
+        header("x-fractal-height"),
+        header("x-fractal-duration-ms"),
+    ) else {
+        console_log!("the response of {} has no size or duration", url);
+        return Ok(());
+    };
+    let (width, height) = (width as u32, height as u32);
+    let data = response.binary().await?;
 
-    let fractal_response = fractal_response.unwrap();
-    draw_to_canvas(&fractal_response, &context, &canvas);
+    set_canvas_width_height(width, height, &canvas);
+    draw_rgba(&data, width, height, &context);
     console_log!("updated data");
-    set_info_text(fractal_response, "rust-rayon");
+    show_duration(width, height, duration_ms, id);
     Ok(())
 }
 
@@ -261,9 +242,10 @@ async fn post_single_java() -> Result<(), reqwasm::Error> {
     console_log!("post_single_java!");
 
     let (context, canvas) = get_canvas_context();
+    cancel_tiles();
     clear_canvas(&canvas);
 
-    let fractal_request = dummy_request();
+    let fractal_request = current_request();
     let fractal_request = serde_json::json!(fractal_request).to_string();
     let url = format!("{}{}", JAVA_SERVER, API_URL_SINGLE_THREADED);
 
@@ -276,10 +258,9 @@ async fn post_single_java() -> Result<(), reqwasm::Error> {
         .await;
 
     let response = re.expect("should be a valid Response/Body !!!");
-    let fractal_response: serde_json::error::Result<FractalResponse> =
-        serde_json::from_str(&response);
-
-    let fractal_response = fractal_response.unwrap();
+    let Some(fractal_response) = parse_fractal_response(&response) else {
+        return Ok(());
+    };
     draw_to_canvas(&fractal_response, &context, &canvas);
     console_log!("image data written to canvas");
     set_info_text(fractal_response, "java-single-threaded");
@@ -290,9 +271,10 @@ async fn post_multi_java() -> Result<(), reqwasm::Error> {
     console_log!("post_multi_java!");
 
     let (context, canvas) = get_canvas_context();
+    cancel_tiles();
     clear_canvas(&canvas);
 
-    let fractal_request = dummy_request();
+    let fractal_request = current_request();
     let fractal_request = serde_json::json!(fractal_request).to_string();
     let url = format!("{}{}", JAVA_SERVER, API_URL_MULTI_THREADED);
 
@@ -305,10 +287,9 @@ async fn post_multi_java() -> Result<(), reqwasm::Error> {
         .await;
 
     let response = re.expect("should be a valid Response/Body !!!");
-    let fractal_response: serde_json::error::Result<FractalResponse> =
-        serde_json::from_str(&response);
-
-    let fractal_response = fractal_response.unwrap();
+    let Some(fractal_response) = parse_fractal_response(&response) else {
+        return Ok(());
+    };
     draw_to_canvas(&fractal_response, &context, &canvas);
     console_log!("image data written to canvas");
     set_info_text(fractal_response, "java-multi-threaded");
@@ -319,9 +300,10 @@ async fn post_multi_java_virtual() -> Result<(), reqwasm::Error> {
     console_log!("post_multi_java_virtual!");
 
     let (context, canvas) = get_canvas_context();
+    cancel_tiles();
     clear_canvas(&canvas);
 
-    let fractal_request = dummy_request();
+    let fractal_request = current_request();
     let fractal_request = serde_json::json!(fractal_request).to_string();
     let url = format!("{}{}/{}", JAVA_SERVER, API_URL_MULTI_THREADED, "virtual");
 
@@ -334,66 +316,217 @@ async fn post_multi_java_virtual() -> Result<(), reqwasm::Error> {
         .await;
 
     let response = re.expect("should be a valid Response/Body !!!");
-    let fractal_response: serde_json::error::Result<FractalResponse> =
-        serde_json::from_str(&response);
-
-    let fractal_response = fractal_response.unwrap();
+    let Some(fractal_response) = parse_fractal_response(&response) else {
+        return Ok(());
+    };
     draw_to_canvas(&fractal_response, &context, &canvas);
     console_log!("image data written to canvas");
     set_info_text(fractal_response, "java-multi-threaded-virtual");
     Ok(())
 }
 
+// the tiles socket of the page. it is opened once and every render is a command on it, the server
+// cancels the render before, so tiles of an old render can't end up on the canvas
+struct TileSocket {
+    socket: WebSocket,
+    // the request of the last RENDERFRACTAL or RERENDER, the server changes its viewport
+    rendered: Option<FractalRequest>,
+    // the request of the last command, RECOLOR only changes its palette
+    shown: Option<FractalRequest>,
+    // the last render is finished, RECOLOR needs its iterations
+    finished: bool,
+    // commands whose RENDER_STARTED hasn't arrived yet
+    pending: u32,
+    // tiles are only drawn between the RENDER_STARTED of the last command and its RENDER_DONE
+    drawing: bool,
+    // the last command was CANCEL, a render that starts before it arrives isn't drawn
+    cancelled: bool,
+}
+
+thread_local! {
+    static TILE_SOCKET: RefCell<Option<TileSocket>> = const { RefCell::new(None) };
+}
+
 async fn post_crossbeam_tiled() {
     console_log!("post_crossbeam_tiled!");
+    stop_palette_cycling();
+
+    let is_open = TILE_SOCKET.with(|s| s.borrow().is_some());
+    if !is_open {
+        open_tile_socket();
+        // the render is sent once the socket is open
+        return;
+    }
+    send_tile_command(&current_request());
+}
+
+// the command with the least work for the server
+fn tile_command(tiles: &TileSocket, req: &FractalRequest) -> WebSocketCommand {
+    let recolor = tiles.finished
+        && tiles.shown.as_ref().is_some_and(|shown| {
+            FractalRequest {
+                palette: req.palette.clone(),
+                palette_offset: req.palette_offset,
+                ..shown.clone()
+            } == *req
+        });
+    if recolor {
+        return WebSocketCommand::RECOLOR(RecolorRequest {
+            palette: Some(req.palette.clone()),
+            palette_offset: Some(req.palette_offset),
+        });
+    }
+    let rerender = tiles.rendered.as_ref().is_some_and(|rendered| {
+        FractalRequest {
+            center: req.center.clone(),
+            zoom: req.zoom,
+            rotation: req.rotation,
+            width: req.width,
+            height: req.height,
+            ..rendered.clone()
+        } == *req
+    });
+    if rerender {
+        return WebSocketCommand::RERENDER(Viewport::from_request(req));
+    }
+    WebSocketCommand::RENDERFRACTAL(req.clone())
+}
+
+fn send_tile_command(req: &FractalRequest) {
+    TILE_SOCKET.with(|s| {
+        let mut s = s.borrow_mut();
+        let Some(tiles) = s.as_mut() else {
+            return;
+        };
+        let command = tile_command(tiles, req);
+        if !matches!(command, WebSocketCommand::RECOLOR(_)) {
+            tiles.rendered = Some(req.clone());
+        }
+        tiles.shown = Some(req.clone());
+        tiles.finished = false;
+        tiles.pending += 1;
+        tiles.drawing = false;
+        tiles.cancelled = false;
+        send_on_tile_socket(&tiles.socket, command);
+    });
+}
 
+// other renders draw on the canvas, the tiles would overwrite them
+fn cancel_tiles() {
+    TILE_SOCKET.with(|s| {
+        if let Some(tiles) = s.borrow_mut().as_mut() {
+            if tiles.drawing || tiles.pending > 0 {
+                tiles.drawing = false;
+                tiles.finished = false;
+                tiles.cancelled = true;
+                send_on_tile_socket(&tiles.socket, WebSocketCommand::CANCEL);
+            }
+        }
+    });
+}
+
+fn send_on_tile_socket(socket: &WebSocket, command: WebSocketCommand) {
+    let req = WebSocketRequest {
+        command,
+        encoding: TileEncoding::Rgb,
+        compress: false,
+    };
+    let req = serde_json::json!(req).to_string();
+    console_log!("sending string to server {}", &req);
+    if let Err(err) = socket.send_with_str(&req) {
+        console_log!("error sending message: {:?}", err);
+    }
+}
+
+// the state of the socket changes with the events, the tiles of a replaced render are dropped
+fn on_tile_event(event: WebSocketEvent, canvas: &HtmlCanvasElement) {
+    TILE_SOCKET.with(|s| {
+        let mut s = s.borrow_mut();
+        let Some(tiles) = s.as_mut() else {
+            return;
+        };
+        match event {
+            WebSocketEvent::RenderStarted {
+                id, width, height, ..
+            } => {
+                tiles.pending = tiles.pending.saturating_sub(1);
+                tiles.drawing = tiles.pending == 0 && !tiles.cancelled;
+                if tiles.drawing {
+                    console_log!("render {} started", id);
+                    set_canvas_width_height(width, height, canvas);
+                }
+            }
+            WebSocketEvent::RenderDone { id, cancelled, .. } => {
+                console_log!("render {} done, cancelled {}", id, cancelled);
+                tiles.finished = tiles.drawing && !cancelled;
+                tiles.drawing = false;
+            }
+            WebSocketEvent::Error {
+                id,
+                message,
+                fields,
+            } => {
+                console_log!("server could not render the fractal {:?}: {}", id, message);
+                for field in &fields {
+                    console_log!("    invalid field {}", field);
+                }
+                // without an id the command was refused, the server kept its last request
+                if id.is_none() {
+                    tiles.pending = tiles.pending.saturating_sub(1);
+                    tiles.rendered = None;
+                }
+                tiles.drawing = false;
+                tiles.finished = false;
+            }
+            WebSocketEvent::Progress { .. } | WebSocketEvent::Pong => {}
+        }
+    });
+}
+
+fn is_drawing_tiles() -> bool {
+    TILE_SOCKET.with(|s| s.borrow().as_ref().is_some_and(|tiles| tiles.drawing))
+}
+
+fn open_tile_socket() {
     let (context, canvas) = get_canvas_context();
     clear_canvas(&canvas);
 
-    let ws = WebSocket::new("ws://localhost:3000/api/crossbeamtiles");
-
-    let socket = match ws {
+    let socket = match WebSocket::new("ws://localhost:3000/api/crossbeamtiles") {
         Ok(websocket) => websocket,
-        Err(e) => panic!("cant open websocket    err {:?}", e),
+        Err(e) => {
+            console_log!("cant open websocket    err {:?}", e);
+            return;
+        }
     };
 
-    // let socket_clone = socket.clone();
-    let fractal_request = dummy_request();
-    let width = fractal_request.width;
-    let height = fractal_request.height;
-    set_canvas_width_height(width, height, &canvas);
-
     let mut cnt_tiles = 0;
 
+    // the tiles are binary, the events are text
+    socket.set_binary_type(BinaryType::Arraybuffer);
     let onmessage_callback = Closure::<dyn FnMut(_)>::new(move |e: MessageEvent| {
         e.prevent_default();
 
-        if let Ok(txt) = e.data().dyn_into::<js_sys::JsString>() {
-            // console_log!("message event, received Text: {:?}", txt);
-            let t = txt.as_string().unwrap();
-            let web_socket_response: serde_json::error::Result<WebSocketResponse> =
-                serde_json::from_str(&t);
-
-            if web_socket_response.is_ok() {
-                console_log!("got a valid WebSocketResponse  {}", &t);
-                let web_socket_response = web_socket_response.unwrap();
-
-                if web_socket_response.tile.is_some() {
+        if let Ok(buffer) = e.data().dyn_into::<js_sys::ArrayBuffer>() {
+            if !is_drawing_tiles() {
+                return;
+            }
+            let frame = js_sys::Uint8Array::new(&buffer).to_vec();
+            match TileFrame::decode(&frame) {
+                Ok(tile) => {
                     cnt_tiles += 1;
-
-                    let tile = web_socket_response.tile.unwrap();
-                    console_log!(
-                        "got a tile with id  {:?}.  cnt_tiles: {}",
-                        tile.idx,
-                        cnt_tiles
-                    );
-                    draw_to_canvas_tiles(&tile, &context, width, height);
-                }
-            } else {
-                console_log!(
-                    "got a message not  a valid FractalResponse, so this is the text {}",
+                    console_log!("got a tile with id  {}.  cnt_tiles: {}", tile.idx, cnt_tiles);
+                    draw_to_canvas_tiles(&tile, &context);
+                }
+                Err(e) => console_log!("invalid tile frame: {}", e),
+            }
+        } else if let Ok(txt) = e.data().dyn_into::<js_sys::JsString>() {
+            let t = txt.as_string().unwrap();
+            match serde_json::from_str::<WebSocketEvent>(&t) {
+                Ok(event) => on_tile_event(event, &canvas),
+                Err(_) => console_log!(
+                    "got a message not  a valid WebSocketEvent, so this is the text {}",
                     &t
-                );
+                ),
             }
         } else {
             console_log!("message event, received Unknown: {:?}", e.data());
@@ -405,33 +538,142 @@ async fn post_crossbeam_tiled() {
     // forget the callback to keep it alive
     onmessage_callback.forget();
 
+    // the next render opens a new socket
     let onerror_callback = Closure::<dyn FnMut(_)>::new(move |e: ErrorEvent| {
         e.prevent_default();
         console_log!("error event: {:?}", e.message());
+        TILE_SOCKET.with(|s| s.borrow_mut().take());
     });
 
     socket.set_onerror(Some(onerror_callback.as_ref().unchecked_ref()));
     onerror_callback.forget();
 
-    let cloned_ws = socket.clone();
+    let onclose_callback = Closure::<dyn FnMut()>::new(move || {
+        console_log!("socket closed");
+        TILE_SOCKET.with(|s| s.borrow_mut().take());
+    });
+    socket.set_onclose(Some(onclose_callback.as_ref().unchecked_ref()));
+    onclose_callback.forget();
+
     let onopen_callback = Closure::<dyn FnMut()>::new(move || {
         console_log!("socket opened");
+        send_tile_command(&current_request());
+    });
+    socket.set_onopen(Some(onopen_callback.as_ref().unchecked_ref()));
+    onopen_callback.forget();
 
-        let req = WebSocketRequest {
-            command: WebSocketCommand::RENDERFRACTAL(fractal_request.clone()),
-        };
+    TILE_SOCKET.with(|s| {
+        *s.borrow_mut() = Some(TileSocket {
+            socket,
+            rendered: None,
+            shown: None,
+            finished: false,
+            pending: 0,
+            drawing: false,
+            cancelled: false,
+        })
+    });
+}
+
+// the timer and its callback, the callback must live as long as the timer runs
+type PaletteCycling = (i32, Closure<dyn FnMut()>);
+
+thread_local! {
+    static PALETTE_CYCLING: RefCell<Option<PaletteCycling>> = const { RefCell::new(None) };
+}
+
+fn is_palette_cycling() -> bool {
+    PALETTE_CYCLING.with(|c| c.borrow().is_some())
+}
+
+fn stop_palette_cycling() {
+    if let Some((handle, _)) = PALETTE_CYCLING.with(|c| c.borrow_mut().take()) {
+        web_sys::window().unwrap().clear_interval_with_handle(handle);
+        console_log!("palette cycling stopped");
+    }
+}
+
+// the server calculates the iterations once, the colors are rotated here without asking it again
+async fn start_palette_cycling() -> Result<(), reqwasm::Error> {
+    stop_palette_cycling();
+    cancel_tiles();
+    let (context, canvas) = get_canvas_context();
 
-        let req = serde_json::json!(req).to_string();
-        console_log!("sending string to server {}", &req);
-        match cloned_ws.send_with_str(&req) {
-            Ok(_) => console_log!(
-                "sending request to server to start sending tiles: message successfully sent"
-            ),
-            Err(err) => console_log!("error sending message: {:?}", err),
+    let fractal_request = serde_json::json!(current_request()).to_string();
+    let url = format!("{}{}", SERVER, API_URL_ITERATIONS);
+    let response = Request::post(&url)
+        .body(fractal_request)
+        .header("content-type", "application/json")
+        .send()
+        .await?;
+    let ok = response.status() == 200;
+    let body = response.binary().await?;
+
+    // errors are still json
+    if !ok {
+        parse_fractal_response(&String::from_utf8_lossy(&body));
+        return Ok(());
+    }
+    let iteration_response = match IterationResponse::decode(&body) {
+        Ok(r) => r,
+        Err(e) => {
+            console_log!("server sent invalid iterations {}", e);
+            return Ok(());
         }
+    };
+    let palette = match resolve_palette(&iteration_response.palette) {
+        Ok(p) => p,
+        Err(e) => {
+            console_log!("server sent an invalid palette {}", e);
+            return Ok(());
+        }
+    };
+
+    let iterations = iteration_response.iterations;
+    set_canvas_width_height(iterations.width, iterations.height, &canvas);
+    let mut palette_offset = iteration_response.palette_offset;
+    let callback = Closure::<dyn FnMut()>::new(move || {
+        let pixels = iterations.colorize(&palette, palette_offset);
+        draw_pixels(&pixels, iterations.width, iterations.height, &context);
+        palette_offset += 1.0;
     });
-    socket.set_onopen(Some(onopen_callback.as_ref().unchecked_ref()));
-    onopen_callback.forget();
+
+    let handle = web_sys::window()
+        .unwrap()
+        .set_interval_with_callback_and_timeout_and_arguments_0(
+            callback.as_ref().unchecked_ref(),
+            40,
+        )
+        .unwrap();
+    PALETTE_CYCLING.with(|c| *c.borrow_mut() = Some((handle, callback)));
+    console_log!("palette cycling started");
+    Ok(())
+}
+
+async fn get_palettes() -> Result<Vec<PaletteInfo>, reqwasm::Error> {
+    let url = format!("{}{}", SERVER, API_URL_PALETTES);
+    Request::get(&url).send().await?.json().await
+}
+
+fn swatch_url(name: &str) -> String {
+    format!("{}{}/{}.png?width=128&height=16", SERVER, API_URL_PALETTES, name)
+}
+
+fn select_palette(name: &str) {
+    stop_palette_cycling();
+    let mut req = current_request();
+    req.palette = PaletteSpec::Name(name.to_string());
+    set_current_request(req);
+    console_log!("selected palette {}", name);
+}
+
+// the zoom and the center stay, only the fitting of the region into the canvas changes
+fn select_aspect(aspect: AspectPolicy) {
+    stop_palette_cycling();
+    let mut req = current_request();
+    req.aspect = aspect;
+    set_current_request(req);
+    console_log!("selected aspect {:?}", aspect);
 }
 
 fn dummy_request() -> FractalRequest {
@@ -439,8 +681,62 @@ fn dummy_request() -> FractalRequest {
     request
 }
 
+thread_local! {
+    // the request of the image currently shown, clicking the canvas zooms into it
+    static CURRENT_REQUEST: RefCell<FractalRequest> = RefCell::new(dummy_request());
+}
+
+fn current_request() -> FractalRequest {
+    CURRENT_REQUEST.with(|r| r.borrow().clone())
+}
+
+fn set_current_request(req: FractalRequest) {
+    CURRENT_REQUEST.with(|r| *r.borrow_mut() = req);
+}
+
+const ROTATION_STEP: f64 = 15.0;
+
+fn rotate_current_request(degrees: f64) {
+    stop_palette_cycling();
+    let req = current_request();
+    let viewport = Viewport::from_request(&req).rotated(degrees);
+    set_current_request(viewport.to_request(&req));
+}
+
+// click zooms in at the clicked point, shift + click zooms out. the viewport knows the rotation,
+// so the clicked point is the same in a rotated image
+fn zoom_to_click(e: &MouseEvent) {
+    let (_, canvas) = get_canvas_context();
+    if canvas.width() == 0 || canvas.client_width() == 0 {
+        return;
+    }
+
+    // the canvas might be scaled by css
+    let scale_x = canvas.width() as f64 / canvas.client_width() as f64;
+    let scale_y = canvas.height() as f64 / canvas.client_height() as f64;
+
+    let req = current_request();
+    let viewport = Viewport::from_request(&req);
+    let point = viewport.pixel_to_complex(e.offset_x() as f64 * scale_x, e.offset_y() as f64 * scale_y);
+    let factor = if e.shift_key() { 0.5 } else { 2.0 };
+    console_log!("zoom to {} with factor {}", point, factor);
+
+    set_current_request(viewport.zoom_at(&point, factor).to_request(&req));
+}
+
 #[component]
 async fn MainContent<G: Html>(cx: Scope<'_>) -> View<G> {
+    let zoom_canvas = move |e: MouseEvent| {
+        e.prevent_default();
+        stop_palette_cycling();
+        zoom_to_click(&e);
+        spawn_local_scoped(cx, {
+            async move {
+                post_crossbeam_tiled().await;
+            }
+        });
+    };
+
     view! { cx,
         div(class = "container-fluid") {
             div(class = "row") {
@@ -461,8 +757,14 @@ async fn MainContent<G: Html>(cx: Scope<'_>) -> View<G> {
                     }
                     div {
                         div(class ="canvas-container"  ) {
-                            canvas(id="fractal_canvas", class="fractal-canvas" )
+                            canvas(id="fractal_canvas", class="fractal-canvas", on:click=zoom_canvas)
+                        }
+                    }
+                    div(class="pt-3") {
+                        h2(class="h2") {
+                            "Tile map"
                         }
+                        TileMap
                     }
                 }
             }
@@ -535,6 +837,50 @@ async fn LeftNavItems<G: Html>(cx: Scope<'_>) -> View<G> {
         });
     };
 
+    let rotate_left = move |_: MouseEvent| {
+        rotate_current_request(ROTATION_STEP);
+        spawn_local_scoped(cx, {
+            async move {
+                post_crossbeam_tiled().await;
+            }
+        });
+    };
+
+    let rotate_right = move |_: MouseEvent| {
+        rotate_current_request(-ROTATION_STEP);
+        spawn_local_scoped(cx, {
+            async move {
+                post_crossbeam_tiled().await;
+            }
+        });
+    };
+
+    let pick_aspect = move |aspect: AspectPolicy| {
+        move |_: MouseEvent| {
+            select_aspect(aspect);
+            spawn_local_scoped(cx, {
+                async move {
+                    post_crossbeam_tiled().await;
+                }
+            });
+        }
+    };
+
+    let toggle_palette_cycling = move |e: MouseEvent| {
+        console_log!("toggle_palette_cycling  clicked.  event {:?}", e.target());
+        if is_palette_cycling() {
+            stop_palette_cycling();
+            return;
+        }
+        spawn_local_scoped(cx, {
+            async move {
+                if let Err(e) = start_palette_cycling().await {
+                    console_log!("error calling server /api/iterations.  {:?}", e)
+                }
+            }
+        });
+    };
+
     let start_java_single = move |e: MouseEvent| {
         console_log!("start_java_single  clicked.  event {:?}", e.target());
         spawn_local_scoped(cx, {
@@ -640,6 +986,55 @@ async fn LeftNavItems<G: Html>(cx: Scope<'_>) -> View<G> {
             }
         }
 
+        div(class = "row", style ="margin-bottom: 10px;") {
+            div (class="col-12") {
+                button(class="btn btn-primary", type="button", id="palette_cycling", on:click=toggle_palette_cycling) {
+                    "Palette cycling"
+                }
+            }
+        }
+
+        div(class = "row", style ="margin-bottom: 10px;") {
+            div (class="col-12") {
+                div(class="btn-group") {
+                    button(class="btn btn-secondary", type="button", id="rotate_left", on:click=rotate_left) {
+                        "Rotate left"
+                    }
+                    button(class="btn btn-secondary", type="button", id="rotate_right", on:click=rotate_right) {
+                        "Rotate right"
+                    }
+                }
+            }
+        }
+
+        div(class = "row", style ="margin-bottom: 10px;") {
+            div (class="col-12") {
+                div(class="btn-group") {
+                    button(class="btn btn-secondary", type="button", id="aspect_fit_width", on:click=pick_aspect(AspectPolicy::FitWidth)) {
+                        "Fit width"
+                    }
+                    button(class="btn btn-secondary", type="button", id="aspect_fit_height", on:click=pick_aspect(AspectPolicy::FitHeight)) {
+                        "Fit height"
+                    }
+                    button(class="btn btn-secondary", type="button", id="aspect_contain", on:click=pick_aspect(AspectPolicy::Contain)) {
+                        "Contain"
+                    }
+                    button(class="btn btn-secondary", type="button", id="aspect_stretch", on:click=pick_aspect(AspectPolicy::Stretch)) {
+                        "Stretch"
+                    }
+                }
+            }
+        }
+
+        div(class = "row", style ="margin-bottom: 10px;") {
+            div (class="col-12") {
+                p {
+                    "Palettes"
+                }
+                PalettePicker
+            }
+        }
+
           div(class = "row", style ="margin-bottom: 10px;") {
             div (class="col-12") {
                 button(class="btn btn-primary", type="button", id="java_singlethreaded" ,on:click=start_java_single){
@@ -681,6 +1076,165 @@ async fn LeftNavItems<G: Html>(cx: Scope<'_>) -> View<G> {
     }
 }
 
+#[component]
+async fn PalettePicker<G: Html>(cx: Scope<'_>) -> View<G> {
+    let palettes = match get_palettes().await {
+        Ok(p) => p,
+        Err(e) => {
+            console_log!("error calling server /api/palettes.  {:?}", e);
+            vec![]
+        }
+    };
+
+    let items = palettes
+        .into_iter()
+        .map(|info| {
+            let src = swatch_url(&info.name);
+            let title = format!("{} ({} colors)", info.name, info.colors);
+            let name_alt = info.name.clone();
+            let name = info.name;
+            let pick_palette = move |e: MouseEvent| {
+                e.prevent_default();
+                select_palette(&name);
+                spawn_local_scoped(cx, {
+                    async move {
+                        post_crossbeam_tiled().await;
+                    }
+                });
+            };
+            view! { cx,
+                div(class = "palette-swatch", style ="cursor: pointer; margin-bottom: 4px;", on:click=pick_palette) {
+                    img(src=src, title=title, alt=name_alt, width="128", height="16")
+                }
+            }
+        })
+        .collect();
+
+    View::new_fragment(items)
+}
+
+const MAP_WIDTH: f64 = 768.0;
+const MAP_HEIGHT: f64 = 512.0;
+
+// the center of the map in tiles of zoom level z, (0.5, 0.5) is the center of level 0
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct MapView {
+    z: u32,
+    x: f64,
+    y: f64,
+}
+
+impl MapView {
+    fn zoom(self, z: u32) -> MapView {
+        let factor = 2f64.powi(z as i32 - self.z as i32);
+        MapView {
+            z,
+            x: self.x * factor,
+            y: self.y * factor,
+        }
+    }
+
+    // the pixel position of the top left corner of a tile in the map
+    fn tile_position(&self, tile: &TileCoord) -> (f64, f64) {
+        let size = TILE_SIZE as f64;
+        (
+            (tile.x as f64 - self.x) * size + MAP_WIDTH / 2.0,
+            (tile.y as f64 - self.y) * size + MAP_HEIGHT / 2.0,
+        )
+    }
+
+    fn visible_tiles(&self) -> Vec<TileCoord> {
+        let size = TILE_SIZE as f64;
+        let tiles = (1u64 << self.z) as f64;
+        let range = |center: f64, extent: f64| {
+            let first = (center - extent / 2.0 / size).floor().max(0.0);
+            let last = (center + extent / 2.0 / size).floor().min(tiles - 1.0);
+            first as u32..(last + 1.0).max(first) as u32
+        };
+        let (xs, ys) = (range(self.x, MAP_WIDTH), range(self.y, MAP_HEIGHT));
+        ys.flat_map(|y| xs.clone().map(move |x| TileCoord { z: self.z, x, y }))
+            .collect()
+    }
+}
+
+fn tile_url(tile: &TileCoord) -> String {
+    format!("{}{}/{}/{}/{}.png", SERVER, TILES_URL, tile.z, tile.x, tile.y)
+}
+
+// drag to pan, double click or the buttons to zoom. only the tiles that come into view are loaded
+#[component]
+async fn TileMap<G: Html>(cx: Scope<'_>) -> View<G> {
+    let map = create_signal(cx, MapView { z: 1, x: 1.0, y: 1.0 });
+    let drag = create_signal(cx, None::<(i32, i32)>);
+    let tiles = create_memo(cx, || map.get().visible_tiles());
+
+    let zoom_by = move |delta: i32| {
+        let view = *map.get();
+        let z = (view.z as i32 + delta).clamp(0, MAX_TILE_ZOOM as i32) as u32;
+        map.set(view.zoom(z));
+    };
+    let start_drag = move |e: MouseEvent| {
+        e.prevent_default();
+        drag.set(Some((e.client_x(), e.client_y())));
+    };
+    let move_drag = move |e: MouseEvent| {
+        if let Some((x, y)) = *drag.get() {
+            let view = *map.get();
+            let size = TILE_SIZE as f64;
+            map.set(MapView {
+                x: view.x - (e.client_x() - x) as f64 / size,
+                y: view.y - (e.client_y() - y) as f64 / size,
+                ..view
+            });
+            drag.set(Some((e.client_x(), e.client_y())));
+        }
+    };
+    let stop_drag = move |_: MouseEvent| drag.set(None);
+    let zoom_in = move |_: MouseEvent| zoom_by(1);
+    let zoom_out = move |_: MouseEvent| zoom_by(-1);
+    let zoom_at = move |e: MouseEvent| {
+        // move the double clicked point to the center first
+        let view = *map.get();
+        let size = TILE_SIZE as f64;
+        map.set(MapView {
+            x: view.x + (e.offset_x() as f64 - MAP_WIDTH / 2.0) / size,
+            y: view.y + (e.offset_y() as f64 - MAP_HEIGHT / 2.0) / size,
+            ..view
+        });
+        zoom_by(1);
+    };
+
+    let map_style = format!(
+        "position: relative; overflow: hidden; width: {}px; height: {}px; cursor: grab; background: black;",
+        MAP_WIDTH, MAP_HEIGHT
+    );
+
+    view! { cx,
+        div {
+            div(class="btn-group mb-2") {
+                button(class="btn btn-secondary", type="button", on:click=zoom_in) { "+" }
+                button(class="btn btn-secondary", type="button", on:click=zoom_out) { "-" }
+            }
+            span(class="ms-2") { "zoom " (map.get().z) }
+            div(style=map_style, on:mousedown=start_drag, on:mousemove=move_drag, on:mouseup=stop_drag, on:mouseleave=stop_drag, on:dblclick=zoom_at) {
+                Keyed(
+                    iterable=tiles,
+                    view=move |cx, tile| {
+                        let style = move || {
+                            let (left, top) = map.get().tile_position(&tile);
+                            format!("position: absolute; left: {}px; top: {}px; pointer-events: none;", left, top)
+                        };
+                        view! { cx,
+                            img(src=tile_url(&tile), style=style(), width=TILE_SIZE, height=TILE_SIZE)
+                        }
+                    },
+                    key=|tile| (tile.z, tile.x, tile.y),
+                )
+            }
+        }
+    }
+}
+
 #[component]
 async fn Header<G: Html>(cx: Scope<'_>) -> View<G> {
     view! { cx,